@@ -0,0 +1,46 @@
+use std::fs::File;
+
+use anyhow::Result;
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DataType, Int64Type};
+use parquet::file::writer::SerializedRowGroupWriter;
+
+/// Writes the next column of a row group. Values of optional columns are given with
+/// their definition levels (1 for a value, 0 for null), and only non-null values
+fn write_column<T: DataType>(row_group_writer: &mut SerializedRowGroupWriter<File>, values: &[T::T], def_levels: Option<&[i16]>) -> Result<()> {
+    let mut col_writer = row_group_writer.next_column()?.unwrap();
+    col_writer.typed::<T>().write_batch(values, def_levels, None)?;
+    col_writer.close()?;
+    Ok(())
+}
+
+fn def_levels<T>(values: &[Option<T>]) -> Vec<i16> {
+    values.iter().map(|value| i16::from(value.is_some())).collect()
+}
+
+pub fn write_string_column(row_group_writer: &mut SerializedRowGroupWriter<File>, values: &[String]) -> Result<()> {
+    let values: Vec<ByteArray> = values.iter().map(|s| ByteArray::from(s.as_bytes())).collect();
+    write_column::<ByteArrayType>(row_group_writer, &values, None)
+}
+
+pub fn write_int64_column(row_group_writer: &mut SerializedRowGroupWriter<File>, values: &[i64]) -> Result<()> {
+    write_column::<Int64Type>(row_group_writer, values, None)
+}
+
+pub fn write_bool_column(row_group_writer: &mut SerializedRowGroupWriter<File>, values: &[bool]) -> Result<()> {
+    write_column::<BoolType>(row_group_writer, values, None)
+}
+
+pub fn write_optional_string_column(row_group_writer: &mut SerializedRowGroupWriter<File>, values: &[Option<String>]) -> Result<()> {
+    let present: Vec<ByteArray> = values.iter().flatten().map(|s| ByteArray::from(s.as_bytes())).collect();
+    write_column::<ByteArrayType>(row_group_writer, &present, Some(&def_levels(values)))
+}
+
+pub fn write_optional_int64_column(row_group_writer: &mut SerializedRowGroupWriter<File>, values: &[Option<i64>]) -> Result<()> {
+    let present: Vec<i64> = values.iter().flatten().copied().collect();
+    write_column::<Int64Type>(row_group_writer, &present, Some(&def_levels(values)))
+}
+
+pub fn write_optional_bool_column(row_group_writer: &mut SerializedRowGroupWriter<File>, values: &[Option<bool>]) -> Result<()> {
+    let present: Vec<bool> = values.iter().flatten().copied().collect();
+    write_column::<BoolType>(row_group_writer, &present, Some(&def_levels(values)))
+}
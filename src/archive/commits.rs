@@ -0,0 +1,167 @@
+use std::fs::File;
+use std::io::Write;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use arrow_array::{ArrayRef, BooleanArray, Int64Array, RecordBatch, StringArray, TimestampMillisecondArray};
+use arrow_schema::SchemaRef;
+use parquet::file::writer::SerializedFileWriter;
+
+use crate::{EventRow, timestamp_json};
+use crate::columns::{write_bool_column, write_int64_column, write_string_column};
+use crate::gh::PushEventPayload;
+
+/// Schema of the per-bucket commit tables written by --emit-commits
+pub const COMMITS_SCHEMA: &str = r#"
+message schema {
+  REQUIRED BYTE_ARRAY repo_name (STRING);
+  REQUIRED INT64 push_id;
+  REQUIRED BYTE_ARRAY ref (STRING);
+  REQUIRED BYTE_ARRAY sha (STRING);
+  REQUIRED BYTE_ARRAY author_name (STRING);
+  REQUIRED BYTE_ARRAY author_email (STRING);
+  REQUIRED BYTE_ARRAY message (STRING);
+  REQUIRED INT64 created_at (TIMESTAMP(MILLIS,true));
+  REQUIRED BOOLEAN distinct;
+}
+"#;
+
+/// One commit of a push, with the push's repository and time
+#[derive(Debug, Clone)]
+pub struct CommitRow {
+    pub repo_name: String,
+    pub push_id: i64,
+    pub ref_name: String,
+    pub sha: String,
+    pub author_name: String,
+    pub author_email: String,
+    pub message: String,
+    pub created_at: i64,
+    pub distinct: bool,
+}
+
+impl CommitRow {
+    /// Approximate in-memory size of the row's data, used to bound buffer sizes
+    pub fn data_bytes(&self) -> usize {
+        self.repo_name.len() + self.ref_name.len() + self.sha.len() + self.author_name.len()
+            + self.author_email.len() + self.message.len() + 2 * std::mem::size_of::<i64>() + 1
+    }
+}
+
+/// One row per commit a PushEvent's payload lists.
+pub fn commits_of_push(event: &EventRow, payload: PushEventPayload) -> Result<Vec<CommitRow>> {
+    let push_id = i64::try_from(payload.push_id)
+        .context(format!("push_id {} of event {} is out of range", payload.push_id, event.id))?;
+    Ok(payload.commits.into_iter()
+        .map(|commit| CommitRow {
+            repo_name: event.repo_name.clone(),
+            push_id,
+            ref_name: payload.ref_name.clone(),
+            sha: commit.sha,
+            author_name: commit.author.name,
+            author_email: commit.author.email,
+            message: commit.message,
+            created_at: event.created_at,
+            distinct: commit.distinct,
+        })
+        .collect())
+}
+
+#[derive(Debug, Default)]
+pub struct CommitBuffer {
+    repo_names: Vec<String>,
+    push_ids: Vec<i64>,
+    ref_names: Vec<String>,
+    shas: Vec<String>,
+    author_names: Vec<String>,
+    author_emails: Vec<String>,
+    messages: Vec<String>,
+    pub created_ats: Vec<i64>,
+    distincts: Vec<bool>,
+    /// Approximate size of the buffered data in bytes
+    pub bytes: usize,
+}
+
+impl CommitBuffer {
+    pub fn add_row(&mut self, row: CommitRow) {
+        self.bytes += row.data_bytes();
+        self.repo_names.push(row.repo_name);
+        self.push_ids.push(row.push_id);
+        self.ref_names.push(row.ref_name);
+        self.shas.push(row.sha);
+        self.author_names.push(row.author_name);
+        self.author_emails.push(row.author_email);
+        self.messages.push(row.message);
+        self.created_ats.push(row.created_at);
+        self.distincts.push(row.distinct);
+    }
+
+    pub fn len(&self) -> usize {
+        self.shas.len()
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Writes the buffered commits as one row group, in COMMITS_SCHEMA column order.
+pub fn flush_commits_to_parquet(writer: &mut SerializedFileWriter<File>, buffer: &mut CommitBuffer) -> Result<()> {
+    if buffer.len() == 0 {
+        return Ok(());
+    }
+
+    let mut row_group_writer = writer.next_row_group()?;
+    write_string_column(&mut row_group_writer, &buffer.repo_names)?;
+    write_int64_column(&mut row_group_writer, &buffer.push_ids)?;
+    write_string_column(&mut row_group_writer, &buffer.ref_names)?;
+    write_string_column(&mut row_group_writer, &buffer.shas)?;
+    write_string_column(&mut row_group_writer, &buffer.author_names)?;
+    write_string_column(&mut row_group_writer, &buffer.author_emails)?;
+    write_string_column(&mut row_group_writer, &buffer.messages)?;
+    write_int64_column(&mut row_group_writer, &buffer.created_ats)?;
+    write_bool_column(&mut row_group_writer, &buffer.distincts)?;
+
+    row_group_writer.close()?;
+    buffer.clear();
+    Ok(())
+}
+
+/// Writes the buffered commits as one JSON object per line, with the same fields as
+/// the parquet columns.
+pub fn write_commits_to_json_lines(out: &mut dyn Write, buffer: &mut CommitBuffer) -> Result<()> {
+    for i in 0..buffer.len() {
+        let row = serde_json::json!({
+            "repo_name": buffer.repo_names[i],
+            "push_id": buffer.push_ids[i],
+            "ref": buffer.ref_names[i],
+            "sha": buffer.shas[i],
+            "author_name": buffer.author_names[i],
+            "author_email": buffer.author_emails[i],
+            "message": buffer.messages[i],
+            "created_at": timestamp_json(buffer.created_ats[i]),
+            "distinct": buffer.distincts[i],
+        });
+        serde_json::to_writer(&mut *out, &row)?;
+        out.write_all(b"\n")?;
+    }
+    buffer.clear();
+    Ok(())
+}
+
+/// Moves the buffered rows into a record batch, in COMMITS_SCHEMA column order.
+pub fn take_commits_record_batch(schema: SchemaRef, buffer: &mut CommitBuffer) -> Result<RecordBatch> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.repo_names))),
+        Arc::new(Int64Array::from(std::mem::take(&mut buffer.push_ids))),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.ref_names))),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.shas))),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.author_names))),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.author_emails))),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.messages))),
+        Arc::new(TimestampMillisecondArray::from(std::mem::take(&mut buffer.created_ats)).with_timezone("UTC")),
+        Arc::new(BooleanArray::from(std::mem::take(&mut buffer.distincts))),
+    ];
+    buffer.clear();
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
@@ -0,0 +1,470 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::writer::SerializedFileWriter;
+use parquet::record::reader::RowIter;
+use parquet::record::{Field, Row, RowAccessor};
+use parquet::schema::parser::parse_message_type;
+use rayon::prelude::*;
+
+use crate::commits::CommitRow;
+use crate::extract::{EVENT_COLUMNS, Extractions};
+use crate::pull_requests::PullRequestRow;
+use crate::{CompressionCodec, EventRow, OutputFormat, OutputRow, OutputTable, WriterConfig, timestamp_millis};
+
+/// Merge the part files of each bucket into a single file
+#[derive(clap::Args, Debug)]
+pub struct CompactArgs {
+    /// Output directory of earlier runs whose buckets are compacted
+    #[arg(long, default_value = crate::OUTPUT_DIR)]
+    pub output_dir: PathBuf,
+
+    /// Compression codec for the merged files
+    #[arg(long, value_enum, default_value_t = CompressionCodec::Zstd)]
+    pub compression: CompressionCodec,
+
+    /// Compression level (gzip: 0-9, zstd: 1-22; not supported by none, snappy or lz4)
+    #[arg(long)]
+    pub compression_level: Option<i32>,
+
+    /// Rows per row group in the merged files
+    #[arg(long, visible_alias = "row-group-size", default_value_t = 1000)]
+    pub buffer_rows: usize,
+
+    /// Disable dictionary encoding in the merged files
+    #[arg(long)]
+    pub no_dictionary: bool,
+
+    /// Order each merged file by created_at instead of keeping the rows in part order.
+    /// Holds a whole bucket in memory at once
+    #[arg(long)]
+    pub sort_by_created_at: bool,
+}
+
+/// The part files of one bucket, in the order their rows are merged
+struct BucketParts {
+    dir: PathBuf,
+    /// Last component of the bucket key (month or event type); empty for hive layouts,
+    /// where the bucket is the directory itself. Followed by the table for derived tables
+    /// (`2024-01.commits`, or `commits` in hive layouts)
+    name: String,
+    table: OutputTable,
+    parts: Vec<PathBuf>,
+}
+
+impl BucketParts {
+    fn merged_path(&self) -> PathBuf {
+        if self.name.is_empty() {
+            self.dir.join("compacted.parquet")
+        } else {
+            self.dir.join(format!("{}.compacted.parquet", self.name))
+        }
+    }
+}
+
+/// The bucket name and table of a part file, which together with its directory identify
+/// the bucket it is merged into
+pub fn bucket_of(path: &Path) -> (String, OutputTable) {
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    let hive = path.parent()
+        .and_then(Path::file_name)
+        .is_some_and(|name| name.to_string_lossy().contains('='));
+    let (mut name, rest) = if hive {
+        (String::new(), &*file_name)
+    } else {
+        file_name.split_once('.').map(|(name, rest)| (name.to_string(), rest)).unwrap_or_default()
+    };
+    let table = OutputTable::of_file_name(rest);
+    if table != OutputTable::Events {
+        let table_name = table.part_prefix().trim_end_matches('.');
+        name = if name.is_empty() { table_name.to_string() } else { format!("{}.{}", name, table_name) };
+    }
+    (name, table)
+}
+
+/// Where compaction merges a part file's bucket to
+pub fn merged_path_of(part: &Path) -> PathBuf {
+    let (name, table) = bucket_of(part);
+    let dir = part.parent().unwrap_or(Path::new(""));
+    BucketParts { dir: dir.to_path_buf(), name, table, parts: Vec::new() }.merged_path()
+}
+
+/// Groups the parquet files under the output directory by bucket. Part files are named
+/// `<month>.<part>[.<n>].parquet`, or `<part>[.<n>].parquet` inside a key=value
+/// directory when written with hive partitions, where the part is the first input of
+/// the checkpoint that wrote them. Derived tables have their prefix (`commits.`) before
+/// the part and are merged separately from the events of the same bucket.
+fn find_bucket_parts(output_dir: &Path) -> Result<Vec<BucketParts>> {
+    let mut buckets: BTreeMap<(PathBuf, String), (OutputTable, Vec<PathBuf>)> = BTreeMap::new();
+    let mut dirs = vec![output_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir).context(format!("Failed to list {}", dir.display()))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+            if file_name.starts_with('.') || !file_name.ends_with(".parquet") {
+                continue;
+            }
+            let (name, table) = bucket_of(&path);
+            buckets.entry((dir.clone(), name)).or_insert_with(|| (table, Vec::new())).1.push(path);
+        }
+    }
+
+    Ok(buckets.into_iter()
+        .filter(|(_, (_, parts))| parts.len() > 1)
+        .map(|((dir, name), (table, parts))| {
+            let mut bucket = BucketParts { dir, name, table, parts };
+            // Part names start with an input file name, so this is input order. A
+            // previous compaction's output holds the earliest rows and goes first
+            let merged_path = bucket.merged_path();
+            bucket.parts.sort_by(|a, b| (*a != merged_path, a).cmp(&(*b != merged_path, b)));
+            bucket
+        })
+        .collect())
+}
+
+/// Positions of the output columns, resolved by name since older files may order them differently
+struct OutputColumns {
+    event_type: usize,
+    payload: usize,
+    repo_name: usize,
+    created_at: usize,
+    actor_login: Option<usize>,
+    actor_id: Option<usize>,
+    id: Option<usize>,
+    /// Columns added by --extract, absent in parts written without them
+    extracted: Vec<Option<usize>>,
+}
+
+/// Positions of the commit table columns
+struct CommitColumns {
+    repo_name: usize,
+    push_id: usize,
+    ref_name: usize,
+    sha: usize,
+    author_name: usize,
+    author_email: usize,
+    message: usize,
+    created_at: usize,
+    distinct: usize,
+}
+
+fn read_commit(row: &Row, columns: &CommitColumns) -> Result<CommitRow> {
+    Ok(CommitRow {
+        repo_name: row.get_string(columns.repo_name)?.clone(),
+        push_id: row.get_long(columns.push_id)?,
+        ref_name: row.get_string(columns.ref_name)?.clone(),
+        sha: row.get_string(columns.sha)?.clone(),
+        author_name: row.get_string(columns.author_name)?.clone(),
+        author_email: row.get_string(columns.author_email)?.clone(),
+        message: row.get_string(columns.message)?.clone(),
+        created_at: timestamp_millis(row, columns.created_at)?,
+        distinct: row.get_bool(columns.distinct)?,
+    })
+}
+
+/// Positions of the pull request table columns
+struct PullRequestColumns {
+    repo_name: usize,
+    pr_number: usize,
+    action: usize,
+    title: usize,
+    state: usize,
+    merged: usize,
+    additions: usize,
+    deletions: usize,
+    changed_files: usize,
+    user_login: usize,
+    created_at: usize,
+    merged_at: usize,
+    base_ref: usize,
+    head_ref: usize,
+    event_created_at: usize,
+}
+
+/// Reads an optional column, which is None where the value is null
+fn nullable<T>(row: &Row, index: usize, get: impl Fn(&Row, usize) -> Result<T>) -> Result<Option<T>> {
+    if matches!(row.get_column_iter().nth(index), Some((_, Field::Null))) {
+        return Ok(None);
+    }
+    get(row, index).map(Some)
+}
+
+fn get_string(row: &Row, index: usize) -> Result<String> {
+    Ok(row.get_string(index)?.clone())
+}
+
+fn get_long(row: &Row, index: usize) -> Result<i64> {
+    Ok(row.get_long(index)?)
+}
+
+fn read_pull_request(row: &Row, columns: &PullRequestColumns) -> Result<PullRequestRow> {
+    Ok(PullRequestRow {
+        repo_name: get_string(row, columns.repo_name)?,
+        pr_number: get_long(row, columns.pr_number)?,
+        action: get_string(row, columns.action)?,
+        title: nullable(row, columns.title, get_string)?,
+        state: nullable(row, columns.state, get_string)?,
+        merged: nullable(row, columns.merged, |row, index| Ok(row.get_bool(index)?))?,
+        additions: nullable(row, columns.additions, get_long)?,
+        deletions: nullable(row, columns.deletions, get_long)?,
+        changed_files: nullable(row, columns.changed_files, get_long)?,
+        user_login: nullable(row, columns.user_login, get_string)?,
+        created_at: nullable(row, columns.created_at, timestamp_millis)?,
+        merged_at: nullable(row, columns.merged_at, timestamp_millis)?,
+        base_ref: get_string(row, columns.base_ref)?,
+        head_ref: get_string(row, columns.head_ref)?,
+        event_created_at: timestamp_millis(row, columns.event_created_at)?,
+    })
+}
+
+fn read_event(row: &Row, columns: &OutputColumns) -> Result<EventRow> {
+    Ok(EventRow {
+        id: columns.id.map(|i| row.get_string(i).cloned()).transpose()?.unwrap_or_default(),
+        event_type: row.get_string(columns.event_type)?.clone(),
+        repo_name: row.get_string(columns.repo_name)?.clone(),
+        payload: row.get_string(columns.payload)?.clone(),
+        created_at: timestamp_millis(row, columns.created_at)?,
+        actor_login: columns.actor_login.map(|i| row.get_string(i).cloned()).transpose()?.unwrap_or_default(),
+        actor_id: columns.actor_id.map(|i| row.get_long(i)).transpose()?.unwrap_or(0),
+        extracted: columns.extracted.iter()
+            .map(|index| index.map_or(Ok(None), |i| nullable(row, i, get_string)))
+            .collect::<Result<_>>()?,
+    })
+}
+
+type PartRows = Box<dyn Iterator<Item = Result<OutputRow>>>;
+
+/// The --extract columns found in any of a bucket's event parts, in the order they first appear
+fn extra_columns(bucket: &BucketParts) -> Result<Vec<String>> {
+    let mut columns: Vec<String> = Vec::new();
+    if bucket.table != OutputTable::Events {
+        return Ok(columns);
+    }
+    for part in &bucket.parts {
+        let file = File::open(part).context(format!("Failed to open {}", part.display()))?;
+        let reader = SerializedFileReader::new(file).context(format!("Failed to read {}", part.display()))?;
+        for field in reader.metadata().file_metadata().schema().get_fields() {
+            if !EVENT_COLUMNS.contains(&field.name()) && !columns.iter().any(|column| column == field.name()) {
+                columns.push(field.name().to_string());
+            }
+        }
+    }
+    Ok(columns)
+}
+
+/// Opens a bucket file for reading, returning its rows and its row count from the footer.
+/// Event rows get the values of `extra_columns`, null where the file lacks the column.
+fn open_part(path: &Path, table: OutputTable, extra_columns: &[String]) -> Result<(PartRows, u64)> {
+    let file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+    let reader = SerializedFileReader::new(file).context(format!("Failed to read {}", path.display()))?;
+    let row_count = reader.metadata().file_metadata().num_rows() as u64;
+
+    let fields = reader.metadata().file_metadata().schema().get_fields();
+    let position = |name: &str| fields.iter().position(|field| field.name() == name);
+    let required = |name: &str| position(name)
+        .ok_or_else(|| anyhow::anyhow!("{} has no {} column", path.display(), name));
+    let rows: PartRows = match table {
+        OutputTable::Events => {
+            let columns = OutputColumns {
+                event_type: required("type")?,
+                payload: required("payload")?,
+                repo_name: required("repo_name")?,
+                created_at: required("created_at")?,
+                actor_login: position("actor_login"),
+                actor_id: position("actor_id"),
+                id: position("id"),
+                extracted: extra_columns.iter().map(|column| position(column)).collect(),
+            };
+            Box::new(RowIter::from_file_into(Box::new(reader))
+                .map(move |row| read_event(&row?, &columns).map(OutputRow::Event)))
+        }
+        OutputTable::Commits => {
+            let columns = CommitColumns {
+                repo_name: required("repo_name")?,
+                push_id: required("push_id")?,
+                ref_name: required("ref")?,
+                sha: required("sha")?,
+                author_name: required("author_name")?,
+                author_email: required("author_email")?,
+                message: required("message")?,
+                created_at: required("created_at")?,
+                distinct: required("distinct")?,
+            };
+            Box::new(RowIter::from_file_into(Box::new(reader))
+                .map(move |row| read_commit(&row?, &columns).map(OutputRow::Commit)))
+        }
+        OutputTable::PullRequests => {
+            let columns = PullRequestColumns {
+                repo_name: required("repo_name")?,
+                pr_number: required("pr_number")?,
+                action: required("action")?,
+                title: required("title")?,
+                state: required("state")?,
+                merged: required("merged")?,
+                additions: required("additions")?,
+                deletions: required("deletions")?,
+                changed_files: required("changed_files")?,
+                user_login: required("user_login")?,
+                created_at: required("created_at")?,
+                merged_at: required("merged_at")?,
+                base_ref: required("base_ref")?,
+                head_ref: required("head_ref")?,
+                event_created_at: required("event_created_at")?,
+            };
+            Box::new(RowIter::from_file_into(Box::new(reader))
+                .map(move |row| read_pull_request(&row?, &columns).map(OutputRow::PullRequest)))
+        }
+    };
+    Ok((rows, row_count))
+}
+
+/// Writes the merged bucket to a temporary file, checks it reads back with every row,
+/// then moves it into place and removes the parts. An interrupted compaction leaves
+/// only the temporary file behind, which the next run overwrites.
+fn compact_bucket(bucket: &BucketParts, config: &WriterConfig, sort_by_created_at: bool) -> Result<u64> {
+    let merged_path = bucket.merged_path();
+    let tmp_path = bucket.dir.join(format!(".{}.tmp", merged_path.file_name().unwrap().to_string_lossy()));
+
+    let extra_columns = extra_columns(bucket)?;
+    let schema = Arc::new(parse_message_type(&bucket.table.schema(&extra_columns))?);
+    let file = File::create(&tmp_path).context(format!("Failed to create {}", tmp_path.display()))?;
+    let mut writer = SerializedFileWriter::new(file, schema, Arc::new(config.writer_properties()))?;
+    let mut buffer = bucket.table.new_buffer(&extra_columns);
+    let mut expected_rows = 0;
+
+    if sort_by_created_at {
+        let mut rows = Vec::new();
+        for part in &bucket.parts {
+            let (part_rows, row_count) = open_part(part, bucket.table, &extra_columns)?;
+            expected_rows += row_count;
+            for row in part_rows {
+                rows.push(row?);
+            }
+        }
+        // Stable, so rows with the same timestamp keep their part order
+        rows.sort_by_key(OutputRow::created_at);
+        for row in rows {
+            buffer.add_row(row);
+            if config.should_flush(&buffer) {
+                buffer.flush_to(&mut writer)?;
+            }
+        }
+    } else {
+        for part in &bucket.parts {
+            let (rows, row_count) = open_part(part, bucket.table, &extra_columns)?;
+            expected_rows += row_count;
+            for row in rows {
+                buffer.add_row(row?);
+                if config.should_flush(&buffer) {
+                    buffer.flush_to(&mut writer)?;
+                }
+            }
+        }
+    }
+    buffer.flush_to(&mut writer)?;
+    writer.close()?;
+
+    let (rows, row_count) = open_part(&tmp_path, bucket.table, &extra_columns)?;
+    let mut read_back = 0u64;
+    for row in rows {
+        row.context(format!("Merged file {} is not readable", tmp_path.display()))?;
+        read_back += 1;
+    }
+    if row_count != expected_rows || read_back != expected_rows {
+        return Err(anyhow::anyhow!(
+            "Merged file {} has {} rows, expected {}; parts left in place",
+            tmp_path.display(), read_back, expected_rows
+        ));
+    }
+
+    std::fs::rename(&tmp_path, &merged_path)
+        .context(format!("Failed to move merged file into place: {}", merged_path.display()))?;
+    for part in &bucket.parts {
+        // A previous compaction's output is one of the parts, and has just been replaced
+        if *part != merged_path {
+            std::fs::remove_file(part).context(format!("Failed to remove {}", part.display()))?;
+        }
+    }
+    Ok(expected_rows)
+}
+
+pub fn run(args: &CompactArgs) -> Result<()> {
+    if args.buffer_rows == 0 {
+        return Err(anyhow::anyhow!("--buffer-rows must be greater than zero"));
+    }
+    if !args.output_dir.is_dir() {
+        return Err(anyhow::anyhow!("Output directory {} does not exist", args.output_dir.display()));
+    }
+
+    let config = WriterConfig {
+        buffer_rows: args.buffer_rows,
+        buffer_bytes: None,
+        dictionary: !args.no_dictionary,
+        compression: args.compression.to_compression(args.compression_level)?,
+        ipc_compression: None,
+        max_open_writers: 1,
+        max_buffer_bytes: None,
+        hive_partitions: false,
+        overwrite: true,
+        output_dir: args.output_dir.to_string_lossy().into_owned(),
+        output_format: OutputFormat::Parquet,
+        // Each bucket keeps the extracted columns its parts have
+        extractions: Extractions::default(),
+        original_repo_names: false,
+        wrap_zstd: false,
+        remote_output: None,
+    };
+
+    let buckets = find_bucket_parts(&args.output_dir)?;
+    println!("Compacting {} buckets with more than one part file", buckets.len());
+
+    let pb = ProgressBar::new(buckets.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}/{duration_precise}] {bar:40.cyan/blue} {pos:>3}/{len:3} {msg}")
+            .unwrap()
+            .progress_chars("##-")
+    );
+    pb.set_message("Compacting buckets");
+
+    let results: Vec<(&BucketParts, Result<u64>)> = buckets.par_iter()
+        .map(|bucket| {
+            let result = compact_bucket(bucket, &config, args.sort_by_created_at);
+            if let Err(e) = &result {
+                pb.println(format!("✗ {}: {:#}", bucket.merged_path().display(), e));
+            }
+            pb.inc(1);
+            (bucket, result)
+        })
+        .collect();
+    pb.finish_with_message("All buckets compacted");
+
+    let (mut compacted, mut parts, mut rows, mut failed) = (0, 0, 0, 0);
+    for (bucket, result) in results {
+        match result {
+            Ok(bucket_rows) => {
+                compacted += 1;
+                parts += bucket.parts.len();
+                rows += bucket_rows;
+            }
+            Err(_) => failed += 1,
+        }
+    }
+
+    println!("Buckets compacted: {} ({} part files merged, {} rows)", compacted, parts, rows);
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} buckets failed to compact", failed));
+    }
+
+    println!("✓ Compaction complete!");
+    Ok(())
+}
@@ -0,0 +1,208 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{Duration as ChronoDuration, NaiveDate, NaiveDateTime, Utc};
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use reqwest::StatusCode;
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::blocking::Client;
+
+/// Download GH Archive hourly files for a timeframe
+#[derive(clap::Args, Debug)]
+pub struct DownloadArgs {
+    /// Timeframe to download (YYYY, YYYY-MM, or YYYY-MM-DD)
+    pub timeframe: String,
+
+    /// Directory the hourly files are written to
+    #[arg(long, default_value = crate::INPUT_DIR)]
+    pub output_dir: PathBuf,
+
+    /// Number of files downloaded at once
+    #[arg(long, default_value_t = 8)]
+    pub parallelism: usize,
+
+    /// Attempts per file before giving up, with exponential backoff between them
+    #[arg(long, default_value_t = 4)]
+    pub retries: u32,
+
+    /// Base URL of the archive
+    #[arg(long, default_value = "https://data.gharchive.org")]
+    pub base_url: String,
+}
+
+/// What happened to one hourly file
+enum Outcome {
+    Downloaded(u64),
+    AlreadyPresent,
+    /// The archive has no file for this hour
+    Missing,
+}
+
+/// Lists the hours covered by a YYYY, YYYY-MM or YYYY-MM-DD timeframe, up to the current hour.
+fn hours_in_timeframe(timeframe: &str) -> Result<Vec<NaiveDateTime>> {
+    let parts: Vec<&str> = timeframe.split('-').collect();
+    let number = |i: usize| -> Result<u32> {
+        parts[i].parse().context(format!("Invalid timeframe: {}", timeframe))
+    };
+
+    let (start, end) = match parts.len() {
+        1 => {
+            let year = number(0)? as i32;
+            (NaiveDate::from_ymd_opt(year, 1, 1), NaiveDate::from_ymd_opt(year + 1, 1, 1))
+        }
+        2 => {
+            let (year, month) = (number(0)? as i32, number(1)?);
+            let next = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+            (NaiveDate::from_ymd_opt(year, month, 1), NaiveDate::from_ymd_opt(next.0, next.1, 1))
+        }
+        3 => {
+            let start = NaiveDate::from_ymd_opt(number(0)? as i32, number(1)?, number(2)?);
+            (start, start.and_then(|day| day.succ_opt()))
+        }
+        _ => return Err(anyhow::anyhow!("Invalid timeframe format. Use YYYY, YYYY-MM, or YYYY-MM-DD")),
+    };
+    let (Some(start), Some(end)) = (start, end) else {
+        return Err(anyhow::anyhow!("Invalid timeframe: {}", timeframe));
+    };
+
+    // Hours that haven't finished yet can't have been published
+    let now = Utc::now().naive_utc();
+    let mut hours = Vec::new();
+    let mut hour = start.and_hms_opt(0, 0, 0).unwrap();
+    let end = end.and_hms_opt(0, 0, 0).unwrap();
+    while hour < end && hour + ChronoDuration::hours(1) <= now {
+        hours.push(hour);
+        hour += ChronoDuration::hours(1);
+    }
+    Ok(hours)
+}
+
+/// GH Archive file name for an hour. The hour is not zero-padded.
+fn archive_file_name(hour: &NaiveDateTime) -> String {
+    format!("{}-{}.json.gz", hour.format("%Y-%m-%d"), hour.format("%-H"))
+}
+
+fn download_file(client: &Client, url: &str, path: &Path) -> Result<Outcome> {
+    // An existing file is kept if it matches the size the server reports
+    if let Ok(metadata) = std::fs::metadata(path) {
+        let response = client.head(url).send()?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Outcome::Missing);
+        }
+        // Read the header directly: for HEAD responses content_length() reports the empty body
+        let remote_size = response.error_for_status_ref()?
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        if remote_size == Some(metadata.len()) {
+            return Ok(Outcome::AlreadyPresent);
+        }
+    }
+
+    let response = client.get(url).send()?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(Outcome::Missing);
+    }
+    let mut response = response.error_for_status()?;
+
+    // Download next to the target and rename, so an interrupted download never
+    // looks like a complete file
+    let part_path = path.with_extension("gz.part");
+    let mut file = File::create(&part_path)
+        .context(format!("Failed to create {}", part_path.display()))?;
+    let bytes = response.copy_to(&mut file)?;
+    std::fs::rename(&part_path, path)
+        .context(format!("Failed to move download into place: {}", path.display()))?;
+    Ok(Outcome::Downloaded(bytes))
+}
+
+fn download_with_retries(client: &Client, url: &str, path: &Path, attempts: u32) -> Result<Outcome> {
+    let mut attempt = 1;
+    loop {
+        match download_file(client, url, path) {
+            Ok(outcome) => return Ok(outcome),
+            Err(_) if attempt < attempts => {
+                std::thread::sleep(Duration::from_secs(1 << (attempt - 1)));
+                attempt += 1;
+            }
+            Err(e) => return Err(e.context(format!("Giving up on {} after {} attempts", url, attempts))),
+        }
+    }
+}
+
+pub fn run(args: &DownloadArgs) -> Result<()> {
+    if args.parallelism == 0 {
+        return Err(anyhow::anyhow!("--parallelism must be greater than zero"));
+    }
+
+    let hours = hours_in_timeframe(&args.timeframe)?;
+    std::fs::create_dir_all(&args.output_dir)
+        .context(format!("Failed to create {}", args.output_dir.display()))?;
+
+    println!("Downloading {} hourly files for timeframe: {}", hours.len(), args.timeframe);
+
+    let client = Client::builder()
+        .timeout(Duration::from_secs(300))
+        .build()?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.parallelism)
+        .build()?;
+
+    let pb = ProgressBar::new(hours.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}/{duration_precise}] {bar:40.cyan/blue} {pos:>3}/{len:3} {msg}")
+            .unwrap()
+            .progress_chars("##-")
+    );
+    pb.set_message("Downloading archives");
+
+    let base_url = args.base_url.trim_end_matches('/');
+    let outcomes: Vec<(String, Result<Outcome>)> = pool.install(|| {
+        hours.par_iter().map(|hour| {
+            let file_name = archive_file_name(hour);
+            let url = format!("{}/{}", base_url, file_name);
+            let outcome = download_with_retries(&client, &url, &args.output_dir.join(&file_name), args.retries.max(1));
+            match &outcome {
+                Ok(Outcome::Missing) => pb.println(format!("- Not in archive, skipped: {}", file_name)),
+                Err(e) => pb.println(format!("✗ {:#}", e)),
+                _ => {}
+            }
+            pb.inc(1);
+            (file_name, outcome)
+        }).collect()
+    });
+    pb.finish_with_message("All downloads finished");
+
+    let (mut downloaded, mut bytes, mut present, mut missing) = (0, 0, 0, 0);
+    let mut failed = Vec::new();
+    for (file_name, outcome) in outcomes {
+        match outcome {
+            Ok(Outcome::Downloaded(size)) => {
+                downloaded += 1;
+                bytes += size;
+            }
+            Ok(Outcome::AlreadyPresent) => present += 1,
+            Ok(Outcome::Missing) => missing += 1,
+            Err(_) => failed.push(file_name),
+        }
+    }
+
+    println!("Downloaded: {} ({:.1} MiB)", downloaded, bytes as f64 / (1024.0 * 1024.0));
+    println!("Already present: {}", present);
+    println!("Missing from archive: {}", missing);
+    if !failed.is_empty() {
+        println!("✗ {} files failed:", failed.len());
+        for file_name in &failed {
+            println!("  {}", file_name);
+        }
+        return Err(anyhow::anyhow!("{} files failed to download", failed.len()));
+    }
+
+    println!("✓ All downloads complete!");
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use thiserror::Error;
+
+/// Errors at the boundaries of a run: input files that can't be opened, schemas that
+/// don't fit, and rows that can't be decoded. They are carried inside `anyhow::Error`
+/// like everything else, so a caller can `downcast_ref::<ArchiveError>()` to tell them
+/// apart from other failures.
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("{context}: {path}")]
+    Io {
+        context: &'static str,
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    
+    #[error("Input schema is missing required column '{column}'. Found columns: {}", found.join(", "))]
+    MissingColumn {
+        column: String,
+        found: Vec<String>,
+    },
+    
+    #[error("Input column '{column}' should be {expected}, found {found}")]
+    SchemaMismatch {
+        column: String,
+        expected: &'static str,
+        found: String,
+    },
+    
+    /// A row of an input file that couldn't be read, with --strict
+    #[error("Failed to read row {ordinal} of {file}")]
+    RowDecode {
+        file: String,
+        /// Zero-based position of the row in the file
+        ordinal: usize,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    
+    /// A bucket file that couldn't be written. Its writer is shared by the inputs of a
+    /// checkpoint, so unlike a failed read this isn't retried
+    #[error("Failed to write to bucket {bucket}")]
+    Write {
+        bucket: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+/// Whether an error came from an I/O failure that may not happen again, such as EIO
+/// from a network filesystem or a timed-out read, as opposed to one that would repeat on
+/// every attempt like a corrupt file.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    const EIO: i32 = 5;
+    error.chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|io| {
+            matches!(io.raw_os_error(), Some(EIO)) || matches!(io.kind(),
+                std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::NotConnected
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::StaleNetworkFileHandle)
+        })
+}
+
+/// Whether an error came from writing an output file rather than reading an input.
+pub fn is_write_error(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref::<ArchiveError>(), Some(ArchiveError::Write { .. }))
+}
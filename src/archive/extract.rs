@@ -0,0 +1,107 @@
+use anyhow::Result;
+use serde_json::Value;
+
+/// Columns of the events table, which extracted columns can't be named after
+pub const EVENT_COLUMNS: [&str; 7] = ["type", "payload", "repo_name", "created_at", "actor_login", "actor_id", "id"];
+
+/// One --extract rule: a payload field of one event type copied into its own column
+#[derive(Debug, Clone)]
+pub struct Extraction {
+    event_type: String,
+    /// Object keys, or array indexes, leading to the value
+    path: Vec<String>,
+    column: String,
+}
+
+impl Extraction {
+    /// Parses `EventType:dotted.path:column`, e.g. `PullRequestEvent:pull_request.number:pr_number`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut fields = value.splitn(3, ':');
+        let (Some(event_type), Some(path), Some(column)) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(format!("expected EVENT:PATH:COLUMN, got '{}'", value));
+        };
+        if event_type.is_empty() || path.is_empty() {
+            return Err(format!("expected EVENT:PATH:COLUMN, got '{}'", value));
+        }
+        let valid_column = column.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && column.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_column {
+            return Err(format!("column name '{}' must be letters, digits and underscores", column));
+        }
+        if EVENT_COLUMNS.contains(&column) {
+            return Err(format!("column name '{}' is already an events column", column));
+        }
+        Ok(Self {
+            event_type: event_type.to_string(),
+            path: path.split('.').map(str::to_string).collect(),
+            column: column.to_string(),
+        })
+    }
+
+    pub fn column(&self) -> &str {
+        &self.column
+    }
+
+    /// The value at the rule's path, or None if the payload doesn't have one. Strings are
+    /// kept as they are; numbers, booleans, objects and arrays are written as JSON.
+    fn value_in(&self, payload: &Value) -> Option<String> {
+        let mut value = payload;
+        for key in &self.path {
+            value = match value {
+                Value::Object(fields) => fields.get(key)?,
+                Value::Array(items) => items.get(key.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
+        }
+        match value {
+            Value::Null => None,
+            Value::String(s) => Some(s.clone()),
+            other => Some(other.to_string()),
+        }
+    }
+}
+
+/// The --extract rules of a run and the extra events columns they fill. Rules for
+/// different event types may share a column.
+#[derive(Debug, Clone, Default)]
+pub struct Extractions {
+    rules: Vec<Extraction>,
+    columns: Vec<String>,
+}
+
+impl Extractions {
+    pub fn new(rules: Vec<Extraction>) -> Result<Self> {
+        let mut columns: Vec<String> = Vec::new();
+        for (i, rule) in rules.iter().enumerate() {
+            if rules[..i].iter().any(|earlier| earlier.column == rule.column && earlier.event_type == rule.event_type) {
+                return Err(anyhow::anyhow!("--extract gives column {} twice for {}", rule.column, rule.event_type));
+            }
+            if !columns.contains(&rule.column) {
+                columns.push(rule.column.clone());
+            }
+        }
+        Ok(Self { rules, columns })
+    }
+
+    /// Names of the extra columns, in the order they are written after the events columns
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// The extra column values of one event. The payload is only parsed when a rule
+    /// applies to the event type.
+    pub fn values_of(&self, event_type: &str, payload: &str) -> Vec<Option<String>> {
+        let mut values = vec![None; self.columns.len()];
+        if !self.rules.iter().any(|rule| rule.event_type == event_type) {
+            return values;
+        }
+        let Ok(payload) = serde_json::from_str::<Value>(payload) else {
+            return values;
+        };
+        for rule in self.rules.iter().filter(|rule| rule.event_type == event_type) {
+            let index = self.columns.iter().position(|column| *column == rule.column).unwrap();
+            values[index] = rule.value_in(&payload);
+        }
+        values
+    }
+}
@@ -1,5 +1,9 @@
+use anyhow::Context;
+use flate2::read::MultiGzDecoder;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
 
 /// Common properties shared by all GitHub events
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,27 +48,67 @@ pub struct Organization {
     pub avatar_url: String,
 }
 
-/// Enum for all GitHub event types
+/// Enum for all GitHub event types. Payloads holding a whole pull request are boxed
+/// so they don't set the size of every variant
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum GitHubEventType {
     CommitCommentEvent(CommitCommentEventPayload),
     CreateEvent(CreateEventPayload),
     DeleteEvent(DeleteEventPayload),
+    DeploymentEvent(DeploymentEventPayload),
+    DeploymentStatusEvent(DeploymentStatusEventPayload),
     ForkEvent(ForkEventPayload),
     GollumEvent(GollumEventPayload),
     IssueCommentEvent(IssueCommentEventPayload),
     IssuesEvent(IssuesEventPayload),
     MemberEvent(MemberEventPayload),
     PublicEvent(PublicEventPayload),
-    PullRequestEvent(PullRequestEventPayload),
-    PullRequestReviewEvent(PullRequestReviewEventPayload),
-    PullRequestReviewCommentEvent(PullRequestReviewCommentEventPayload),
-    PullRequestReviewThreadEvent(PullRequestReviewThreadEventPayload),
+    PullRequestEvent(Box<PullRequestEventPayload>),
+    PullRequestReviewEvent(Box<PullRequestReviewEventPayload>),
+    PullRequestReviewCommentEvent(Box<PullRequestReviewCommentEventPayload>),
+    PullRequestReviewThreadEvent(Box<PullRequestReviewThreadEventPayload>),
     PushEvent(PushEventPayload),
     ReleaseEvent(ReleaseEventPayload),
     SponsorshipEvent(SponsorshipEventPayload),
+    StatusEvent(StatusEventPayload),
     WatchEvent(WatchEventPayload),
+    /// Any event type that isn't modeled above (GistEvent, TeamAddEvent, legacy
+    /// DownloadEvent/FollowEvent, ...), kept as raw JSON. Known types whose payload
+    /// doesn't match the model also end up here.
+    #[serde(untagged)]
+    Other(serde_json::Value),
+}
+
+impl GitHubEventType {
+    /// Parses a payload, given as JSON text, into the variant for its event type, or
+    /// `Other` with the raw payload for types that aren't modeled. Unlike deserializing
+    /// `GitHubEventType` directly, a modeled type whose payload doesn't match is an error.
+    pub fn parse(event_type: &str, payload: &str) -> Result<Self, serde_json::Error> {
+        Ok(match event_type {
+            "CommitCommentEvent" => Self::CommitCommentEvent(serde_json::from_str(payload)?),
+            "CreateEvent" => Self::CreateEvent(serde_json::from_str(payload)?),
+            "DeleteEvent" => Self::DeleteEvent(serde_json::from_str(payload)?),
+            "DeploymentEvent" => Self::DeploymentEvent(serde_json::from_str(payload)?),
+            "DeploymentStatusEvent" => Self::DeploymentStatusEvent(serde_json::from_str(payload)?),
+            "ForkEvent" => Self::ForkEvent(serde_json::from_str(payload)?),
+            "GollumEvent" => Self::GollumEvent(serde_json::from_str(payload)?),
+            "IssueCommentEvent" => Self::IssueCommentEvent(serde_json::from_str(payload)?),
+            "IssuesEvent" => Self::IssuesEvent(serde_json::from_str(payload)?),
+            "MemberEvent" => Self::MemberEvent(serde_json::from_str(payload)?),
+            "PublicEvent" => Self::PublicEvent(serde_json::from_str(payload)?),
+            "PullRequestEvent" => Self::PullRequestEvent(serde_json::from_str(payload)?),
+            "PullRequestReviewEvent" => Self::PullRequestReviewEvent(serde_json::from_str(payload)?),
+            "PullRequestReviewCommentEvent" => Self::PullRequestReviewCommentEvent(serde_json::from_str(payload)?),
+            "PullRequestReviewThreadEvent" => Self::PullRequestReviewThreadEvent(serde_json::from_str(payload)?),
+            "PushEvent" => Self::PushEvent(serde_json::from_str(payload)?),
+            "ReleaseEvent" => Self::ReleaseEvent(serde_json::from_str(payload)?),
+            "SponsorshipEvent" => Self::SponsorshipEvent(serde_json::from_str(payload)?),
+            "StatusEvent" => Self::StatusEvent(serde_json::from_str(payload)?),
+            "WatchEvent" => Self::WatchEvent(serde_json::from_str(payload)?),
+            _ => Self::Other(serde_json::from_str(payload)?),
+        })
+    }
 }
 
 // Event Payload Structures
@@ -111,6 +155,57 @@ pub struct DeleteEventPayload {
     pub pusher_type: String,
 }
 
+/// DeploymentEvent payload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeploymentEventPayload {
+    pub action: Option<String>, // "created"
+    pub deployment: Deployment,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deployment {
+    pub id: u64,
+    pub sha: String,
+    #[serde(rename = "ref")]
+    pub ref_name: String,
+    pub task: String,
+    pub payload: serde_json::Value,
+    pub original_environment: Option<String>,
+    pub environment: String,
+    pub description: Option<String>,
+    pub creator: Option<User>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub statuses_url: String,
+    pub repository_url: String,
+    pub url: String,
+}
+
+/// DeploymentStatusEvent payload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeploymentStatusEventPayload {
+    pub action: Option<String>, // "created"
+    pub deployment_status: DeploymentStatus,
+    pub deployment: Deployment,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeploymentStatus {
+    pub id: u64,
+    pub state: String, // "error", "failure", "inactive", "in_progress", "queued", "pending", "success"
+    pub creator: Option<User>,
+    pub description: Option<String>,
+    pub environment: Option<String>,
+    pub target_url: Option<String>,
+    pub log_url: Option<String>,
+    pub environment_url: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub deployment_url: String,
+    pub repository_url: String,
+    pub url: String,
+}
+
 /// ForkEvent payload
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ForkEventPayload {
@@ -267,53 +362,60 @@ pub struct PullRequestEventPayload {
     pub label: Option<Label>,
 }
 
+/// Pull request as embedded in event payloads. Older GH Archive years predate many of
+/// these fields and recent ones trim the object down, so anything not present in
+/// every year is optional rather than failing the whole payload.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PullRequest {
     pub id: u64,
     pub number: u32,
-    pub title: String,
+    pub title: Option<String>,
     pub body: Option<String>,
     pub user: Option<User>,
-    pub state: String,
-    pub locked: bool,
+    pub state: Option<String>,
+    pub locked: Option<bool>,
     pub assignee: Option<User>,
+    #[serde(default)]
     pub assignees: Vec<User>,
+    #[serde(default)]
     pub requested_reviewers: Vec<User>,
+    #[serde(default)]
     pub requested_teams: Vec<Team>,
     pub milestone: Option<Milestone>,
     pub head: PullRequestBranch,
     pub base: PullRequestBranch,
-    pub merged: bool,
+    pub merged: Option<bool>,
     pub mergeable: Option<bool>,
     pub rebaseable: Option<bool>,
-    pub mergeable_state: String,
+    pub mergeable_state: Option<String>,
     pub merged_by: Option<User>,
-    pub comments: u32,
-    pub review_comments: u32,
-    pub maintainer_can_modify: bool,
-    pub commits: u32,
-    pub additions: u32,
-    pub deletions: u32,
-    pub changed_files: u32,
-    pub created_at: String,
-    pub updated_at: String,
+    pub comments: Option<u32>,
+    pub review_comments: Option<u32>,
+    pub maintainer_can_modify: Option<bool>,
+    pub commits: Option<u32>,
+    pub additions: Option<u32>,
+    pub deletions: Option<u32>,
+    pub changed_files: Option<u32>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
     pub closed_at: Option<String>,
     pub merged_at: Option<String>,
     pub merge_commit_sha: Option<String>,
-    pub author_association: String,
-    pub draft: bool,
-    pub html_url: String,
+    pub author_association: Option<String>,
+    pub draft: Option<bool>,
+    pub html_url: Option<String>,
     pub url: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PullRequestBranch {
-    pub label: String,
+    pub label: Option<String>,
     #[serde(rename = "ref")]
     pub ref_name: String,
     pub sha: String,
     pub user: Option<User>,
-    pub repo: Repository,
+    /// Null once the branch's repository (usually a fork) has been deleted
+    pub repo: Option<Repository>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -523,6 +625,35 @@ pub struct SponsorshipTier {
     pub name: String,
 }
 
+/// StatusEvent payload (commit status changes)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusEventPayload {
+    pub id: u64,
+    pub sha: String,
+    pub name: String, // Full repository name
+    pub target_url: Option<String>,
+    pub context: String,
+    pub description: Option<String>,
+    pub state: String, // "pending", "success", "failure", "error"
+    pub commit: serde_json::Value,
+    pub branches: Vec<StatusBranch>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusBranch {
+    pub name: String,
+    pub commit: StatusBranchCommit,
+    pub protected: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusBranchCommit {
+    pub sha: String,
+    pub url: String,
+}
+
 /// WatchEvent payload
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WatchEventPayload {
@@ -552,7 +683,9 @@ pub struct User {
     pub user_type: String,
 }
 
-/// Helper function to parse a GitHub event into a specific type
+/// Helper function to parse a GitHub event into a specific type. The archive binary
+/// reads payloads through `GitHubEventType::parse`; these are kept for other callers
+#[allow(dead_code)]
 impl GitHubEvent {
     pub fn parse_payload<T>(&self) -> Result<T, serde_json::Error>
     where
@@ -560,83 +693,576 @@ impl GitHubEvent {
     {
         serde_json::from_value(self.payload.clone())
     }
+
+    /// Parses the payload into the variant for this event's type. See `GitHubEventType::parse`.
+    pub fn parse_typed(&self) -> Result<GitHubEventType, serde_json::Error> {
+        GitHubEventType::parse(&self.event_type, &self.payload.to_string())
+    }
 }
 
-/// Helper functions for common event type parsing
+/// Helper functions for common event type parsing.
+///
+/// The `as_*` methods return `None` both when the event is of a different type and when
+/// its payload fails to parse. The `try_as_*` methods distinguish the two: `Ok(None)` for
+/// a different event type, `Err` for a payload that doesn't parse.
+#[allow(dead_code)]
 impl GitHubEvent {
-    pub fn as_push_event(&self) -> Option<PushEventPayload> {
-        if self.event_type == "PushEvent" {
-            self.parse_payload().ok()
+    fn try_parse_as<T>(&self, event_type: &str) -> Result<Option<T>, serde_json::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        if self.event_type == event_type {
+            self.parse_payload().map(Some)
         } else {
-            None
+            Ok(None)
         }
     }
 
+    pub fn as_push_event(&self) -> Option<PushEventPayload> {
+        self.try_as_push_event().ok().flatten()
+    }
+
+    pub fn try_as_push_event(&self) -> Result<Option<PushEventPayload>, serde_json::Error> {
+        self.try_parse_as("PushEvent")
+    }
+
     pub fn as_pull_request_event(&self) -> Option<PullRequestEventPayload> {
-        if self.event_type == "PullRequestEvent" {
-            self.parse_payload().ok()
-        } else {
-            None
-        }
+        self.try_as_pull_request_event().ok().flatten()
+    }
+
+    pub fn try_as_pull_request_event(&self) -> Result<Option<PullRequestEventPayload>, serde_json::Error> {
+        self.try_parse_as("PullRequestEvent")
     }
 
     pub fn as_issues_event(&self) -> Option<IssuesEventPayload> {
-        if self.event_type == "IssuesEvent" {
-            self.parse_payload().ok()
-        } else {
-            None
-        }
+        self.try_as_issues_event().ok().flatten()
+    }
+
+    pub fn try_as_issues_event(&self) -> Result<Option<IssuesEventPayload>, serde_json::Error> {
+        self.try_parse_as("IssuesEvent")
     }
 
     pub fn as_fork_event(&self) -> Option<ForkEventPayload> {
-        if self.event_type == "ForkEvent" {
-            self.parse_payload().ok()
-        } else {
-            None
-        }
+        self.try_as_fork_event().ok().flatten()
+    }
+
+    pub fn try_as_fork_event(&self) -> Result<Option<ForkEventPayload>, serde_json::Error> {
+        self.try_parse_as("ForkEvent")
     }
 
     pub fn as_watch_event(&self) -> Option<WatchEventPayload> {
-        if self.event_type == "WatchEvent" {
-            self.parse_payload().ok()
-        } else {
-            None
-        }
+        self.try_as_watch_event().ok().flatten()
+    }
+
+    pub fn try_as_watch_event(&self) -> Result<Option<WatchEventPayload>, serde_json::Error> {
+        self.try_parse_as("WatchEvent")
     }
 
     pub fn as_create_event(&self) -> Option<CreateEventPayload> {
-        if self.event_type == "CreateEvent" {
-            self.parse_payload().ok()
-        } else {
-            None
-        }
+        self.try_as_create_event().ok().flatten()
+    }
+
+    pub fn try_as_create_event(&self) -> Result<Option<CreateEventPayload>, serde_json::Error> {
+        self.try_parse_as("CreateEvent")
     }
 
     pub fn as_delete_event(&self) -> Option<DeleteEventPayload> {
-        if self.event_type == "DeleteEvent" {
-            self.parse_payload().ok()
-        } else {
-            None
-        }
+        self.try_as_delete_event().ok().flatten()
+    }
+
+    pub fn try_as_delete_event(&self) -> Result<Option<DeleteEventPayload>, serde_json::Error> {
+        self.try_parse_as("DeleteEvent")
     }
 
     pub fn as_release_event(&self) -> Option<ReleaseEventPayload> {
-        if self.event_type == "ReleaseEvent" {
-            self.parse_payload().ok()
-        } else {
-            None
-        }
+        self.try_as_release_event().ok().flatten()
+    }
+
+    pub fn try_as_release_event(&self) -> Result<Option<ReleaseEventPayload>, serde_json::Error> {
+        self.try_parse_as("ReleaseEvent")
+    }
+
+    pub fn as_deployment_event(&self) -> Option<DeploymentEventPayload> {
+        self.try_as_deployment_event().ok().flatten()
+    }
+
+    pub fn try_as_deployment_event(&self) -> Result<Option<DeploymentEventPayload>, serde_json::Error> {
+        self.try_parse_as("DeploymentEvent")
+    }
+
+    pub fn as_deployment_status_event(&self) -> Option<DeploymentStatusEventPayload> {
+        self.try_as_deployment_status_event().ok().flatten()
+    }
+
+    pub fn try_as_deployment_status_event(&self) -> Result<Option<DeploymentStatusEventPayload>, serde_json::Error> {
+        self.try_parse_as("DeploymentStatusEvent")
+    }
+
+    pub fn as_status_event(&self) -> Option<StatusEventPayload> {
+        self.try_as_status_event().ok().flatten()
+    }
+
+    pub fn try_as_status_event(&self) -> Result<Option<StatusEventPayload>, serde_json::Error> {
+        self.try_parse_as("StatusEvent")
+    }
+
+    pub fn as_pull_request_review_event(&self) -> Option<PullRequestReviewEventPayload> {
+        self.try_as_pull_request_review_event().ok().flatten()
+    }
+
+    pub fn try_as_pull_request_review_event(&self) -> Result<Option<PullRequestReviewEventPayload>, serde_json::Error> {
+        self.try_parse_as("PullRequestReviewEvent")
+    }
+
+    pub fn as_pull_request_review_comment_event(&self) -> Option<PullRequestReviewCommentEventPayload> {
+        self.try_as_pull_request_review_comment_event().ok().flatten()
+    }
+
+    pub fn try_as_pull_request_review_comment_event(&self) -> Result<Option<PullRequestReviewCommentEventPayload>, serde_json::Error> {
+        self.try_parse_as("PullRequestReviewCommentEvent")
     }
 
     pub fn as_issue_comment_event(&self) -> Option<IssueCommentEventPayload> {
-        if self.event_type == "IssueCommentEvent" {
-            let maybe_ok = self.parse_payload();
-            if let Ok(payload) = maybe_ok {
-                return Some(payload);
+        self.try_as_issue_comment_event().ok().flatten()
+    }
+
+    pub fn try_as_issue_comment_event(&self) -> Result<Option<IssueCommentEventPayload>, serde_json::Error> {
+        self.try_parse_as("IssueCommentEvent")
+    }
+}
+
+/// An event in the timeline format GH Archive used before 2015: the repository is a
+/// `repository` object with its owner and name apart, the actor is only a login and
+/// events have no id
+#[derive(Debug, Deserialize)]
+struct LegacyEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    actor: Option<String>,
+    actor_attributes: Option<LegacyActorAttributes>,
+    /// Missing for events outside a repository, like FollowEvent and GistEvent
+    repository: Option<LegacyRepository>,
+    #[serde(default)]
+    payload: serde_json::Value,
+    #[serde(default = "legacy_public")]
+    public: bool,
+    created_at: String,
+}
+
+fn legacy_public() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyActorAttributes {
+    gravatar_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyRepository {
+    #[serde(default)]
+    id: u64,
+    #[serde(default)]
+    owner: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    url: String,
+}
+
+impl LegacyEvent {
+    /// Converts to the current shape, with the repository name rebuilt as `owner/name`.
+    /// The event type is kept as is, so types that no longer exist (ForkApplyEvent,
+    /// DownloadEvent, ...) pass through unchanged.
+    fn into_event(self) -> anyhow::Result<GitHubEvent> {
+        let created_at = chrono::DateTime::parse_from_rfc3339(&self.created_at)
+            .with_context(|| format!("Invalid created_at: {}", self.created_at))?
+            .timestamp_millis();
+        let repo = match self.repository {
+            Some(repository) => Repository {
+                id: repository.id,
+                name: legacy_repo_name(&repository.owner, &repository.name),
+                url: repository.url,
+            },
+            None => Repository { id: 0, name: String::new(), url: String::new() },
+        };
+        let login = self.actor.unwrap_or_default();
+        let id = legacy_event_id(&self.event_type, &repo.name, &login, created_at, &self.payload.to_string());
+        Ok(GitHubEvent {
+            id,
+            event_type: self.event_type,
+            actor: Actor {
+                id: 0,
+                display_login: None,
+                gravatar_id: self.actor_attributes.and_then(|attributes| attributes.gravatar_id).unwrap_or_default(),
+                url: String::new(),
+                avatar_url: String::new(),
+                login,
+            },
+            repo,
+            payload: self.payload,
+            public: self.public,
+            created_at: self.created_at,
+            org: None,
+        })
+    }
+}
+
+/// Joins the owner and name of a pre-2015 timeline repository. Either half may be
+/// missing from partial records, in which case the other is used alone.
+pub fn legacy_repo_name(owner: &str, name: &str) -> String {
+    match (owner.is_empty(), name.is_empty()) {
+        (false, false) => format!("{}/{}", owner, name),
+        (true, _) => name.to_string(),
+        (false, true) => owner.to_string(),
+    }
+}
+
+/// Derives an id for a pre-2015 timeline event, which has none, from the fields that
+/// identify it. The same event read from overlapping files gets the same id, so
+/// --dedupe and --sample treat it like any other.
+pub fn legacy_event_id(event_type: &str, repo_name: &str, actor_login: &str, created_at: i64, payload: &str) -> String {
+    let key = format!("{}\n{}\n{}\n{}\n{}", event_type, repo_name, actor_login, created_at, payload);
+    format!("legacy-{:016x}", crate::stable_hash(&key))
+}
+
+/// Parses one line of GH Archive events, in the current format or the pre-2015
+/// timeline one. The error reported is the current format's, which is what most
+/// inputs are in.
+fn parse_event(line: &str) -> anyhow::Result<GitHubEvent> {
+    match serde_json::from_str::<GitHubEvent>(line) {
+        Ok(event) => Ok(event),
+        Err(e) => match serde_json::from_str::<LegacyEvent>(line) {
+            Ok(legacy) if legacy.repository.is_some() || legacy.actor.is_some() => legacy.into_event(),
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// Reads newline-delimited GH Archive events, skipping blank lines. A line that doesn't
+/// parse yields an `Err` item and reading continues with the next one; a read error
+/// yields an `Err` item and ends the iterator, since the rest of the input is unreadable.
+pub fn read_events<R: BufRead>(reader: R) -> impl Iterator<Item = anyhow::Result<GitHubEvent>> {
+    let mut read_failed = false;
+    reader.lines()
+        .enumerate()
+        .map_while(move |(index, line)| {
+            if read_failed {
+                return None;
             }
-            panic!("Failed to parse IssueCommentEvent: {:?}", maybe_ok);
-        } else {
-            None
+            let line_number = index + 1;
+            Some(match line {
+                Ok(line) if line.trim().is_empty() => None,
+                Ok(line) => Some(parse_event(&line)
+                    .with_context(|| format!("Failed to parse event on line {}", line_number))),
+                Err(e) => {
+                    read_failed = true;
+                    Some(Err(e).with_context(|| format!("Failed to read line {}", line_number)))
+                }
+            })
+        })
+        .flatten()
+}
+
+/// Reads a gzipped GH Archive hourly file. See `read_events`. The archive binary opens
+/// its inputs itself, since they may or may not be gzipped
+#[allow(dead_code)]
+pub fn read_events_from_gz(path: &Path) -> anyhow::Result<impl Iterator<Item = anyhow::Result<GitHubEvent>>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    // Hourly archives can be concatenated gzip members
+    Ok(read_events(BufReader::new(MultiGzDecoder::new(file))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    fn user() -> Value {
+        json!({
+            "id": 1, "login": "octocat", "gravatar_id": "", "url": "u", "html_url": "h",
+            "followers_url": "f", "following_url": "f", "gists_url": "g", "starred_url": "s",
+            "subscriptions_url": "s", "organizations_url": "o", "repos_url": "r", "events_url": "e",
+            "received_events_url": "r", "site_admin": false, "avatar_url": "a", "type": "User",
+        })
+    }
+
+    fn repository() -> Value {
+        json!({ "id": 2, "name": "octocat/hello", "url": "u" })
+    }
+
+    fn label() -> Value {
+        json!({ "id": 3, "name": "bug", "color": "f00", "description": null, "default": true, "url": "u" })
+    }
+
+    fn branch(ref_name: &str) -> Value {
+        json!({ "label": null, "ref": ref_name, "sha": "abc", "user": user(), "repo": repository() })
+    }
+
+    fn pull_request() -> Value {
+        json!({
+            "id": 4, "number": 7, "title": "Fix", "body": null, "user": user(), "state": "open",
+            "locked": false, "assignee": null, "assignees": [], "requested_reviewers": [user()],
+            "requested_teams": [], "milestone": null, "head": branch("feature"), "base": branch("main"),
+            "merged": false, "mergeable": null, "rebaseable": null, "mergeable_state": null,
+            "merged_by": null, "comments": 0, "review_comments": 1, "maintainer_can_modify": true,
+            "commits": 2, "additions": 10, "deletions": 3, "changed_files": 1,
+            "created_at": "2024-01-01T00:00:00Z", "updated_at": "2024-01-02T00:00:00Z",
+            "closed_at": null, "merged_at": null, "merge_commit_sha": null,
+            "author_association": "OWNER", "draft": false, "html_url": "h", "url": "u",
+        })
+    }
+
+    fn issue() -> Value {
+        json!({
+            "id": 5, "number": 8, "title": "Broken", "body": "It broke", "user": user(), "state": "open",
+            "locked": false, "assignee": null, "assignees": [], "milestone": null, "comments": 1,
+            "created_at": "2024-01-01T00:00:00Z", "updated_at": "2024-01-01T00:00:00Z", "closed_at": null,
+            "author_association": "NONE", "labels": [label()], "html_url": "h", "url": "u",
+            "pull_request": null,
+        })
+    }
+
+    fn review_comment() -> Value {
+        json!({
+            "id": 6, "url": "u", "html_url": "h", "pull_request_url": "p", "body": "nit", "user": user(),
+            "created_at": "2024-01-01T00:00:00Z", "updated_at": "2024-01-01T00:00:00Z",
+            "author_association": "MEMBER", "commit_id": "abc", "original_commit_id": "abc",
+            "diff_hunk": "@@ -1 +1 @@", "path": "src/lib.rs", "position": 1, "original_position": 1,
+            "line": 1, "original_line": 1, "start_line": null, "original_start_line": null,
+            "side": "RIGHT", "start_side": null, "pull_request_review_id": 9,
+        })
+    }
+
+    fn deployment() -> Value {
+        json!({
+            "id": 10, "sha": "abc", "ref": "main", "task": "deploy", "payload": {},
+            "original_environment": "production", "environment": "production", "description": null,
+            "creator": user(), "created_at": "2024-01-01T00:00:00Z", "updated_at": "2024-01-01T00:00:00Z",
+            "statuses_url": "s", "repository_url": "r", "url": "u",
+        })
+    }
+
+    fn sponsorship_tier() -> Value {
+        json!({
+            "node_id": "T", "created_at": "2024-01-01T00:00:00Z", "description": "Gold",
+            "monthly_price_in_cents": 500, "monthly_price_in_dollars": 5, "name": "$5 a month",
+        })
+    }
+
+    /// A sample payload for every modeled event type, with every field present
+    fn sample_payloads() -> Vec<(&'static str, Value)> {
+        vec![
+            ("CommitCommentEvent", json!({
+                "action": "created",
+                "comment": {
+                    "id": 11, "url": "u", "html_url": "h", "body": "lgtm", "user": user(),
+                    "created_at": "2024-01-01T00:00:00Z", "updated_at": "2024-01-01T00:00:00Z",
+                    "commit_id": "abc", "path": null, "position": null, "line": null,
+                },
+            })),
+            ("CreateEvent", json!({
+                "ref_type": "branch", "ref": "feature", "master_branch": "main", "description": null,
+                "pusher_type": "user",
+            })),
+            ("DeleteEvent", json!({ "ref_type": "tag", "ref": "v1.0", "pusher_type": "user" })),
+            ("DeploymentEvent", json!({ "action": "created", "deployment": deployment() })),
+            ("DeploymentStatusEvent", json!({
+                "action": null,
+                "deployment_status": {
+                    "id": 12, "state": "success", "creator": null, "description": null,
+                    "environment": "production", "target_url": null, "log_url": null,
+                    "environment_url": null, "created_at": "2024-01-01T00:00:00Z",
+                    "updated_at": "2024-01-01T00:00:00Z", "deployment_url": "d", "repository_url": "r",
+                    "url": "u",
+                },
+                "deployment": deployment(),
+            })),
+            ("ForkEvent", json!({ "forkee": repository() })),
+            ("GollumEvent", json!({
+                "pages": [{
+                    "page_name": "Home", "title": "Home", "summary": null, "action": "edited",
+                    "sha": "abc", "html_url": "h",
+                }],
+            })),
+            ("IssueCommentEvent", json!({
+                "action": "edited",
+                "changes": { "body": { "from": "old" }, "title": null },
+                "issue": issue(),
+                "comment": {
+                    "id": 13, "url": "u", "html_url": "h", "body": "new", "user": user(),
+                    "created_at": "2024-01-01T00:00:00Z", "updated_at": "2024-01-02T00:00:00Z",
+                    "author_association": "NONE",
+                },
+            })),
+            ("IssuesEvent", json!({
+                "action": "labeled", "issue": issue(), "changes": null, "assignee": null, "label": label(),
+            })),
+            ("MemberEvent", json!({
+                "action": "edited", "member": user(), "changes": { "permission": { "from": "read" } },
+            })),
+            ("PublicEvent", json!({})),
+            ("PullRequestEvent", json!({
+                "action": "opened", "number": 7, "changes": null, "pull_request": pull_request(),
+                "assignee": null, "requested_reviewer": null, "requested_team": null, "label": null,
+            })),
+            ("PullRequestReviewEvent", json!({
+                "action": "submitted",
+                "review": {
+                    "id": 9, "user": user(), "body": null, "state": "approved", "html_url": "h",
+                    "pull_request_url": "p", "author_association": "MEMBER",
+                    "submitted_at": "2024-01-01T00:00:00Z", "commit_id": "abc",
+                },
+                "pull_request": pull_request(),
+                "changes": null,
+            })),
+            ("PullRequestReviewCommentEvent", json!({
+                "action": "created", "changes": null, "pull_request": pull_request(), "comment": review_comment(),
+            })),
+            ("PullRequestReviewThreadEvent", json!({
+                "action": "resolved",
+                "pull_request": pull_request(),
+                "thread": { "node_id": "N", "comments": [review_comment()] },
+            })),
+            ("PushEvent", json!({
+                "push_id": 14, "size": 1, "distinct_size": 1, "ref": "refs/heads/main", "head": "def",
+                "before": "abc",
+                "commits": [{
+                    "sha": "def", "message": "Fix", "author": { "name": "Octo Cat", "email": "o@example.com" },
+                    "url": "u", "distinct": true,
+                }],
+            })),
+            ("ReleaseEvent", json!({
+                "action": "published",
+                "changes": null,
+                "release": {
+                    "id": 15, "tag_name": "v1.0", "target_commitish": "main", "name": "1.0", "body": null,
+                    "draft": false, "prerelease": false, "created_at": "2024-01-01T00:00:00Z",
+                    "published_at": "2024-01-01T00:00:00Z", "author": user(),
+                    "assets": [{
+                        "id": 16, "name": "bin.tar.gz", "label": null, "uploader": user(),
+                        "content_type": "application/gzip", "state": "uploaded", "size": 1024,
+                        "download_count": 0, "created_at": "2024-01-01T00:00:00Z",
+                        "updated_at": "2024-01-01T00:00:00Z", "browser_download_url": "b", "url": "u",
+                    }],
+                    "tarball_url": null, "zipball_url": null, "html_url": "h", "url": "u",
+                },
+            })),
+            ("SponsorshipEvent", json!({
+                "action": "tier_changed",
+                "effective_date": null,
+                "changes": { "tier": { "from": sponsorship_tier() }, "privacy_level": null },
+                "sponsorship": {
+                    "node_id": "S", "created_at": "2024-01-01T00:00:00Z", "sponsorable": user(),
+                    "sponsor": user(), "privacy_level": "public", "tier": sponsorship_tier(),
+                },
+            })),
+            ("StatusEvent", json!({
+                "id": 17, "sha": "abc", "name": "octocat/hello", "target_url": null, "context": "ci",
+                "description": null, "state": "success", "commit": { "sha": "abc" },
+                "branches": [{ "name": "main", "commit": { "sha": "abc", "url": "u" }, "protected": null }],
+                "created_at": "2024-01-01T00:00:00Z", "updated_at": "2024-01-01T00:00:00Z",
+            })),
+            ("WatchEvent", json!({ "action": "started" })),
+        ]
+    }
+
+    /// The payload fields of a parsed event, without the tag serde adds
+    fn payload_of(typed: &GitHubEventType) -> Value {
+        let mut value = serde_json::to_value(typed).unwrap();
+        value.as_object_mut().unwrap().remove("type");
+        value
+    }
+
+    #[test]
+    fn every_modeled_event_type_round_trips() {
+        for (event_type, payload) in sample_payloads() {
+            let typed = GitHubEventType::parse(event_type, &payload.to_string())
+                .unwrap_or_else(|e| panic!("{} didn't parse: {}", event_type, e));
+            assert!(!matches!(typed, GitHubEventType::Other(_)), "{} parsed as Other", event_type);
+            assert_eq!(serde_json::to_value(&typed).unwrap()["type"], event_type);
+            assert_eq!(payload_of(&typed), payload, "{} changed in the round trip", event_type);
+        }
+    }
+
+    #[test]
+    fn unmodeled_event_types_keep_the_raw_payload() {
+        let payload = json!({ "action": "added", "team": { "name": "core" } });
+        match GitHubEventType::parse("TeamAddEvent", &payload.to_string()).unwrap() {
+            GitHubEventType::Other(value) => assert_eq!(value, payload),
+            other => panic!("parsed as {:?}", other),
         }
     }
+
+    #[test]
+    fn modeled_event_type_with_mismatched_payload_is_an_error() {
+        assert!(GitHubEventType::parse("PushEvent", r#"{"action": "started"}"#).is_err());
+    }
+
+    fn event(event_type: &str, payload: Value) -> GitHubEvent {
+        serde_json::from_value(json!({
+            "id": "1", "type": event_type, "public": true, "created_at": "2024-01-01T00:00:00Z", "org": null,
+            "actor": { "id": 1, "login": "octocat", "gravatar_id": "", "url": "u", "avatar_url": "a" },
+            "repo": repository(),
+            "payload": payload,
+        })).unwrap()
+    }
+
+    #[test]
+    fn accessors_tell_other_types_from_unparseable_payloads() {
+        let (_, payload) = sample_payloads().into_iter().find(|(event_type, _)| *event_type == "IssueCommentEvent").unwrap();
+        let comment = event("IssueCommentEvent", payload);
+        assert!(comment.as_issue_comment_event().is_some());
+        assert!(comment.as_push_event().is_none());
+        assert!(comment.try_as_push_event().unwrap().is_none());
+
+        let broken = event("IssueCommentEvent", json!({ "action": "created" }));
+        assert!(broken.as_issue_comment_event().is_none());
+        assert!(broken.try_as_issue_comment_event().is_err());
+    }
+
+    #[test]
+    fn deployment_and_status_accessors_parse_their_payloads() {
+        let payloads = sample_payloads();
+        let event_of = |event_type: &str| {
+            let (_, payload) = payloads.iter().find(|(name, _)| *name == event_type).unwrap();
+            event(event_type, payload.clone())
+        };
+        assert!(event_of("DeploymentEvent").as_deployment_event().is_some());
+        assert!(event_of("DeploymentStatusEvent").as_deployment_status_event().is_some());
+        assert!(event_of("StatusEvent").as_status_event().is_some());
+        assert!(event_of("StatusEvent").try_as_deployment_event().unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_typed_matches_the_event_type() {
+        let watch = event("WatchEvent", json!({ "action": "started" }));
+        assert!(matches!(watch.parse_typed().unwrap(), GitHubEventType::WatchEvent(payload) if payload.action == "started"));
+
+        let gist = event("GistEvent", json!({ "action": "create" }));
+        assert!(matches!(gist.parse_typed().unwrap(), GitHubEventType::Other(_)));
+
+        let broken = event("IssueCommentEvent", json!({ "action": "created" }));
+        assert!(broken.parse_typed().is_err());
+    }
+
+    #[test]
+    fn read_events_from_gz_reads_a_gzipped_hour() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let watch = serde_json::to_string(&event("WatchEvent", json!({ "action": "started" }))).unwrap();
+        let gist = serde_json::to_string(&event("GistEvent", json!({ "action": "create" }))).unwrap();
+        let path = std::env::temp_dir().join(format!("gh-read-events-{}.json.gz", std::process::id()));
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        write!(encoder, "{}\n\n{}\n", watch, gist).unwrap();
+        encoder.finish().unwrap();
+
+        let events: Vec<GitHubEvent> = read_events_from_gz(&path).unwrap().collect::<anyhow::Result<_>>().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let types: Vec<&str> = events.iter().map(|event| event.event_type.as_str()).collect();
+        assert_eq!(types, ["WatchEvent", "GistEvent"]);
+        assert!(read_events_from_gz(&path).is_err());
+    }
 }
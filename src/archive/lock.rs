@@ -0,0 +1,101 @@
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The lock file currently held, so the Ctrl-C handler can remove it before exiting
+static HELD: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Contents of a lock file: which process holds it and since when
+#[derive(Debug, Serialize, Deserialize)]
+struct LockOwner {
+    pid: u32,
+    started_at: DateTime<Utc>,
+}
+
+/// Whether a process is still running, when that can be told. Only Linux is checked,
+/// through /proc.
+fn process_alive(pid: u32) -> Option<bool> {
+    if cfg!(target_os = "linux") {
+        Some(Path::new(&format!("/proc/{}", pid)).exists())
+    } else {
+        None
+    }
+}
+
+/// An advisory lock on an output directory, held for the length of a run so two runs
+/// can't truncate each other's bucket files. The lock file is removed when this is dropped.
+pub struct OutputLock {
+    path: PathBuf,
+}
+
+impl OutputLock {
+    /// Creates the lock file, failing if another run holds it. With `force`, a lock left
+    /// behind by a process that no longer runs is taken over.
+    pub fn acquire(path: &Path, force: bool) -> Result<Self> {
+        let owner = LockOwner { pid: std::process::id(), started_at: Utc::now() };
+        let mut stale = false;
+        loop {
+            let created = if stale {
+                File::create(path)
+            } else {
+                OpenOptions::new().write(true).create_new(true).open(path)
+            };
+            match created {
+                Ok(mut file) => {
+                    file.write_all(serde_json::to_string(&owner)?.as_bytes())
+                        .context(format!("Failed to write lock file {}", path.display()))?;
+                    *HELD.lock().unwrap() = Some(path.to_path_buf());
+                    return Ok(Self { path: path.to_path_buf() });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e).context(format!("Failed to create lock file {}", path.display())),
+            }
+
+            let holder = std::fs::read_to_string(path).ok()
+                .and_then(|contents| serde_json::from_str::<LockOwner>(&contents).ok());
+            let Some(holder) = holder else {
+                return Err(anyhow::anyhow!(
+                    "{} exists but doesn't name the run holding it; remove it if no other run uses this output directory",
+                    path.display()
+                ));
+            };
+            match process_alive(holder.pid) {
+                Some(true) => return Err(anyhow::anyhow!(
+                    "{} is held by process {} (started {}), which is still writing to this output directory; wait for it to finish or use another --output-dir",
+                    path.display(), holder.pid, holder.started_at.to_rfc3339()
+                )),
+                _ if force => {
+                    log::warn!("Taking over the lock of process {} (started {})", holder.pid, holder.started_at.to_rfc3339());
+                    stale = true;
+                }
+                Some(false) => return Err(anyhow::anyhow!(
+                    "{} was left by process {} (started {}), which is no longer running; pass --force to take it over",
+                    path.display(), holder.pid, holder.started_at.to_rfc3339()
+                )),
+                None => return Err(anyhow::anyhow!(
+                    "{} is held by process {} (started {}); if that run is gone, pass --force to take it over",
+                    path.display(), holder.pid, holder.started_at.to_rfc3339()
+                )),
+            }
+        }
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        HELD.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Removes the held lock file, for exits that skip destructors.
+pub fn release_held() {
+    if let Some(path) = HELD.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+        let _ = std::fs::remove_file(path);
+    }
+}
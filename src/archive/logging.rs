@@ -0,0 +1,60 @@
+use std::io::{IsTerminal, Write};
+
+use anyhow::Result;
+use indicatif::{MultiProgress, ProgressDrawTarget};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::progress::ProgressFormat;
+
+/// Writes log records around the progress bars, which would otherwise be torn by lines
+/// printed while they are drawn
+struct ProgressLogger {
+    inner: env_logger::Logger,
+    progress: MultiProgress,
+}
+
+impl Log for ProgressLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.matches(record) {
+            self.progress.suspend(|| self.inner.log(record));
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Sets up logging for a run: warnings and errors only with --quiet, info by default,
+/// debug with -v and trace with -vv. RUST_LOG, when set, overrides the level. Progress
+/// bars are only drawn when not quiet, not replaced by --progress json, and stderr (where
+/// they are drawn) is a terminal, so CI logs get plain lines.
+pub fn init(verbose: u8, quiet: bool, format: ProgressFormat, progress: &MultiProgress) -> Result<()> {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    let inner = env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        // The messages carry their own ✓/✗/⚠ markers; only the chattier levels are tagged
+        .format(|buf, record| match record.level() {
+            Level::Debug | Level::Trace => writeln!(buf, "[{}] {}", record.level().as_str().to_lowercase(), record.args()),
+            _ => writeln!(buf, "{}", record.args()),
+        })
+        .build();
+
+    if quiet || format == ProgressFormat::Json || !std::io::stderr().is_terminal() {
+        progress.set_draw_target(ProgressDrawTarget::hidden());
+    }
+
+    log::set_max_level(inner.filter());
+    log::set_boxed_logger(Box::new(ProgressLogger { inner, progress: progress.clone() }))?;
+    Ok(())
+}
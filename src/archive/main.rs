@@ -1,76 +1,1198 @@
+mod columns;
+mod commits;
+mod compact;
+mod download;
+mod error;
+mod extract;
 mod gh;
+mod lock;
+mod logging;
+// Replays the archive events of a pull request into its timeline. Not wired into the
+// archive binary, which writes one pull_requests row per event instead
+#[allow(dead_code)]
 mod pr;
+mod progress;
+mod pull_requests;
+mod query;
+mod remote;
+mod renames;
+mod repo_counts;
+mod timing;
+mod verify;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{File, create_dir_all};
-use std::path::Path;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
 use anyhow::{Result, Context};
-use indicatif::{ProgressBar, ProgressStyle};
-use clap::Parser;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use log::{debug, error, info, trace, warn};
+use rayon::prelude::*;
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use parquet::file::reader::{FileReader, SerializedFileReader};
-use parquet::record::{Row, RowAccessor};
+use parquet::record::{Field, Row, RowAccessor};
+use parquet::record::reader::RowIter;
 use parquet::file::writer::SerializedFileWriter;
 use parquet::schema::parser::parse_message_type;
 use parquet::file::properties::WriterProperties;
-use parquet::basic::Compression;
+use parquet::basic::{Compression, ConvertedType, GzipLevel, LogicalType, Type as PhysicalType, ZstdLevel};
 use parquet::schema::types::Type;
-use parquet::column::writer::ColumnWriter;
-use parquet::data_type::{ByteArray, Int64Type, ByteArrayType};
+use parquet::schema::types::SchemaDescriptor;
+use parquet::arrow::parquet_to_arrow_schema;
+use arrow_array::{ArrayRef, Int64Array, RecordBatch, StringArray, TimestampMillisecondArray};
+use arrow_ipc::CompressionType as IpcCompression;
+use arrow_ipc::writer::{FileWriter, IpcWriteOptions};
+use arrow_schema::SchemaRef;
 use serde_json::Value;
 use chrono::{DateTime, Utc, Datelike};
+use flate2::read::MultiGzDecoder;
+use gh::{GitHubEvent, GitHubEventType};
+use error::ArchiveError;
+use extract::{Extraction, Extractions};
+use renames::{ORIGINAL_REPO_NAME_COLUMN, RenameMap};
+use commits::{COMMITS_SCHEMA, CommitBuffer, CommitRow, flush_commits_to_parquet};
+use columns::write_optional_string_column;
+use lock::OutputLock;
+use remote::RemoteDir;
+use repo_counts::RepoCounts;
+use timing::{Phase, PhaseTimings};
+use progress::{FileStatus, ProgressEvent, ProgressFormat};
+use pull_requests::{PULL_REQUESTS_SCHEMA, PullRequestBuffer, PullRequestRow, flush_pull_requests_to_parquet};
 
 #[derive(Parser)]
 #[command(name = "git-history-exporter")]
 #[command(about = "Export and process Git history archives")]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Args {
-    /// Timeframe to process (YYYY, YYYY-MM, or YYYY-MM-DD)
-    timeframe: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Timeframe to process (YYYY, YYYY-MM, or YYYY-MM-DD). Optional when the inputs
+    /// are given by --files or --file-list
+    #[arg(required_unless_present_any = ["files", "file_list"])]
+    timeframe: Option<String>,
+
+    /// Directory the input files are read from. May also be an s3://bucket/prefix or
+    /// gs://bucket/prefix URL, in which case each input is downloaded to a temporary
+    /// file while it is processed
+    #[arg(long, default_value = INPUT_DIR)]
+    input_dir: PathBuf,
+
+    /// Process the local files matching a glob (e.g. 'data/2024-*/*.json.gz') instead of
+    /// looking up the timeframe in --input-dir. May be repeated
+    #[arg(long = "files", value_name = "GLOB")]
+    files: Vec<String>,
+
+    /// Process the local files listed in a file, one path per line, instead of looking
+    /// up the timeframe in --input-dir. Blank lines and lines starting with # are ignored
+    #[arg(long)]
+    file_list: Option<PathBuf>,
+
+    /// Directory the bucket files, manifest and run summary are written to. May also be
+    /// an s3:// or gs:// URL: files are then written to a temporary directory and
+    /// uploaded once closed. Credentials for both come from the environment or instance
+    /// metadata. Runs add to what the directory already holds and never truncate an
+    /// existing file (unless --overwrite): a bucket's rows for a month are spread over
+    /// part files, one per checkpoint (see --checkpoint-every) plus numbered parts, so
+    /// readers should treat each bucket as a dataset of all its parts, or merge them
+    /// with `compact`
+    #[arg(long, default_value = OUTPUT_DIR)]
+    output_dir: PathBuf,
+
+    /// Skip input files already recorded as completed in the manifest. The files of a
+    /// checkpoint that did not complete are removed and all of its inputs redone
+    #[arg(long)]
+    resume: bool,
+
+    /// Like --resume, but also redo inputs whose size or modification time changed
+    /// since they were processed. Output files of unchanged inputs are left untouched
+    #[arg(long)]
+    skip_existing: bool,
+
+    /// Take over the output directory's lock file when the run that left it is no
+    /// longer running. A lock held by a live process is never taken over
+    #[arg(long)]
+    force: bool,
+
+    /// Rows buffered per bucket before they are written out as one row group.
+    /// Larger row groups read faster but hold more memory per open bucket; with
+    /// wide payloads prefer a byte limit via --buffer-bytes instead
+    #[arg(long, visible_alias = "row-group-size", default_value_t = 1000)]
+    buffer_rows: usize,
+
+    /// Also flush a bucket's buffer once its string data reaches this many bytes,
+    /// which bounds memory when payloads are large: a single row at or over the limit
+    /// is written out on its own. Unset by default, so only --buffer-rows decides when
+    /// a buffer is flushed; when set, the row limit still applies
+    #[arg(long, visible_alias = "max-buffer-bytes", value_name = "BYTES")]
+    buffer_bytes: Option<u64>,
+
+    /// Cap on the data buffered across all buckets and threads, in MiB. When exceeded,
+    /// the largest buffers of the pool receiving a row are written out first until the
+    /// total is back under half the cap. Memory held by the open files themselves is
+    /// bounded by --max-open-writers instead
+    #[arg(long)]
+    max_buffer_mb: Option<usize>,
+
+    /// Disable dictionary encoding in the output files
+    #[arg(long)]
+    no_dictionary: bool,
+
+    /// File format of the bucket files
+    #[arg(long, value_enum, default_value_t = OutputFormat::Parquet)]
+    output_format: OutputFormat,
+
+    /// Compression codec for the output files
+    #[arg(long, value_enum, default_value_t = CompressionCodec::Zstd)]
+    compression: CompressionCodec,
+
+    /// Compression level (gzip: 0-9, zstd: 1-22; not supported by none, snappy or lz4)
+    #[arg(long)]
+    compression_level: Option<i32>,
+
+    /// Compress each closed parquet file as a whole into a zstd frame, named .parquet.zst
+    /// like the input files. `verify` and `query` read these files; `compact` skips them
+    #[arg(long)]
+    wrap_zstd: bool,
+
+    /// Only keep rows with these event types. Accepts a comma-separated list and may be
+    /// repeated (--event-type PushEvent --event-type PullRequestEvent). When empty, all
+    /// event types are kept.
+    #[arg(long, alias = "event-type", value_name = "TYPE", value_delimiter = ',')]
+    event_types: Vec<String>,
+
+    /// Drop rows with these event types (comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    exclude_event_types: Vec<String>,
+
+    /// Only keep rows for these repositories. Accepts exact owner/name values or globs
+    /// such as rust-lang/* and may be repeated
+    #[arg(long = "repo", value_name = "PATTERN")]
+    repos: Vec<String>,
+
+    /// File with one repository name or glob per line, combined with --repo
+    #[arg(long)]
+    repo_file: Option<PathBuf>,
+
+    /// Drop rows whose actor login ends with [bot] (dependabot[bot], renovate[bot], ...)
+    /// or is listed in --bot-logins-file
+    #[arg(long)]
+    no_bots: bool,
+
+    /// File with one more bot login per line, for bots that don't use a [bot] account
+    #[arg(long, requires = "no_bots")]
+    bot_logins_file: Option<PathBuf>,
+
+    /// Keep this fraction (0 < F <= 1) of the rows that pass the other filters, chosen by
+    /// a hash of the event id so reruns keep the same rows
+    #[arg(long, value_name = "F")]
+    sample: Option<f64>,
+
+    /// Stop once this many rows have been written. Inputs cut short are not recorded
+    /// as completed
+    #[arg(long, value_name = "N")]
+    limit: Option<u64>,
+
+    /// Drop events whose id was already written to the same bucket during this run
+    #[arg(long)]
+    dedupe: bool,
+
+    /// Abort an input file on its first unreadable row instead of skipping the row
+    #[arg(long)]
+    strict: bool,
+
+    /// Continue with the remaining input files after one fails (--keep-going=false aborts
+    /// on the first failure). The exit code is non-zero if any file failed either way
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    keep_going: bool,
+
+    /// Read an input file again, up to N more times with exponential backoff, after a
+    /// transient I/O error such as EIO or a timeout. Each attempt continues after the
+    /// last row the failed one read, keeping the rows already written. Errors that
+    /// would repeat, like an unreadable footer, and errors writing output still fail the
+    /// file right away
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retries: u32,
+
+    /// Append skipped rows (input file, row index, error) to this JSONL file
+    #[arg(long)]
+    error_log: Option<PathBuf>,
+
+    /// Close every output file after each N inputs and record those inputs as completed
+    /// in the manifest. The inputs of a checkpoint are read in parallel and share one
+    /// file per bucket, named after the checkpoint's first input, with their rows in the
+    /// order they arrive. Larger checkpoints make fewer, larger files; a checkpoint cut
+    /// short is redone as a whole by --resume
+    #[arg(long, value_name = "N", default_value_t = 256)]
+    checkpoint_every: usize,
+
+    /// Maximum number of output files open at once across all threads. When exceeded,
+    /// the least recently used bucket of the writer pool receiving a row is closed and
+    /// later rows go to a new part file. A pool with nothing open to close may go over
+    /// the cap by one file until another pool closes one
+    #[arg(long, default_value_t = 512)]
+    max_open_writers: usize,
+
+    /// How output files are split into directories. Changing this between runs that
+    /// share a manifest leaves files from both layouts in the output directory
+    #[arg(long, value_enum, default_value_t = PartitionStrategy::Prefix3)]
+    partition_by: PartitionStrategy,
+
+    /// Name output directories key=value (prefix=ten/month=2024-01/<part>.parquet) so
+    /// readers such as DuckDB and Spark can prune partitions
+    #[arg(long)]
+    hive_partitions: bool,
+
+    /// Also split each bucket by event type (t/e/n/2024-01/PushEvent.<part>.parquet). Every
+    /// event type gets its own writer, so a bucket can hold ~15 open files instead of one;
+    /// raise --max-open-writers accordingly or expect more evicted part files
+    #[arg(long)]
+    split_by_event_type: bool,
+
+    /// Also write the commits listed in PushEvent payloads to a second table per bucket
+    /// (2024-01.commits.<part>.parquet), one row per commit. Commit files have their own
+    /// writers, counted separately from --max-open-writers. Payloads that don't parse are
+    /// counted and logged; their events are still written
+    #[arg(long)]
+    emit_commits: bool,
+
+    /// Also write one row per PullRequestEvent to a third table per bucket
+    /// (2024-01.pull_requests.<part>.parquet) with the pull request's number, title,
+    /// state, size and refs. Fields older payloads don't have are null
+    #[arg(long)]
+    emit_pull_requests: bool,
+
+    /// Process only this share of the work, as i/N with 0 <= i < N, so N machines can
+    /// split a run. Output, manifest and summary file names are tagged with the shard
+    #[arg(long, value_parser = ShardId::parse)]
+    shard: Option<ShardId>,
+
+    /// What --shard divides between shards: whole input files, or repositories (every
+    /// shard reads every input and keeps the rows of its own repositories)
+    #[arg(long, value_enum, default_value_t = ShardBy::File, requires = "shard")]
+    shard_by: ShardBy,
+
+    /// Drop rows whose created_at falls outside the requested timeframe, even if their
+    /// input file is named for it (--clip-to-timeframe=false keeps them)
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    clip_to_timeframe: bool,
+
+    /// Replace existing output files. By default an existing file is kept and the new
+    /// rows go to a numbered part next to it (2024-01.<part>.0001.parquet), so
+    /// re-processing an input adds a second copy of its rows
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Format of the input files. By default .parquet/.parquet.zst files are read as
+    /// BigQuery exports and .json.gz/.json files as GH Archive hourly event files
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    input_format: InputFormat,
+
+    /// Write the number of rows written for each repository, in total and per event
+    /// type, to this CSV file. In a dry run the counts are estimated like the buckets
+    #[arg(long, value_name = "CSV")]
+    repo_counts: Option<PathBuf>,
+
+    /// Copy a payload field of one event type into its own events column, given as
+    /// EVENT:PATH:COLUMN with a dotted path, e.g. PushEvent:ref:ref or
+    /// PullRequestEvent:pull_request.number:pr_number. Extracted columns are nullable
+    /// strings (numbers and objects are written as JSON) and are null for other event
+    /// types. May be repeated
+    #[arg(long = "extract", value_name = "EVENT:PATH:COLUMN", value_parser = Extraction::parse)]
+    extractions: Vec<Extraction>,
+
+    /// CSV file of old_name,new_name[,effective_date] lines mapping renamed or transferred
+    /// repositories to their current name. Rows are renamed before filtering and
+    /// bucketing, following chained renames; with an effective date (YYYY-MM-DD) only
+    /// events created before it are renamed. The name a renamed row had is kept in a
+    /// repo_name_original column
+    #[arg(long, value_name = "FILE")]
+    rename_map: Option<PathBuf>,
+
+    /// Read the input files' metadata and a sample of their rows and print what a run
+    /// would produce, without creating any output files or directories
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Rows sampled from the start of each input file in a dry run
+    #[arg(long, default_value_t = 1000)]
+    dry_run_sample_rows: usize,
+
+    /// Route every input row in a dry run instead of a sample, so the bucket counts
+    /// match what a real run would write
+    #[arg(long, requires = "dry_run")]
+    dry_run_full: bool,
+
+    /// Number of largest buckets listed by a dry run
+    #[arg(long, default_value_t = 10)]
+    dry_run_top: usize,
+
+    /// Also write the dry run report to this JSON file
+    #[arg(long, requires = "dry_run")]
+    dry_run_json: Option<PathBuf>,
+
+    /// Reopen each file once it is closed and check that its footer has every row written
+    /// and the created_at range the summary lists, failing the run on any mismatch.
+    /// Needs local parquet output
+    #[arg(long, conflicts_with = "dry_run")]
+    verify: bool,
+
+    /// Measure the time spent decoding input, extracting row fields, filtering and
+    /// bucketing, buffering and writing output, and print it by phase (also in the run
+    /// summary). Without it the phases aren't timed at all
+    #[arg(long, conflicts_with = "dry_run")]
+    report_timing: bool,
+
+    /// Print more while running: -v adds debug messages, -vv also traces rows without
+    /// data. RUST_LOG overrides the level when set
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Only print warnings and errors, without progress bars. Progress bars are also
+    /// left out when stderr isn't a terminal
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// How progress is shown: bars, or JSON lines on stderr with the phase, input file,
+    /// rows processed and expected, open buckets and bytes written
+    #[arg(long, value_enum, default_value_t = ProgressFormat::Bars)]
+    progress: ProgressFormat,
+
+    /// Rows read from an input file between --progress json lines
+    #[arg(long, value_name = "N", default_value_t = 100_000)]
+    progress_rows: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Parquet, one row group per buffer flush
+    Parquet,
+    /// Zstandard-compressed JSON lines (.jsonl.zst), one object per row with the parquet
+    /// columns as fields and timestamps as RFC 3339 strings. The parquet options
+    /// (--compression, --no-dictionary) don't apply, and `compact` skips these files
+    Ndjson,
+    /// Arrow IPC files (.arrow), one record batch per buffer flush, compressed with
+    /// --compression lz4 (LZ4 frame), zstd or none. `compact` skips these files
+    Arrow,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Ndjson => "jsonl.zst",
+            OutputFormat::Arrow => "arrow",
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Download GH Archive hourly files for a timeframe into the input directory
+    Download(download::DownloadArgs),
+    /// Merge the part files of each bucket in the output directory into one file
+    Compact(compact::CompactArgs),
+    /// Check that the bucket files in the output directory are complete and match the run summaries
+    Verify(verify::VerifyArgs),
+    /// Print one repository's events for a month from its bucket, as JSON lines or a table
+    Query(query::QueryArgs),
+}
+
+/// Matches `text` against a glob pattern supporting `*` (any run of characters) and `?`
+/// (any single character). Matching is case-insensitive, like GitHub repository names.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen and the text position it was tried against
+    let mut backtrack: Option<(usize, usize)> = None;
+    
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // Let the last `*` swallow one more character and retry
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Reads a list from a file, one entry per line, ignoring blank lines and # comments.
+/// `kind` names the file in errors.
+fn read_list_file(path: &Path, kind: &str) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .context(format!("Failed to read {}: {}", kind, path.display()))?;
+    Ok(contents.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// One shard of a run split across machines, given on the command line as `i/N`
+#[derive(Debug, Clone, Copy, Serialize)]
+struct ShardId {
+    index: u64,
+    count: u64,
+}
+
+impl ShardId {
+    fn parse(value: &str) -> Result<Self, String> {
+        let (index, count) = value.split_once('/')
+            .ok_or_else(|| format!("expected i/N, got '{}'", value))?;
+        let index: u64 = index.parse().map_err(|_| format!("invalid shard index '{}'", index))?;
+        let count: u64 = count.parse().map_err(|_| format!("invalid shard count '{}'", count))?;
+        if index >= count {
+            return Err(format!("shard index must be below the shard count, got {}/{}", index, count));
+        }
+        Ok(Self { index, count })
+    }
+
+    /// Whether this shard is responsible for the input file or repository `key`.
+    fn owns(&self, key: &str) -> bool {
+        stable_hash(key) % self.count == self.index
+    }
+
+    /// Added to the names of the files this shard writes so shards never collide
+    fn tag(&self) -> String {
+        format!("shard-{}-of-{}", self.index, self.count)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum ShardBy {
+    File,
+    Repo,
+}
+
+/// FNV-1a. Shards on different machines must agree on every hash, which std's
+/// hasher doesn't promise across builds.
+fn stable_hash(key: &str) -> u64 {
+    key.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Inserts the shard tag before the extension of a manifest or summary file name.
+fn shard_file_name(file_name: &str, shard: Option<ShardId>) -> String {
+    match (shard, file_name.rsplit_once('.')) {
+        (Some(shard), Some((stem, extension))) => format!("{}.{}.{}", stem, shard.tag(), extension),
+        _ => file_name.to_string(),
+    }
+}
+
+/// Decides which input rows make it into the output.
+#[derive(Debug, Default)]
+struct RowFilter {
+    /// Only rows whose created_at formats to this value at this resolution are kept
+    timeframe: Option<(TimeframeResolution, String)>,
+    /// Event types to keep; empty means all types
+    include_event_types: HashSet<String>,
+    exclude_event_types: HashSet<String>,
+    /// Repository names or globs to keep; empty means all repositories
+    repos: Vec<String>,
+    /// Keep only the repositories owned by this shard
+    repo_shard: Option<ShardId>,
+    /// Fraction of rows kept by --sample
+    sample: Option<f64>,
+    /// Set by --no-bots: logins dropped besides the ones ending with [bot]
+    bot_logins: Option<HashSet<String>>,
+}
+
+impl RowFilter {
+    fn is_bot(&self, actor_login: &str) -> bool {
+        self.bot_logins.as_ref().is_some_and(|logins| actor_login.ends_with("[bot]") || logins.contains(actor_login))
+    }
+
+
+    fn allows_repo(&self, repo_name: &str) -> bool {
+        self.repos.is_empty() || self.repos.iter().any(|pattern| glob_match(pattern, repo_name))
+    }
+
+    fn sampled(&self, event_id: &str) -> bool {
+        // FNV alone leaves the high bits of short, sequential ids clustered, so the
+        // hash is mixed further (the SplitMix64 finalizer) before it is scaled
+        let mut hash = stable_hash(event_id);
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+        hash ^= hash >> 31;
+        self.sample.is_none_or(|fraction| (hash as f64 / u64::MAX as f64) < fraction)
+    }
+
+    fn allows_event_type(&self, event_type: &str) -> bool {
+        (self.include_event_types.is_empty() || self.include_event_types.contains(event_type))
+            && !self.exclude_event_types.contains(event_type)
+    }
+}
+
+/// Row counts gathered while processing input files.
+#[derive(Debug, Default)]
+struct ProcessStats {
+    /// Rows read from the input, including ones that were skipped
+    rows_read: u64,
+    rows_written: u64,
+    /// Rows written, keyed by event type
+    written_event_types: HashMap<String, u64>,
+    /// Rows dropped by the event type filter, keyed by event type
+    filtered_event_types: HashMap<String, u64>,
+    /// Rows dropped by the repository filter
+    filtered_repo_rows: u64,
+    /// Rows dropped by --no-bots, keyed by actor login
+    bot_rows: HashMap<String, u64>,
+    /// Rows of repositories that belong to another shard
+    other_shard_rows: u64,
+    /// Rows left out by --sample
+    sampled_out_rows: u64,
+    /// Reading stopped early because --limit rows were written
+    limit_reached: bool,
+    /// Rows dropped because their event id was already written
+    duplicates_dropped: u64,
+    /// Rows that could not be read or extracted and were skipped
+    bad_rows: u64,
+    /// Rows dropped because their created_at falls outside the requested timeframe
+    clipped_rows: u64,
+    /// Reading stopped early because the run was interrupted
+    interrupted: bool,
+    /// Rows written to the commit tables by --emit-commits
+    commits_written: u64,
+    /// Rows written to the pull request tables by --emit-pull-requests
+    pull_requests_written: u64,
+    /// Events written without their derived rows because the payload didn't parse
+    bad_payloads: u64,
+    /// Rows written per repository and event type, when --repo-counts is given
+    repo_counts: RepoCounts,
+}
+
+impl ProcessStats {
+    fn merge(&mut self, other: ProcessStats) {
+        self.rows_read += other.rows_read;
+        self.rows_written += other.rows_written;
+        for (event_type, count) in other.written_event_types {
+            *self.written_event_types.entry(event_type).or_insert(0) += count;
+        }
+        self.filtered_repo_rows += other.filtered_repo_rows;
+        for (actor_login, count) in other.bot_rows {
+            *self.bot_rows.entry(actor_login).or_insert(0) += count;
+        }
+        self.other_shard_rows += other.other_shard_rows;
+        self.sampled_out_rows += other.sampled_out_rows;
+        self.limit_reached |= other.limit_reached;
+        self.duplicates_dropped += other.duplicates_dropped;
+        self.bad_rows += other.bad_rows;
+        self.clipped_rows += other.clipped_rows;
+        self.interrupted |= other.interrupted;
+        self.commits_written += other.commits_written;
+        self.pull_requests_written += other.pull_requests_written;
+        self.bad_payloads += other.bad_payloads;
+        self.repo_counts.merge(other.repo_counts);
+        for (event_type, count) in other.filtered_event_types {
+            *self.filtered_event_types.entry(event_type).or_insert(0) += count;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum CompressionCodec {
+    None,
+    Snappy,
+    Gzip,
+    /// LZ4 in the raw block format, which current parquet readers expect over the
+    /// deprecated framed LZ4 codec
+    Lz4,
+    Zstd,
+}
+
+impl CompressionCodec {
+    /// Resolves the codec and optional level into parquet's compression setting,
+    /// rejecting levels the codec doesn't support.
+    fn to_compression(self, level: Option<i32>) -> Result<Compression> {
+        match (self, level) {
+            (CompressionCodec::None, None) => Ok(Compression::UNCOMPRESSED),
+            (CompressionCodec::Snappy, None) => Ok(Compression::SNAPPY),
+            (CompressionCodec::Lz4, None) => Ok(Compression::LZ4_RAW),
+            (CompressionCodec::None | CompressionCodec::Snappy | CompressionCodec::Lz4, Some(_)) => {
+                Err(anyhow::anyhow!("--compression-level is not supported for {:?} compression", self))
+            }
+            (CompressionCodec::Gzip, level) => {
+                let level = match level {
+                    Some(level) => GzipLevel::try_new(u32::try_from(level).unwrap_or(u32::MAX))
+                        .context("Invalid gzip compression level (expected 0-9)")?,
+                    None => GzipLevel::default(),
+                };
+                Ok(Compression::GZIP(level))
+            }
+            (CompressionCodec::Zstd, level) => {
+                let level = match level {
+                    Some(level) => ZstdLevel::try_new(level)
+                        .context("Invalid zstd compression level (expected 1-22)")?,
+                    None => ZstdLevel::default(),
+                };
+                Ok(Compression::ZSTD(level))
+            }
+        }
+    }
+
+    /// Resolves the codec into the Arrow IPC buffer compression, which only has LZ4
+    /// (framed) and zstd, both at a fixed level.
+    fn to_ipc_compression(self, level: Option<i32>) -> Result<Option<IpcCompression>> {
+        if level.is_some() {
+            return Err(anyhow::anyhow!("--compression-level is not supported for Arrow IPC output"));
+        }
+        match self {
+            CompressionCodec::None => Ok(None),
+            CompressionCodec::Lz4 => Ok(Some(IpcCompression::LZ4_FRAME)),
+            CompressionCodec::Zstd => Ok(Some(IpcCompression::ZSTD)),
+            CompressionCodec::Snappy | CompressionCodec::Gzip => {
+                Err(anyhow::anyhow!("Arrow IPC output supports none, lz4 or zstd compression, not {:?}", self))
+            }
+        }
+    }
+}
+
+/// Controls how rows are buffered and how the output files are encoded.
+#[derive(Debug, Clone)]
+struct WriterConfig {
+    buffer_rows: usize,
+    buffer_bytes: Option<usize>,
+    dictionary: bool,
+    compression: Compression,
+    /// Buffer compression of Arrow IPC files, used instead of `compression` there
+    ipc_compression: Option<IpcCompression>,
+    /// Open writer cap shared by all pools of a table
+    max_open_writers: usize,
+    /// Buffered bytes cap shared by all pools of a table
+    max_buffer_bytes: Option<usize>,
+    /// Use key=value directory names, with one file per input inside each month directory
+    hive_partitions: bool,
+    /// Truncate existing output files instead of writing a new part next to them
+    overwrite: bool,
+    /// Root directory bucket files are written under. For remote output this is a local
+    /// staging directory
+    output_dir: String,
+    output_format: OutputFormat,
+    /// Payload fields copied into extra events columns by --extract
+    extractions: Extractions,
+    /// Add the repo_name_original column after the extracted ones, for --rename-map
+    original_repo_names: bool,
+    /// Compress closed files into .parquet.zst files, for --wrap-zstd
+    wrap_zstd: bool,
+    /// Where closed files are uploaded to, when --output-dir is a URL
+    remote_output: Option<Arc<RemoteDir>>,
+}
+
+impl WriterConfig {
+    fn from_args(args: &Args) -> Result<Self> {
+        if args.buffer_rows == 0 {
+            return Err(anyhow::anyhow!("--buffer-rows must be greater than zero"));
+        }
+        if args.buffer_bytes == Some(0) {
+            return Err(anyhow::anyhow!("--buffer-bytes must be greater than zero"));
+        }
+        if args.max_open_writers == 0 {
+            return Err(anyhow::anyhow!("--max-open-writers must be greater than zero"));
+        }
+        if args.rename_map.is_some() && args.extractions.iter().any(|rule| rule.column() == ORIGINAL_REPO_NAME_COLUMN) {
+            return Err(anyhow::anyhow!("--extract can't name a column {} with --rename-map, which adds it", ORIGINAL_REPO_NAME_COLUMN));
+        }
+        if args.wrap_zstd && args.output_format != OutputFormat::Parquet {
+            return Err(anyhow::anyhow!("--wrap-zstd only applies to --output-format parquet"));
+        }
+        let remote_output = RemoteDir::parse(&args.output_dir)?.map(Arc::new);
+        Ok(Self {
+            buffer_rows: args.buffer_rows,
+            buffer_bytes: args.buffer_bytes.map(|bytes| bytes as usize),
+            dictionary: !args.no_dictionary,
+            compression: args.compression.to_compression(args.compression_level)?,
+            ipc_compression: match args.output_format {
+                OutputFormat::Arrow => args.compression.to_ipc_compression(args.compression_level)?,
+                _ => None,
+            },
+            max_open_writers: args.max_open_writers,
+            max_buffer_bytes: args.max_buffer_mb.map(|mb| mb * 1024 * 1024),
+            hive_partitions: args.hive_partitions,
+            overwrite: args.overwrite,
+            output_dir: match &remote_output {
+                Some(_) => staging_dir("output").to_string_lossy().into_owned(),
+                None => args.output_dir.to_string_lossy().trim_end_matches('/').to_string(),
+            },
+            output_format: args.output_format,
+            extractions: Extractions::new(args.extractions.clone())?,
+            original_repo_names: args.rename_map.is_some(),
+            wrap_zstd: args.wrap_zstd,
+            remote_output,
+        })
+    }
+
+    /// Names of the events columns written after the fixed ones
+    fn extra_columns(&self) -> Vec<String> {
+        let mut columns = self.extractions.columns().to_vec();
+        if self.original_repo_names {
+            columns.push(ORIGINAL_REPO_NAME_COLUMN.to_string());
+        }
+        columns
+    }
+
+    /// The remote path of a file under the staging directory
+    fn remote_relative_path<'a>(&self, path: &'a str) -> Result<&'a str> {
+        path.strip_prefix(&self.output_dir)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .ok_or_else(|| anyhow::anyhow!("{} is not in the output directory {}", path, self.output_dir))
+    }
+
+    /// Whether an output file exists, in the remote directory when there is one. With
+    /// --wrap-zstd the file may only exist in its compressed form
+    fn output_exists(&self, path: &str) -> Result<bool> {
+        let exists = |path: &str| match &self.remote_output {
+            Some(remote) => remote.exists(self.remote_relative_path(path)?),
+            None => Ok(Path::new(path).exists()),
+        };
+        Ok(exists(path)? || (self.wrap_zstd && exists(&format!("{}.zst", path))?))
+    }
+
+    fn writer_properties(&self) -> WriterProperties {
+        // Each buffer flush becomes exactly one row group, so the row group size
+        // is bounded by the buffer size.
+        WriterProperties::builder()
+            .set_compression(self.compression)
+            .set_max_row_group_size(self.buffer_rows)
+            .set_dictionary_enabled(self.dictionary)
+            .build()
+    }
+
+    fn should_flush(&self, buffer: &Buffered) -> bool {
+        buffer.len() >= self.buffer_rows
+            || self.buffer_bytes.is_some_and(|limit| buffer.bytes() >= limit)
+    }
+}
+
+/// Set by the Ctrl-C handler; input files stop reading rows once it is set
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Exit code of a run that was interrupted, following the 128 + SIGINT convention
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+const INPUT_DIR: &str = "work/archives-bq";
+const OUTPUT_DIR: &str = "work/archives-separated";
+/// Written to the output directory
+const MANIFEST_FILE: &str = ".manifest.json";
+const SUMMARY_FILE: &str = "_summary.json";
+/// Held in the output directory while a run writes to it, naming the process
+const LOCK_FILE: &str = ".lock";
+/// Bot logins listed in the summary of a --no-bots run
+const TOP_BOT_LOGINS: usize = 10;
+
+/// Records run progress so an interrupted run can be resumed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    /// Input files whose rows have all been written and whose buckets were closed
+    completed_inputs: Vec<String>,
+    /// Output files that were fully written and closed
+    finalized_buckets: Vec<String>,
+    /// Size and modification time of each completed input when it was processed
+    #[serde(default)]
+    input_fingerprints: BTreeMap<String, InputFingerprint>,
+    /// The part name of the checkpoint each input was written in, recorded before any of
+    /// the checkpoint's files are opened
+    #[serde(default)]
+    input_parts: BTreeMap<String, String>,
+}
+
+/// Identifies one version of an input file, to notice when it is re-exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct InputFingerprint {
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+}
+
+impl InputFingerprint {
+    fn of(file_path: &str, remote: Option<&RemoteDir>) -> Result<Self> {
+        if let Some(remote) = remote {
+            let (size, modified) = remote.metadata(remote.relative_path(file_path)?)?
+                .ok_or_else(|| anyhow::anyhow!("{} no longer exists", file_path))?;
+            return Ok(Self {
+                size,
+                modified_secs: u64::try_from(modified.timestamp()).unwrap_or_default(),
+                modified_nanos: modified.timestamp_subsec_nanos(),
+            });
+        }
+        let metadata = std::fs::metadata(file_path)
+            .context(format!("Failed to read metadata of {}", file_path))?;
+        let modified = metadata.modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(Self { size: metadata.len(), modified_secs: modified.as_secs(), modified_nanos: modified.subsec_nanos() })
+    }
+}
+
+impl Manifest {
+    /// The part name of the files an input's rows were written to. Inputs of manifests
+    /// written before checkpoints had files of their own.
+    fn part_of(&self, file_path: &str, shard: Option<ShardId>) -> String {
+        self.input_parts.get(file_path).cloned().unwrap_or_else(|| output_part_name(file_path, shard))
+    }
+    
+    /// Reads the manifest, fetching it first when the output directory is remote.
+    fn load(manifest_path: &Path, remote: Option<&RemoteDir>) -> Result<Self> {
+        if let Some(remote) = remote {
+            let file_name = manifest_path.file_name().unwrap().to_string_lossy();
+            if !remote.exists(&file_name)? {
+                return Ok(Self::default());
+            }
+            if let Some(staging_dir) = manifest_path.parent() {
+                create_dir_all(staging_dir)?;
+            }
+            remote.download(&file_name, manifest_path)?;
+        }
+        if !manifest_path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(manifest_path)
+            .context(format!("Failed to read manifest: {}", manifest_path.display()))?;
+        serde_json::from_str(&contents)
+            .context(format!("Failed to parse manifest: {}", manifest_path.display()))
+    }
+
+    /// Writes the manifest to a temporary file and renames it into place, so a crash
+    /// can never leave a partially written manifest behind. A remote copy is replaced
+    /// in one upload.
+    fn save(&self, manifest_path: &Path, remote: Option<&RemoteDir>) -> Result<()> {
+        let tmp_path = manifest_path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(self)?)
+            .context(format!("Failed to write manifest: {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, manifest_path)
+            .context(format!("Failed to replace manifest: {}", manifest_path.display()))?;
+        if let Some(remote) = remote {
+            remote.upload(manifest_path, &manifest_path.file_name().unwrap().to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the part name of the checkpoint an output file was written in, given the file
+/// name and the part names being looked for. Output files are named after the part name
+/// of the checkpoint's first input, optionally followed by numbered part suffixes, and
+/// outside hive layouts are prefixed with the month. Derived tables add their prefix before the part name.
+fn part_name_of_output<'a>(file_name: &str, parts: &'a HashSet<String>, hive: bool) -> Option<&'a String> {
+    // Files compressed by --wrap-zstd keep their parquet name inside
+    let file_name = file_name.strip_suffix(".zst").filter(|name| name.ends_with(".parquet")).unwrap_or(file_name);
+    let stem = OutputFormat::value_variants().iter()
+        .find_map(|format| file_name.strip_suffix(format.extension())?.strip_suffix('.'))?;
+    let stem = if hive { stem } else { stem.split_once('.')?.1 };
+    let mut stem = stem.strip_prefix(OutputTable::of_file_name(stem).part_prefix()).unwrap_or(stem);
+    // Evicted buckets add one numbered suffix and already existing files another
+    for _ in 0..=2 {
+        if let Some(part) = parts.get(stem) {
+            return Some(part);
+        }
+        match stem.rsplit_once('.') {
+            Some((rest, number)) if !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) => stem = rest,
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Deletes the output files of the checkpoints (named by `parts`) whose inputs are about
+/// to be processed again, whether they were closed before an interrupt or cut off
+/// mid-write by a crash, so the rerun neither duplicates their rows nor leaves
+/// unreadable files behind. Returns the number of files removed.
+fn remove_incomplete_outputs(output_dir: &Path, parts: &HashSet<String>, manifest: &mut Manifest, hive: bool, remote: Option<&RemoteDir>) -> Result<usize> {
+    let mut removed = HashSet::new();
+    if let Some(remote) = remote {
+        for relative in remote.list_all()? {
+            let file_name = relative.rsplit('/').next().unwrap_or(&relative);
+            if part_name_of_output(file_name, parts, hive).is_some() {
+                remote.delete(&relative)?;
+                removed.insert(format!("{}/{}", remote.url(), relative));
+            }
+        }
+    }
+    let mut dirs = vec![output_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).context(format!("Failed to list {}", dir.display())),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if part_name_of_output(&path.file_name().unwrap().to_string_lossy(), parts, hive).is_some() {
+                std::fs::remove_file(&path)
+                    .context(format!("Failed to remove incomplete output {}", path.display()))?;
+                removed.insert(path.to_string_lossy().into_owned());
+            }
+        }
+    }
+    manifest.finalized_buckets.retain(|path| !removed.contains(path));
+    Ok(removed.len())
+}
+
+/// How precisely a requested timeframe narrows the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimeframeResolution {
+    Year,
+    Month,
+    Day,
 }
 
 fn extract_month_from_created_at(created_at_millis: i64) -> Result<String> {
-    // Simple conversion - just extract year-month from timestamp
-    let dt = std::time::UNIX_EPOCH + std::time::Duration::from_millis(created_at_millis as u64);
-    let datetime = chrono::DateTime::<chrono::Utc>::from(dt);
-    Ok(format!("{:04}-{:02}", datetime.year(), datetime.month()))
+    format_created_at(created_at_millis, TimeframeResolution::Month)
 }
 
-fn get_bucket_key(repo_name: &str, month: &str) -> String {
-    let repo_prefix = if repo_name.len() >= 3 {
-        &repo_name[..3]
-    } else {
-        repo_name
-    };
+/// Formats a timestamp as YYYY, YYYY-MM, or YYYY-MM-DD depending on the resolution.
+/// Timestamps before the epoch are an error; rows with one are skipped as bad rows
+/// before they are routed.
+fn format_created_at(created_at_millis: i64, resolution: TimeframeResolution) -> Result<String> {
+    let datetime = DateTime::from_timestamp_millis(created_at_millis)
+        .filter(|_| created_at_millis >= 0)
+        .ok_or_else(|| anyhow::anyhow!("created_at {} is before 1970 or out of range", created_at_millis))?;
+    Ok(match resolution {
+        TimeframeResolution::Year => format!("{:04}", datetime.year()),
+        TimeframeResolution::Month => format!("{:04}-{:02}", datetime.year(), datetime.month()),
+        TimeframeResolution::Day => format!("{:04}-{:02}-{:02}", datetime.year(), datetime.month(), datetime.day()),
+    })
+}
+
+/// Number of leading repo name characters used as nested bucket directories
+const BUCKET_PREFIX_CHARS: usize = 3;
+
+/// Directory used for events that carry no repo name at all
+const EMPTY_REPO_BUCKET: &str = "_";
+
+/// Format of the input files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+    /// Decide per file from its extension
+    Auto,
+    /// BigQuery exports: .parquet or .parquet.zst
+    Parquet,
+    /// GH Archive hourly files of newline-delimited events: .json.gz or .json
+    Json,
+}
+
+impl InputFormat {
+    fn for_file_name(file_name: &str) -> Option<InputFormat> {
+        if file_name.ends_with(".parquet.zst") || file_name.ends_with(".parquet") {
+            Some(InputFormat::Parquet)
+        } else if file_name.ends_with(".json.gz") || file_name.ends_with(".json") {
+            Some(InputFormat::Json)
+        } else {
+            None
+        }
+    }
+    
+    /// Whether a file in the input directory should be processed
+    fn accepts(self, file_name: &str) -> bool {
+        match InputFormat::for_file_name(file_name) {
+            Some(format) => self == InputFormat::Auto || self == format,
+            None => false,
+        }
+    }
     
-    let safe_repo_prefix = repo_prefix.replace('/', "_");
+    /// The format to read a file with; an explicit choice wins over the extension
+    fn resolve(self, file_path: &str) -> InputFormat {
+        match self {
+            InputFormat::Auto => InputFormat::for_file_name(file_path).unwrap_or(InputFormat::Parquet),
+            format => format,
+        }
+    }
+}
+
+/// How rows are grouped into output directories
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PartitionStrategy {
+    /// One directory level per character of the first three characters of the repo name
+    Prefix3,
+    /// One directory per repository owner
+    Owner,
+    /// One owner/name directory per repository
+    Repo,
+}
+
+/// Builds the bucket key for a row. The last component names the output file (or, with
+/// `hive`, the directory holding it); passing an event type adds one more level below the month.
+fn get_bucket_key(repo_name: &str, month: &str, event_type: Option<&str>, strategy: PartitionStrategy, hive: bool) -> String {
+    let mut path_parts: Vec<String> = match strategy {
+        // Take whole characters rather than bytes so names starting with multibyte
+        // characters (e.g. CJK org names) don't split a code point. Shorter names
+        // simply produce fewer directory levels.
+        PartitionStrategy::Prefix3 => {
+            let chars = repo_name
+                .chars()
+                .take(BUCKET_PREFIX_CHARS)
+                .map(sanitize_path_char);
+            if hive {
+                // A partition value has to be a single directory
+                let prefix: String = chars.collect();
+                if prefix.is_empty() { vec![] } else { vec![format!("prefix={}", prefix)] }
+            } else {
+                chars.collect()
+            }
+        }
+        // GitHub names are case-insensitive, so they are lowercased to keep one
+        // directory per repository on case-insensitive filesystems as well
+        PartitionStrategy::Owner | PartitionStrategy::Repo => {
+            let repo_name = repo_name.to_lowercase();
+            let (owner, name) = repo_name.split_once('/').unwrap_or((&repo_name, ""));
+            let (owner, name) = (sanitize_path_component(owner), sanitize_path_component(name));
+            match (strategy, hive) {
+                (PartitionStrategy::Owner, false) => vec![owner],
+                (PartitionStrategy::Owner, true) => vec![format!("owner={}", owner)],
+                (_, false) => vec![owner, name],
+                (_, true) => vec![format!("owner={}", owner), format!("repo={}", name)],
+            }
+        }
+    };
     
-    let mut path_parts = Vec::new();
-    for ch in safe_repo_prefix.chars() {
-        path_parts.push(ch.to_string());
+    if path_parts.is_empty() {
+        path_parts.push(if hive {
+            format!("prefix={}", EMPTY_REPO_BUCKET)
+        } else {
+            EMPTY_REPO_BUCKET.to_string()
+        });
     }
     
-    path_parts.push(month.to_string());
+    path_parts.push(if hive { format!("month={}", month) } else { month.to_string() });
+    if let Some(event_type) = event_type {
+        let event_type = sanitize_path_component(event_type);
+        path_parts.push(if hive { format!("event_type={}", event_type) } else { event_type });
+    }
     path_parts.join("/")
 }
 
+/// Device names Windows reserves in every directory, whatever the case
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "con", "prn", "aux", "nul",
+    "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9",
+    "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// Sanitizes a whole owner or repository name for use as a single directory name.
+/// Names Windows reserves (`con`, `nul`, ...) get their last character percent-encoded,
+/// which no other name maps to since `%` itself is always encoded.
+fn sanitize_path_component(component: &str) -> String {
+    if component.is_empty() {
+        return EMPTY_REPO_BUCKET.to_string();
+    }
+    let sanitized: String = component.chars().map(sanitize_path_char).collect();
+    if WINDOWS_RESERVED_NAMES.contains(&sanitized.to_lowercase().as_str()) {
+        let (stem, last) = sanitized.split_at(sanitized.len() - 1);
+        return format!("{}%{:02X}", stem, last.as_bytes()[0]);
+    }
+    sanitized
+}
+
+/// Maps one character of a repo name to a directory name that is safe on every
+/// platform. ASCII alphanumerics, `-` and `_` are kept; anything else (`.`, `/`,
+/// `\`, `:`, `%`, non-ASCII, ...) is percent-encoded byte by byte. Since `%` is
+/// itself encoded, distinct characters never map to the same directory.
+fn sanitize_path_char(ch: char) -> String {
+    if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' {
+        return ch.to_string();
+    }
+    
+    let mut buf = [0u8; 4];
+    ch.encode_utf8(&mut buf)
+        .bytes()
+        .map(|byte| format!("%{:02X}", byte))
+        .collect()
+}
+
+fn timeframe_resolution(timeframe: &str) -> Result<TimeframeResolution> {
+    match timeframe.split('-').count() {
+        1 => Ok(TimeframeResolution::Year),
+        2 => Ok(TimeframeResolution::Month),
+        3 => Ok(TimeframeResolution::Day),
+        _ => Err(anyhow::anyhow!("Invalid timeframe format. Use YYYY, YYYY-MM, or YYYY-MM-DD")),
+    }
+}
+
 fn parse_timeframe(timeframe: &str) -> Result<Vec<String>> {
     let parts: Vec<&str> = timeframe.split('-').collect();
     
-    match parts.len() {
-        1 => Ok(vec![parts[0].to_string()]),
-        2 => Ok(vec![format!("{}-{}", parts[0], parts[1])]),
-        3 => Ok(vec![format!("{}-{}", parts[0], parts[1])]),
-        _ => Err(anyhow::anyhow!("Invalid timeframe format. Use YYYY, YYYY-MM, or YYYY-MM-DD")),
+    match timeframe_resolution(timeframe)? {
+        TimeframeResolution::Year => Ok(vec![parts[0].to_string()]),
+        TimeframeResolution::Month => Ok(vec![format!("{}-{}", parts[0], parts[1])]),
+        TimeframeResolution::Day => Ok(vec![format!("{}-{}-{}", parts[0], parts[1], parts[2])]),
     }
 }
 
-fn find_parquet_files(timeframe_patterns: &[String]) -> Result<Vec<String>> {
+/// Collects the inputs given by --files and --file-list. Every file must exist and have
+/// an extension the input format reads, since a typo in a curated list should fail the
+/// run rather than quietly leave a file out.
+fn list_input_files(globs: &[String], file_list: Option<&Path>, input_format: InputFormat) -> Result<Vec<String>> {
     let mut files = Vec::new();
+    for pattern in globs {
+        let matches = glob::glob(pattern)
+            .context(format!("Invalid --files glob: {}", pattern))?;
+        let before = files.len();
+        for path in matches {
+            let path = path.context(format!("Failed to read a match of {}", pattern))?;
+            if path.is_file() {
+                files.push(path.to_string_lossy().into_owned());
+            }
+        }
+        if files.len() == before {
+            return Err(anyhow::anyhow!("No files match --files {}", pattern));
+        }
+    }
+    if let Some(file_list) = file_list {
+        files.extend(read_list_file(file_list, "file list")?);
+    }
+    
+    files.sort();
+    files.dedup();
+    let mut names = HashMap::new();
+    for file_path in &files {
+        let path = Path::new(file_path);
+        if !path.is_file() {
+            return Err(anyhow::anyhow!("Input file {} does not exist", file_path));
+        }
+        let file_name = path.file_name().unwrap().to_string_lossy();
+        if !input_format.accepts(&file_name) {
+            return Err(anyhow::anyhow!("Input file {} does not end in an extension of the input format (.parquet, .parquet.zst, .json, .json.gz)", file_path));
+        }
+        // Output parts are named after the input's file name
+        if let Some(other) = names.insert(file_name.into_owned(), file_path) {
+            return Err(anyhow::anyhow!("Input files {} and {} have the same name and would write the same output files", other, file_path));
+        }
+    }
+    Ok(files)
+}
+
+fn find_parquet_files(dir_path: &Path, timeframe_patterns: &[String], input_format: InputFormat, remote: Option<&RemoteDir>) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    
+    if let Some(remote) = remote {
+        let file_names = remote.list_files()?;
+        for pattern in timeframe_patterns {
+            files.extend(file_names.iter()
+                .filter(|file_name| file_name.starts_with(pattern) && input_format.accepts(file_name))
+                .map(|file_name| format!("{}/{}", remote.url(), file_name)));
+        }
+        files.sort();
+        return Ok(files);
+    }
     
     for pattern in timeframe_patterns {
-        let dir_path = Path::new("work/archives-bq");
-        if !dir_path.exists() {
-            return Err(anyhow::anyhow!("Directory work/archives-bq does not exist"));
+        if !dir_path.is_dir() {
+            return Err(anyhow::anyhow!("Input directory {} does not exist", dir_path.display()));
         }
         
         for entry in std::fs::read_dir(dir_path)? {
@@ -78,7 +1200,7 @@ fn find_parquet_files(timeframe_patterns: &[String]) -> Result<Vec<String>> {
             let file_name = entry.file_name();
             let file_name_str = file_name.to_string_lossy();
             
-            if file_name_str.starts_with(pattern) && file_name_str.ends_with(".parquet.zst") {
+            if file_name_str.starts_with(pattern) && input_format.accepts(&file_name_str) {
                 files.push(entry.path().to_string_lossy().to_string());
             }
         }
@@ -88,29 +1210,77 @@ fn find_parquet_files(timeframe_patterns: &[String]) -> Result<Vec<String>> {
     Ok(files)
 }
 
+/// The fields kept from one input row
+#[derive(Debug)]
+struct EventRow {
+    /// GitHub event id
+    id: String,
+    event_type: String,
+    repo_name: String,
+    payload: String,
+    /// Milliseconds since the Unix epoch
+    created_at: i64,
+    /// Empty when the input row has no actor
+    actor_login: String,
+    /// Zero when the input row has no actor
+    actor_id: i64,
+    /// Values of the --extract columns, in column order; empty until the row is routed
+    extracted: Vec<Option<String>>,
+}
+
+impl EventRow {
+    /// Approximate size of the row's data in bytes, before encoding and compression
+    fn data_bytes(&self) -> usize {
+        self.id.len() + self.event_type.len() + self.payload.len() + self.repo_name.len()
+            + self.actor_login.len() + 2 * std::mem::size_of::<i64>()
+            + self.extracted.iter().map(|value| value.as_ref().map_or(0, String::len)).sum::<usize>()
+    }
+}
+
 #[derive(Debug)]
 struct RowBuffer {
+    ids: Vec<String>,
     event_types: Vec<String>,
     payloads: Vec<String>,
     repo_names: Vec<String>,
     created_ats: Vec<i64>,
+    actor_logins: Vec<String>,
+    actor_ids: Vec<i64>,
+    /// The --extract columns and their values
+    extracted: Vec<(String, Vec<Option<String>>)>,
+    /// Approximate size of the buffered data in bytes
+    bytes: usize,
 }
 
 impl RowBuffer {
-    fn new() -> Self {
+    fn new(extra_columns: &[String]) -> Self {
         Self {
+            ids: Vec::new(),
             event_types: Vec::new(),
             payloads: Vec::new(),
             repo_names: Vec::new(),
             created_ats: Vec::new(),
+            actor_logins: Vec::new(),
+            actor_ids: Vec::new(),
+            extracted: extra_columns.iter().map(|column| (column.clone(), Vec::new())).collect(),
+            bytes: 0,
         }
     }
     
-    fn add_row(&mut self, event_type: String, payload: String, repo_name: String, created_at: i64) {
-        self.event_types.push(event_type);
-        self.payloads.push(payload);
-        self.repo_names.push(repo_name);
-        self.created_ats.push(created_at);
+    fn add_row(&mut self, row: EventRow) {
+        self.bytes += row.data_bytes();
+        self.ids.push(row.id);
+        self.event_types.push(row.event_type);
+        self.payloads.push(row.payload);
+        self.repo_names.push(row.repo_name);
+        self.created_ats.push(row.created_at);
+        self.actor_logins.push(row.actor_login);
+        self.actor_ids.push(row.actor_id);
+        // Rows that were never routed, like compacted rows without the columns, get nulls
+        let mut values = row.extracted.into_iter();
+        for (_, column) in &mut self.extracted {
+            column.push(values.next().flatten());
+        }
     }
     
     fn len(&self) -> usize {
@@ -118,130 +1288,1540 @@ impl RowBuffer {
     }
     
     fn clear(&mut self) {
+        self.ids.clear();
         self.event_types.clear();
         self.payloads.clear();
         self.repo_names.clear();
         self.created_ats.clear();
+        self.actor_logins.clear();
+        self.actor_ids.clear();
+        for (_, column) in &mut self.extracted {
+            column.clear();
+        }
+        self.bytes = 0;
     }
 }
 
-type ParquetWriters = Arc<Mutex<HashMap<String, (SerializedFileWriter<File>, RowBuffer)>>>;
+/// The tables a run writes. Each has its own schema and files; the tables derived from
+/// payloads sit next to the event files of their bucket, with the table's prefix
+/// (`commits.`, `pull_requests.`) before the part name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum OutputTable {
+    #[default]
+    Events,
+    Commits,
+    PullRequests,
+}
 
-fn get_or_create_parquet_writer(writers: &ParquetWriters, bucket_key: &str) -> Result<()> {
-    let mut writers_map = writers.lock().unwrap();
-    
-    if !writers_map.contains_key(bucket_key) {
-        let parts: Vec<&str> = bucket_key.split('/').collect();
-        if parts.len() < 2 {
-            return Err(anyhow::anyhow!("Invalid bucket key format: '{}'", bucket_key));
+impl OutputTable {
+    /// The tables derived from event payloads
+    const DERIVED: [OutputTable; 2] = [OutputTable::Commits, OutputTable::PullRequests];
+
+    /// The table's schema. The events table ends with the --extract columns
+    fn schema(self, extra_columns: &[String]) -> String {
+        match self {
+            OutputTable::Events => output_schema(extra_columns),
+            OutputTable::Commits => COMMITS_SCHEMA.to_string(),
+            OutputTable::PullRequests => PULL_REQUESTS_SCHEMA.to_string(),
         }
+    }
+
+    fn new_buffer(self, extra_columns: &[String]) -> Buffered {
+        match self {
+            OutputTable::Events => Buffered::Events(RowBuffer::new(extra_columns)),
+            OutputTable::Commits => Buffered::Commits(CommitBuffer::default()),
+            OutputTable::PullRequests => Buffered::PullRequests(PullRequestBuffer::default()),
+        }
+    }
+
+    /// Added before the input's part name in the table's file names
+    fn part_prefix(self) -> &'static str {
+        match self {
+            OutputTable::Events => "",
+            OutputTable::Commits => "commits.",
+            OutputTable::PullRequests => "pull_requests.",
+        }
+    }
+
+    /// The table of an output file, given its name after the month prefix (or its whole
+    /// name in hive layouts)
+    fn of_file_name(name: &str) -> OutputTable {
+        OutputTable::DERIVED.into_iter()
+            .find(|table| name.starts_with(table.part_prefix()))
+            .unwrap_or(OutputTable::Events)
+    }
+}
+
+/// A row of one of the output tables
+enum OutputRow {
+    Event(EventRow),
+    Commit(CommitRow),
+    PullRequest(PullRequestRow),
+}
+
+impl OutputRow {
+    fn data_bytes(&self) -> usize {
+        match self {
+            OutputRow::Event(event) => event.data_bytes(),
+            OutputRow::Commit(commit) => commit.data_bytes(),
+            OutputRow::PullRequest(pull_request) => pull_request.data_bytes(),
+        }
+    }
+
+    /// When the row's event happened
+    fn created_at(&self) -> i64 {
+        match self {
+            OutputRow::Event(event) => event.created_at,
+            OutputRow::Commit(commit) => commit.created_at,
+            OutputRow::PullRequest(pull_request) => pull_request.event_created_at,
+        }
+    }
+}
+
+/// The rows waiting to be written to one output file
+enum Buffered {
+    Events(RowBuffer),
+    Commits(CommitBuffer),
+    PullRequests(PullRequestBuffer),
+}
+
+impl Buffered {
+    fn add_row(&mut self, row: OutputRow) {
+        match (self, row) {
+            (Buffered::Events(buffer), OutputRow::Event(event)) => buffer.add_row(event),
+            (Buffered::Commits(buffer), OutputRow::Commit(commit)) => buffer.add_row(commit),
+            (Buffered::PullRequests(buffer), OutputRow::PullRequest(pull_request)) => buffer.add_row(pull_request),
+            _ => unreachable!("row added to the buffer of another table"),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Buffered::Events(buffer) => buffer.len(),
+            Buffered::Commits(buffer) => buffer.len(),
+            Buffered::PullRequests(buffer) => buffer.len(),
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        match self {
+            Buffered::Events(buffer) => buffer.bytes,
+            Buffered::Commits(buffer) => buffer.bytes,
+            Buffered::PullRequests(buffer) => buffer.bytes,
+        }
+    }
+
+    fn created_ats(&self) -> &[i64] {
+        match self {
+            Buffered::Events(buffer) => &buffer.created_ats,
+            Buffered::Commits(buffer) => &buffer.created_ats,
+            Buffered::PullRequests(buffer) => &buffer.created_ats,
+        }
+    }
+
+    /// Writes the buffered rows as one row group and empties the buffer.
+    fn flush_to(&mut self, writer: &mut SerializedFileWriter<File>) -> Result<()> {
+        match self {
+            Buffered::Events(buffer) => flush_buffer_to_parquet(writer, buffer),
+            Buffered::Commits(buffer) => flush_commits_to_parquet(writer, buffer),
+            Buffered::PullRequests(buffer) => flush_pull_requests_to_parquet(writer, buffer),
+        }
+    }
+
+    /// Moves the buffered rows into a record batch with the given schema, emptying the buffer.
+    fn take_record_batch(&mut self, schema: SchemaRef) -> Result<RecordBatch> {
+        match self {
+            Buffered::Events(buffer) => take_buffer_record_batch(schema, buffer),
+            Buffered::Commits(buffer) => commits::take_commits_record_batch(schema, buffer),
+            Buffered::PullRequests(buffer) => pull_requests::take_pull_requests_record_batch(schema, buffer),
+        }
+    }
+
+    /// Writes the buffered rows as one JSON object per line and empties the buffer.
+    fn write_json_lines(&mut self, out: &mut dyn Write) -> Result<()> {
+        match self {
+            Buffered::Events(buffer) => write_buffer_to_json_lines(out, buffer),
+            Buffered::Commits(buffer) => commits::write_commits_to_json_lines(out, buffer),
+            Buffered::PullRequests(buffer) => pull_requests::write_pull_requests_to_json_lines(out, buffer),
+        }
+    }
+}
+
+/// An open bucket file in one of the output formats
+trait TableWriter: Send {
+    /// Writes out the buffered rows and empties the buffer.
+    fn write_rows(&mut self, buffer: &mut Buffered) -> Result<()>;
+    
+    /// Finishes the file, which is complete on disk once this returns.
+    fn close(self: Box<Self>) -> Result<()>;
+    
+    /// Bytes of the file written so far, not counting data the writer still holds.
+    fn bytes_written(&self) -> u64;
+}
+
+/// Size of a file being written, for writers that don't count their own bytes
+fn file_len(file: &File) -> u64 {
+    file.metadata().map_or(0, |metadata| metadata.len())
+}
+
+impl TableWriter for SerializedFileWriter<File> {
+    fn write_rows(&mut self, buffer: &mut Buffered) -> Result<()> {
+        buffer.flush_to(self)
+    }
+    
+    fn close(self: Box<Self>) -> Result<()> {
+        (*self).close()?;
+        Ok(())
+    }
+    
+    fn bytes_written(&self) -> u64 {
+        SerializedFileWriter::bytes_written(self) as u64
+    }
+}
+
+/// Writes rows as zstd-compressed JSON lines
+struct JsonLinesWriter {
+    encoder: zstd::Encoder<'static, BufWriter<File>>,
+}
+
+impl TableWriter for JsonLinesWriter {
+    fn write_rows(&mut self, buffer: &mut Buffered) -> Result<()> {
+        buffer.write_json_lines(&mut self.encoder)
+    }
+    
+    fn close(self: Box<Self>) -> Result<()> {
+        // finish() writes the end of the zstd frame; dropping the encoder would leave it truncated
+        self.encoder.finish()?.flush()?;
+        Ok(())
+    }
+    
+    fn bytes_written(&self) -> u64 {
+        file_len(self.encoder.get_ref().get_ref())
+    }
+}
+
+/// Writes rows as an Arrow IPC file, one record batch per flush
+struct ArrowFileWriter {
+    writer: FileWriter<BufWriter<File>>,
+    schema: SchemaRef,
+}
+
+impl ArrowFileWriter {
+    fn new(file: File, schema: &str, compression: Option<IpcCompression>) -> Result<Self> {
+        // Derived from the parquet schema so both formats have the same columns and types,
+        // e.g. created_at as a millisecond UTC timestamp
+        let descriptor = SchemaDescriptor::new(Arc::new(parse_message_type(schema)?));
+        let schema = Arc::new(parquet_to_arrow_schema(&descriptor, None)?);
+        let options = IpcWriteOptions::default().try_with_compression(compression)?;
+        let writer = FileWriter::try_new_with_options(BufWriter::new(file), &schema, options)?;
+        Ok(Self { writer, schema })
+    }
+}
+
+impl TableWriter for ArrowFileWriter {
+    fn write_rows(&mut self, buffer: &mut Buffered) -> Result<()> {
+        if buffer.len() == 0 {
+            return Ok(());
+        }
+        let batch = buffer.take_record_batch(self.schema.clone())?;
+        self.writer.write(&batch)?;
+        Ok(())
+    }
+    
+    fn close(mut self: Box<Self>) -> Result<()> {
+        // finish() writes the footer readers locate the record batches through
+        self.writer.finish()?;
+        self.writer.into_inner()?.flush()?;
+        Ok(())
+    }
+    
+    fn bytes_written(&self) -> u64 {
+        file_len(self.writer.get_ref().get_ref())
+    }
+}
+
+/// Timestamps in JSON output, in the format GH Archive uses
+fn timestamp_json(millis: i64) -> Value {
+    DateTime::from_timestamp_millis(millis)
+        .map_or(Value::Null, |timestamp| Value::String(timestamp.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)))
+}
+
+struct OpenWriter {
+    writer: Box<dyn TableWriter>,
+    buffer: Buffered,
+    summary: OutputFileSummary,
+    last_used: u64,
+}
+
+impl OpenWriter {
+    /// Writes the buffered rows out (as a row group for parquet), counting them in the file summary.
+    fn flush(&mut self) -> Result<()> {
+        self.summary.record(&self.buffer);
+        timing::time(Phase::Write, || self.writer.write_rows(&mut self.buffer))
+    }
+}
+
+/// What was written to one output file, as listed in the run summary
+#[derive(Debug, Serialize)]
+struct OutputFileSummary {
+    bucket: String,
+    table: OutputTable,
+    path: String,
+    rows: u64,
+    min_created_at: Option<DateTime<Utc>>,
+    max_created_at: Option<DateTime<Utc>>,
+    /// Size of the closed file on disk
+    bytes: u64,
+}
+
+impl OutputFileSummary {
+    fn new(bucket: &str, table: OutputTable, path: String) -> Self {
+        Self { bucket: bucket.to_string(), table, path, rows: 0, min_created_at: None, max_created_at: None, bytes: 0 }
+    }
+
+    fn record(&mut self, buffer: &Buffered) {
+        self.rows += buffer.len() as u64;
+        let min = buffer.created_ats().iter().min().and_then(|&millis| DateTime::from_timestamp_millis(millis));
+        let max = buffer.created_ats().iter().max().and_then(|&millis| DateTime::from_timestamp_millis(millis));
+        self.min_created_at = self.min_created_at.into_iter().chain(min).min();
+        self.max_created_at = self.max_created_at.into_iter().chain(max).max();
+    }
+}
+
+/// Machine-readable record of a run, written to `_summary.json` in the output root.
+#[derive(Debug, Serialize)]
+struct RunSummary<'a> {
+    tool_version: &'static str,
+    /// None when the inputs were listed by --files or --file-list
+    timeframe: Option<&'a str>,
+    /// The share of the work this run covered, when the run was sharded
+    shard: Option<ShardId>,
+    shard_by: Option<ShardBy>,
+    /// The run was interrupted, so some inputs were only partly processed or not at all
+    partial: bool,
+    /// Fraction of rows kept by --sample; the output is not complete data when set
+    sample: Option<f64>,
+    /// Row cap given by --limit, and whether it cut the run short
+    limit: Option<u64>,
+    limit_reached: bool,
+    input_files: usize,
+    /// Inputs already completed by an earlier run and left as they were
+    up_to_date_inputs: Vec<String>,
+    /// Output files of those inputs, which this run did not rewrite
+    up_to_date_files: usize,
+    failed_inputs: Vec<String>,
+    /// Inputs per output part. Every input of a checkpoint writes to the same files,
+    /// named `<month>.<part>.parquet` after the checkpoint's first input
+    checkpoint_every: usize,
+    checkpoints: BTreeMap<String, Vec<String>>,
+    rows_read: u64,
+    /// Rows read but not written: bad rows, filtered, clipped and duplicate rows
+    rows_skipped: u64,
+    rows_written: u64,
+    /// Rows written, keyed by event type
+    event_types: BTreeMap<String, u64>,
+    /// Derived table rows, when written with --emit-commits or --emit-pull-requests
+    commits_written: Option<u64>,
+    pull_requests_written: Option<u64>,
+    /// Events written without their derived rows because the payload didn't parse
+    bad_payloads: Option<u64>,
+    /// Rows dropped by --no-bots, and the logins that sent the most of them
+    bot_rows: Option<u64>,
+    top_bot_logins: Option<Vec<(String, u64)>>,
+    /// Number of distinct buckets that received rows
+    bucket_count: usize,
+    bytes_written: u64,
+    /// Time spent by phase, with --report-timing
+    timing: Option<PhaseTimings>,
+    /// Files closed for inputs that were processed by this run
+    files: Vec<OutputFileSummary>,
+}
+
+impl RunSummary<'_> {
+    /// Written through a temporary file like the manifest, so readers never see a partial summary.
+    fn save(&self, summary_path: &Path, remote: Option<&RemoteDir>) -> Result<()> {
+        if let Some(output_dir) = summary_path.parent() {
+            create_dir_all(output_dir)?;
+        }
+        let tmp_path = summary_path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(self)?)
+            .context(format!("Failed to write run summary: {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, summary_path)
+            .context(format!("Failed to replace run summary: {}", summary_path.display()))?;
+        if let Some(remote) = remote {
+            remote.upload(summary_path, &summary_path.file_name().unwrap().to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
+/// The bucket writers of one of a table's pools. The number of open writers is capped by
+/// closing the least recently used one; if that bucket receives more rows later they
+/// go to a new, numbered part file.
+#[derive(Default)]
+struct WriterPool {
+    writers: HashMap<String, OpenWriter>,
+    /// Number of part files already closed for each bucket
+    closed_parts: HashMap<String, usize>,
+    /// Every part file closed so far
+    finalized: Vec<OutputFileSummary>,
+    /// Incremented on each access to order writers by recency
+    clock: u64,
+    /// Approximate size of the rows buffered across all open writers
+    buffered_bytes: usize,
+    /// Shared with the table's other pools, for the caps on open writers and buffered data
+    totals: Arc<WriterTotals>,
+}
+
+/// Open writers and buffered bytes over every pool of a table, which --max-open-writers
+/// and --max-buffer-mb limit
+#[derive(Debug, Default)]
+struct WriterTotals {
+    open_writers: AtomicUsize,
+    buffered_bytes: AtomicUsize,
+}
+
+impl WriterPool {
+    /// Counts bytes added to (or, negative, removed from) the buffers of the pool.
+    fn add_buffered_bytes(&mut self, bytes: isize) {
+        self.buffered_bytes = self.buffered_bytes.wrapping_add_signed(bytes);
+        if bytes >= 0 {
+            self.totals.buffered_bytes.fetch_add(bytes.unsigned_abs(), Ordering::Relaxed);
+        } else {
+            self.totals.buffered_bytes.fetch_sub(bytes.unsigned_abs(), Ordering::Relaxed);
+        }
+    }
+    
+    fn close_writer(&mut self, bucket_key: &str) -> Result<()> {
+        if let Some(mut open) = self.writers.remove(bucket_key) {
+            self.totals.open_writers.fetch_sub(1, Ordering::Relaxed);
+            self.add_buffered_bytes(-(open.buffer.bytes() as isize));
+            open.flush()?;
+            timing::time(Phase::Write, || open.writer.close())?;
+            open.summary.bytes = std::fs::metadata(&open.summary.path)
+                .context(format!("Failed to read size of {}", open.summary.path))?
+                .len();
+            self.finalized.push(open.summary);
+            *self.closed_parts.entry(bucket_key.to_string()).or_insert(0) += 1;
+        }
+        Ok(())
+    }
+    
+    /// Writes out the pool's largest buffers until the data buffered by all pools of the
+    /// table fits in `limit` bytes, or this pool has nothing left to write.
+    fn flush_largest_buffers(&mut self, limit: usize) -> Result<()> {
+        let mut by_size: Vec<&mut OpenWriter> = self.writers.values_mut()
+            .filter(|open| open.buffer.len() > 0)
+            .collect();
+        by_size.sort_by_key(|open| std::cmp::Reverse(open.buffer.bytes()));
+        let mut flushed = 0;
+        for open in by_size {
+            if self.totals.buffered_bytes.load(Ordering::Relaxed) <= limit {
+                break;
+            }
+            let bytes = open.buffer.bytes();
+            self.totals.buffered_bytes.fetch_sub(bytes, Ordering::Relaxed);
+            flushed += bytes;
+            open.flush()?;
+        }
+        self.buffered_bytes -= flushed;
+        Ok(())
+    }
+    
+    /// Bytes written so far to the pool's files, open or closed.
+    fn bytes_written(&self) -> u64 {
+        self.finalized.iter().map(|file| file.bytes).sum::<u64>()
+            + self.writers.values().map(|open| open.writer.bytes_written()).sum::<u64>()
+    }
+    
+    fn evict_least_recently_used(&mut self) -> Result<()> {
+        let oldest = self.writers.iter()
+            .min_by_key(|(_, open)| open.last_used)
+            .map(|(bucket_key, _)| bucket_key.clone());
+        if let Some(bucket_key) = oldest {
+            debug!("Closing {} to stay under --max-open-writers; later rows go to a new part file", bucket_key);
+            self.close_writer(&bucket_key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Pools a table's writers are spread over; well above the thread count, so two threads
+/// rarely wait for the same one
+const WRITER_POOLS: usize = 64;
+
+/// The writers of one output table during a checkpoint, shared by every thread reading
+/// its inputs. Buckets are spread over separately locked pools by a hash of their key,
+/// so threads writing to different buckets seldom wait for each other.
+struct ParquetWriters {
+    table: OutputTable,
+    /// The part name every file of the checkpoint is named after
+    part: String,
+    pools: Vec<Mutex<WriterPool>>,
+    totals: Arc<WriterTotals>,
+}
+
+impl ParquetWriters {
+    fn new(table: OutputTable, part: &str) -> Self {
+        let totals = Arc::new(WriterTotals::default());
+        Self {
+            table,
+            part: part.to_string(),
+            pools: (0..WRITER_POOLS)
+                .map(|_| Mutex::new(WriterPool { totals: Arc::clone(&totals), ..WriterPool::default() }))
+                .collect(),
+            totals,
+        }
+    }
+    
+    /// Locks the pool holding a bucket's writer.
+    fn pool(&self, bucket_key: &str) -> std::sync::MutexGuard<'_, WriterPool> {
+        self.pools[(stable_hash(bucket_key) % WRITER_POOLS as u64) as usize].lock().unwrap()
+    }
+    
+    fn open_writers(&self) -> usize {
+        self.totals.open_writers.load(Ordering::Relaxed)
+    }
+    
+    /// Bytes written so far to the table's files, open or closed.
+    fn bytes_written(&self) -> u64 {
+        self.pools.iter().map(|pool| pool.lock().unwrap().bytes_written()).sum()
+    }
+}
+
+/// The writers of a checkpoint's derived tables, for those requested
+#[derive(Default)]
+struct DerivedWriters {
+    commits: Option<ParquetWriters>,
+    pull_requests: Option<ParquetWriters>,
+}
+
+impl DerivedWriters {
+    fn new(emit_commits: bool, emit_pull_requests: bool, part: &str) -> Self {
+        Self {
+            commits: emit_commits.then(|| ParquetWriters::new(OutputTable::Commits, part)),
+            pull_requests: emit_pull_requests.then(|| ParquetWriters::new(OutputTable::PullRequests, part)),
+        }
+    }
+    
+    fn pools(&self) -> impl Iterator<Item = &ParquetWriters> {
+        self.commits.iter().chain(&self.pull_requests)
+    }
+    
+    /// The rows an event adds to the derived tables, with the writers they go to, or an
+    /// error if its payload doesn't parse. None for events that add no rows.
+    fn rows_of(&self, event: &EventRow) -> Option<(&ParquetWriters, Result<Vec<OutputRow>>)> {
+        // Only the payloads of event types with a requested table are parsed
+        let writers = match event.event_type.as_str() {
+            "PushEvent" => self.commits.as_ref()?,
+            "PullRequestEvent" => self.pull_requests.as_ref()?,
+            _ => return None,
+        };
+        let rows = GitHubEventType::parse(&event.event_type, &event.payload)
+            .context(format!("Failed to parse {} payload of event {}", event.event_type, event.id))
+            .and_then(|payload| match payload {
+                GitHubEventType::PushEvent(push) => {
+                    Ok(commits::commits_of_push(event, push)?.into_iter().map(OutputRow::Commit).collect())
+                }
+                GitHubEventType::PullRequestEvent(payload) => {
+                    Ok(vec![OutputRow::PullRequest(pull_requests::pull_request_of_event(event, *payload))])
+                }
+                _ => unreachable!("payload parsed as the wrong event type"),
+            });
+        Some((writers, rows))
+    }
+}
+
+/// Settings and shared state used while processing every input file in a run.
+struct RunContext<'a> {
+    config: &'a WriterConfig,
+    filter: &'a RowFilter,
+    seen_ids: Option<&'a SeenIds>,
+    /// Where skipped rows are recorded, if requested
+    error_log: Option<&'a Mutex<BufWriter<File>>>,
+    /// Fail the input file on the first bad row instead of skipping it
+    strict: bool,
+    partition_by: PartitionStrategy,
+    split_by_event_type: bool,
+    input_format: InputFormat,
+    /// Set when --input-dir is a URL; inputs are then URLs too
+    input_remote: Option<&'a RemoteDir>,
+    row_limit: Option<&'a RowLimit>,
+    /// Count the rows written per repository for --repo-counts
+    count_repos: bool,
+    renames: Option<&'a RenameMap>,
+    progress: &'a MultiProgress,
+    /// Rows between --progress json lines; None when progress is shown as bars
+    progress_rows: Option<u64>,
+    /// The run's bar when it counts the rows of all inputs, advanced with each row read
+    overall_rows: Option<&'a ProgressBar>,
+}
+
+/// Rows left to write under --limit, shared by the input files processed in parallel
+struct RowLimit {
+    max: u64,
+    written: AtomicU64,
+}
+
+impl RowLimit {
+    /// Claims room for one more row, returning false once the limit is reached.
+    fn take(&self) -> bool {
+        self.written.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |written| (written < self.max).then_some(written + 1)).is_ok()
+    }
+
+    /// Returns claimed rows that ended up not being written.
+    fn give_back(&self, rows: u64) {
+        self.written.fetch_sub(rows, Ordering::SeqCst);
+    }
+
+    fn reached(&self) -> bool {
+        self.written.load(Ordering::SeqCst) >= self.max
+    }
+}
+
+/// Event ids already written, per bucket key. Shared by all input files in a run since
+/// overlapping exports put the same event in more than one file.
+type SeenIds = Mutex<HashMap<String, HashSet<String>>>;
+
+/// The part name of an input file: the input's stem, tagged with the shard when the run
+/// is sharded. A checkpoint's files are named after the part name of its first input.
+fn output_part_name(file_path: &str, shard: Option<ShardId>) -> String {
+    match shard {
+        Some(shard) => format!("{}.{}", input_part_name(file_path), shard.tag()),
+        None => input_part_name(file_path),
+    }
+}
+
+/// Returns the stem of an input file name.
+fn input_part_name(file_path: &str) -> String {
+    let file_name = Path::new(file_path).file_name().unwrap().to_string_lossy();
+    file_name
+        .trim_end_matches(".zst")
+        .trim_end_matches(".parquet")
+        .trim_end_matches(".gz")
+        .trim_end_matches(".json")
+        .to_string()
+}
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+const PARQUET_MAGIC: [u8; 4] = *b"PAR1";
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// A decompressed copy of an input file, removed when dropped.
+struct TempInput {
+    path: PathBuf,
+}
+
+impl Drop for TempInput {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Local directory remote inputs or outputs pass through, private to this process
+fn staging_dir(kind: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("archive-{}-{}", kind, std::process::id()))
+}
+
+/// Returns a local path to read an input from, downloading remote inputs into a
+/// temporary file first; the returned guard must be kept alive while the file is in use.
+/// The download keeps the input's file name, which output part names are derived from.
+fn stage_input(file_path: &str, remote: Option<&RemoteDir>) -> Result<(String, Option<TempInput>)> {
+    let Some(remote) = remote else {
+        return Ok((file_path.to_string(), None));
+    };
+    let staging_dir = staging_dir("input");
+    create_dir_all(&staging_dir)
+        .context(format!("Failed to create {}", staging_dir.display()))?;
+    let relative = remote.relative_path(file_path)?;
+    let temp = TempInput { path: staging_dir.join(relative) };
+    remote.download(relative, &temp.path)?;
+    Ok((temp.path.to_string_lossy().into_owned(), Some(temp)))
+}
+
+/// Opens an input file for parquet reading. Zstd-compressed inputs (detected by their
+/// magic bytes, not the extension) are stream-decoded into a temporary file first; the
+/// returned guard must be kept alive while the file is in use.
+fn open_input_file(file_path: &str) -> Result<(File, Option<TempInput>)> {
+    let io_error = |context| move |source| ArchiveError::Io { context, path: file_path.to_string(), source };
+    let mut file = File::open(file_path).map_err(io_error("Failed to open parquet file"))?;
+    
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic).map_err(io_error("Input file is too short to be parquet"))?;
+    if magic == PARQUET_MAGIC {
+        return Ok((File::open(file_path)?, None));
+    }
+    if magic != ZSTD_MAGIC {
+        return Err(anyhow::anyhow!(
+            "Input file is neither parquet nor a zstd stream (starts with {:02x?}): {}",
+            magic, file_path
+        ));
+    }
+    
+    let temp = TempInput {
+        path: std::env::temp_dir().join(format!(
+            "archive-{}-{}.parquet",
+            std::process::id(),
+            input_part_name(file_path)
+        )),
+    };
+    
+    let mut decoder = zstd::stream::read::Decoder::new(File::open(file_path)?)?;
+    let mut output = File::create(&temp.path)
+        .context(format!("Failed to create temporary file: {}", temp.path.display()))?;
+    std::io::copy(&mut decoder, &mut output)
+        .context(format!("Failed to decompress {}", file_path))?;
+    
+    Ok((File::open(&temp.path)?, Some(temp)))
+}
+
+/// Builds the output path for a bucket's part file, returning the containing directory and the file path.
+/// Hive-style keys end in a `month=...` directory holding one file per part; otherwise the month
+/// is the file name prefix.
+fn bucket_output_path(output_dir: &str, bucket_key: &str, part: &str, hive: bool, extension: &str) -> Result<(String, String)> {
+    let parts: Vec<&str> = bucket_key.split('/').collect();
+    if parts.len() < 2 {
+        return Err(anyhow::anyhow!("Invalid bucket key format: '{}'", bucket_key));
+    }
+    
+    if hive {
+        let repo_dir = format!("{}/{}", output_dir, bucket_key);
+        let path = format!("{}/{}.{}", repo_dir, part, extension);
+        return Ok((repo_dir, path));
+    }
+    
+    let dir_parts = &parts[..parts.len()-1];
+    let month = parts[parts.len()-1];
+    
+    let repo_dir = format!("{}/{}", output_dir, dir_parts.join("/"));
+    let path = format!("{}/{}.{}.{}", repo_dir, month, part, extension);
+    Ok((repo_dir, path))
+}
+
+fn get_or_create_parquet_writer(writers: &ParquetWriters, pool: &mut WriterPool, bucket_key: &str, config: &WriterConfig) -> Result<()> {
+    pool.clock += 1;
+    let now = pool.clock;
+    
+    if let Some(open) = pool.writers.get_mut(bucket_key) {
+        open.last_used = now;
+    } else {
+        if pool.totals.open_writers.load(Ordering::Relaxed) >= config.max_open_writers {
+            pool.evict_least_recently_used()?;
+        }
+        
+        // A bucket that was evicted earlier continues in a numbered part file
+        let part = match pool.closed_parts.get(bucket_key) {
+            Some(closed) => format!("{}{}.{}", writers.table.part_prefix(), writers.part, closed),
+            None => format!("{}{}", writers.table.part_prefix(), writers.part),
+        };
+        let extension = config.output_format.extension();
+        let (repo_dir, mut path) = bucket_output_path(&config.output_dir, bucket_key, &part, config.hive_partitions, extension)?;
+        
+        // Parquet files can't be appended to, so rows for a file that already exists
+        // (e.g. from an earlier run) go to the next free numbered part instead
+        if !config.overwrite {
+            let mut suffix = 1;
+            while config.output_exists(&path)? {
+                (_, path) = bucket_output_path(&config.output_dir, bucket_key, &format!("{}.{:04}", part, suffix), config.hive_partitions, extension)?;
+                suffix += 1;
+            }
+        }
+        create_dir_all(&repo_dir)?;
+        
+        debug!("Opening {}", path);
+        let file = File::create(&path)?;
+
+        let writer: Box<dyn TableWriter> = match config.output_format {
+            OutputFormat::Parquet => {
+                let schema = Arc::new(parse_message_type(&writers.table.schema(&config.extra_columns()))?);
+                let props = config.writer_properties();
+                Box::new(SerializedFileWriter::new(file, schema, Arc::new(props))?)
+            }
+            OutputFormat::Ndjson => Box::new(JsonLinesWriter { encoder: zstd::Encoder::new(BufWriter::new(file), 0)? }),
+            OutputFormat::Arrow => Box::new(ArrowFileWriter::new(file, &writers.table.schema(&config.extra_columns()), config.ipc_compression)?),
+        };
+        let buffer = writers.table.new_buffer(&config.extra_columns());
+        let summary = OutputFileSummary::new(bucket_key, writers.table, path);
+        pool.writers.insert(bucket_key.to_string(), OpenWriter { writer, buffer, summary, last_used: now });
+        pool.totals.open_writers.fetch_add(1, Ordering::Relaxed);
+    }
+    
+    Ok(())
+}
+
+/// Positions of the input fields we read, resolved by name from each file's schema
+#[derive(Debug, Clone, Copy)]
+struct ColumnIndices {
+    event_type: usize,
+    payload: usize,
+    repo: RepoColumns,
+    created_at: usize,
+    /// Absent in the pre-2015 timeline, whose ids are derived from the row instead
+    id: Option<usize>,
+    actor: Option<ActorColumns>,
+}
+
+/// Where the repository name is read from
+#[derive(Debug, Clone, Copy)]
+enum RepoColumns {
+    /// The `repo` group and the `name` field inside it
+    Name(usize, usize),
+    /// The `repository` group of the pre-2015 timeline and its `owner` and `name`
+    /// fields, joined into `owner/name`
+    OwnerAndName { group: usize, owner: usize, name: usize },
+}
+
+/// Where the actor is read from
+#[derive(Debug, Clone, Copy)]
+enum ActorColumns {
+    /// The `actor` group and its `id` and `login` fields
+    Group { group: usize, id: usize, login: usize },
+    /// The pre-2015 timeline's `actor` string, which is only the login
+    Login(usize),
+}
+
+/// Input fields that are read, with the subfields kept for group fields. Everything
+/// else is left out of the read projection.
+const PROJECTED_FIELDS: &[(&str, &[&str])] = &[
+    ("type", &[]),
+    ("payload", &[]),
+    ("repo", &["name"]),
+    ("repository", &["owner", "name"]),
+    ("created_at", &[]),
+    ("id", &[]),
+    ("actor", &["id", "login"]),
+];
+
+/// Builds a read projection of the input schema containing only `PROJECTED_FIELDS`,
+/// so the reader doesn't decode large unused columns like `org` and `other`.
+fn projected_schema(schema: &Type) -> Result<Type> {
+    let mut fields = Vec::new();
+    for field in schema.get_fields() {
+        let Some((_, subfields)) = PROJECTED_FIELDS.iter().find(|(name, _)| *name == field.name()) else {
+            continue;
+        };
+        
+        if subfields.is_empty() || !field.is_group() {
+            fields.push(Arc::clone(field));
+            continue;
+        }
+        
+        let kept: Vec<Arc<Type>> = field.get_fields().iter()
+            .filter(|subfield| subfields.contains(&subfield.name()))
+            .cloned()
+            .collect();
+        let group = Type::group_type_builder(field.name())
+            .with_repetition(field.get_basic_info().repetition())
+            .with_fields(kept)
+            .build()?;
+        fields.push(Arc::new(group));
+    }
+    
+    Ok(Type::group_type_builder(schema.name()).with_fields(fields).build()?)
+}
+
+/// Checks that an input column is a string or timestamp column as the row extraction
+/// expects, so a mismatched export fails once up front instead of on every row.
+fn check_column_type(field: &Type, name: &str, timestamp: bool) -> Result<(), ArchiveError> {
+    let actual = if field.is_primitive() {
+        format!("{:?} ({:?})", field.get_physical_type(), field.get_basic_info().logical_type())
+    } else {
+        "a group".to_string()
+    };
+    let matches = field.is_primitive() && if timestamp {
+        // Older writers only set the converted type
+        field.get_physical_type() == PhysicalType::INT64
+            && (matches!(field.get_basic_info().logical_type(), Some(LogicalType::Timestamp { .. }))
+                || matches!(field.get_basic_info().converted_type(), ConvertedType::TIMESTAMP_MILLIS | ConvertedType::TIMESTAMP_MICROS))
+    } else {
+        field.get_physical_type() == PhysicalType::BYTE_ARRAY
+    };
+    if !matches {
+        let expected = if timestamp { "an INT64 timestamp" } else { "a BYTE_ARRAY string" };
+        return Err(ArchiveError::SchemaMismatch { column: name.to_string(), expected, found: actual });
+    }
+    Ok(())
+}
+
+/// Resolves the input columns by name so that exports with a different column order
+/// still read correctly, failing with the first missing column (and the list of
+/// available ones) or the first column whose type can't be read.
+/// The indices are only valid for rows read with exactly this schema.
+fn validate_schema(schema: &Type) -> Result<ColumnIndices, ArchiveError> {
+    check_schema(schema).map_err(|mut problems| problems.remove(0))
+}
+
+/// Like `validate_schema`, but reports every missing or mistyped column at once.
+fn check_schema(schema: &Type) -> Result<ColumnIndices, Vec<ArchiveError>> {
+    let missing = |name: &str| {
+        let mut found = Vec::new();
+        for field in schema.get_fields() {
+            if field.is_group() {
+                found.extend(field.get_fields().iter().map(|subfield| format!("{}.{}", field.name(), subfield.name())));
+            } else {
+                found.push(field.name().to_string());
+            }
+        }
+        ArchiveError::MissingColumn { column: name.to_string(), found }
+    };
+    
+    let field_index = |fields: &[Arc<Type>], name: &str| {
+        fields.iter().position(|field| field.name() == name)
+    };
+    let group_field_index = |group: &str, name: &str| -> Option<(usize, usize)> {
+        let group_index = field_index(schema.get_fields(), group)?;
+        let group_type = &schema.get_fields()[group_index];
+        if !group_type.is_group() {
+            return None;
+        }
+        Some((group_index, field_index(group_type.get_fields(), name)?))
+    };
+    
+    let fields = schema.get_fields();
+    let mut problems = Vec::new();
+    let actor = match (group_field_index("actor", "id"), group_field_index("actor", "login")) {
+        (Some((group, id)), Some((_, login))) => Some(ActorColumns::Group { group, id, login }),
+        _ => field_index(fields, "actor")
+            .filter(|&index| fields[index].is_primitive())
+            .map(ActorColumns::Login),
+    };
+    
+    let required = |name: &str| field_index(fields, name).ok_or_else(|| missing(name));
+    // Exports of the pre-2015 timeline have a `repository` group with the owner and
+    // name split apart instead of `repo.name`, and no event ids
+    let repo = match (group_field_index("repo", "name"), group_field_index("repository", "owner"), group_field_index("repository", "name")) {
+        (Some((group, name)), _, _) => Ok(RepoColumns::Name(group, name)),
+        (None, Some((group, owner)), Some((_, name))) => Ok(RepoColumns::OwnerAndName { group, owner, name }),
+        _ => Err(missing("repo.name")),
+    };
+    // Listed in column order, so the first problem is the one validate_schema reports
+    let event_type = required("type").map_err(|problem| problems.push(problem)).ok();
+    let payload = required("payload").map_err(|problem| problems.push(problem)).ok();
+    let repo = repo.map_err(|problem| problems.push(problem)).ok();
+    let created_at = required("created_at").map_err(|problem| problems.push(problem)).ok();
+    let id = field_index(fields, "id");
+    if id.is_none() && matches!(repo, Some(RepoColumns::Name(..))) {
+        problems.push(missing("id"));
+    }
+    
+    let mut check = |field: &Type, name: &str, timestamp: bool| {
+        if let Err(problem) = check_column_type(field, name, timestamp) {
+            problems.push(problem);
+        }
+    };
+    if let Some(event_type) = event_type {
+        check(&fields[event_type], "type", false);
+    }
+    if let Some(payload) = payload {
+        check(&fields[payload], "payload", false);
+    }
+    match repo {
+        Some(RepoColumns::Name(group, name)) => check(&fields[group].get_fields()[name], "repo.name", false),
+        Some(RepoColumns::OwnerAndName { group, owner, name }) => {
+            check(&fields[group].get_fields()[owner], "repository.owner", false);
+            check(&fields[group].get_fields()[name], "repository.name", false);
+        }
+        None => {}
+    }
+    if let Some(created_at) = created_at {
+        check(&fields[created_at], "created_at", true);
+    }
+    if let Some(id) = id {
+        check(&fields[id], "id", false);
+    }
+    if let Some(ActorColumns::Login(login)) = actor {
+        check(&fields[login], "actor", false);
+    }
+    
+    match (event_type, payload, repo, created_at) {
+        (Some(event_type), Some(payload), Some(repo), Some(created_at)) if problems.is_empty() => {
+            Ok(ColumnIndices { event_type, payload, repo, created_at, id, actor })
+        }
+        _ => Err(problems),
+    }
+}
+
+/// Reads a timestamp column as milliseconds since the Unix epoch, whichever unit the
+/// input stores it in.
+fn timestamp_millis(row: &Row, index: usize) -> Result<i64> {
+    match row.get_column_iter().nth(index).map(|(_, field)| field) {
+        Some(Field::TimestampMillis(millis)) => Ok(*millis),
+        Some(Field::TimestampMicros(micros)) => Ok(micros.div_euclid(1000)),
+        Some(field) => Err(anyhow::anyhow!("Expected a timestamp for created_at, found {:?}", field)),
+        None => Err(anyhow::anyhow!("Row has no column {}", index)),
+    }
+}
+
+fn extract_data_from_parquet_row(row: &Row, columns: &ColumnIndices) -> Result<Option<EventRow>> {
+    // Extract event type
+    let event_type = row.get_string(columns.event_type)?.to_string();
+
+    let repo_name = match columns.repo {
+        RepoColumns::Name(group, name) => row.get_group(group)?.get_string(name)?.to_string(),
+        // Timeline events outside a repository (FollowEvent, GistEvent, ...) have no
+        // repository group and get an empty name
+        RepoColumns::OwnerAndName { group, owner, name } => match row.get_group(group) {
+            Ok(repository) => gh::legacy_repo_name(
+                repository.get_string(owner).map(String::as_str).unwrap_or_default(),
+                repository.get_string(name).map(String::as_str).unwrap_or_default(),
+            ),
+            Err(_) => String::new(),
+        },
+    };
+
+    let payload = row.get_string(columns.payload)?.to_string();
+    
+    // Old data has no actor group, so fall back to empty values instead of failing
+    let (actor_login, actor_id) = match columns.actor {
+        Some(ActorColumns::Group { group, id, login }) => match row.get_group(group) {
+            Ok(actor_group) => (
+                actor_group.get_string(login).map(|login| login.to_string()).unwrap_or_default(),
+                actor_group.get_long(id).unwrap_or(0),
+            ),
+            Err(_) => (String::new(), 0),
+        },
+        Some(ActorColumns::Login(login)) => (row.get_string(login).map(|login| login.to_string()).unwrap_or_default(), 0),
+        None => (String::new(), 0),
+    };
+    
+    // Extract created_at timestamp in milliseconds to match the output schema
+    let created_timestamp = timestamp_millis(row, columns.created_at)?;
+    
+    let id = match columns.id {
+        Some(id) => row.get_string(id)?.to_string(),
+        None => gh::legacy_event_id(&event_type, &repo_name, &actor_login, created_timestamp, &payload),
+    };
+    
+    Ok(Some(EventRow {
+        id,
+        event_type,
+        repo_name,
+        payload,
+        created_at: created_timestamp,
+        actor_login,
+        actor_id,
+        extracted: Vec::new(),
+    }))
+}
+
+/// Schema of the events table, followed by one nullable string column per --extract column.
+fn output_schema(extra_columns: &[String]) -> String {
+    let mut schema = String::from("
+message schema {
+  REQUIRED BYTE_ARRAY type (STRING);
+  REQUIRED BYTE_ARRAY payload (STRING);
+  REQUIRED BYTE_ARRAY repo_name (STRING);
+  REQUIRED INT64 created_at (TIMESTAMP(MILLIS,true));
+  REQUIRED BYTE_ARRAY actor_login (STRING);
+  REQUIRED INT64 actor_id;
+  REQUIRED BYTE_ARRAY id (STRING);
+");
+    for column in extra_columns {
+        schema.push_str(&format!("  OPTIONAL BYTE_ARRAY {} (STRING);\n", column));
+    }
+    schema.push_str("}\n");
+    schema
+}
+
+/// The rows of an opened input file
+struct InputEvents {
+    events: Box<dyn Iterator<Item = Result<Option<EventRow>>>>,
+    /// Row count recorded in the file's metadata, for formats that have one
+    row_count: Option<u64>,
+    /// Keeps a decompressed temporary copy alive while the rows are read
+    _temp_input: Option<TempInput>,
+}
+
+/// Checks the schema of every local, uncompressed parquet input from its footer before
+/// anything is processed, so an incompatible file stops the run up front with all of
+/// its problems listed instead of failing partway through. JSON, zstd-compressed and
+/// remote inputs can't be checked without reading them and are left to the run.
+/// Returns the number of rows in the inputs when every one of them could be checked.
+fn preflight_schemas(files: &[String], input_format: InputFormat) -> Result<Option<u64>> {
+    // None for inputs that weren't checked, otherwise their row count or problems
+    let checked: Vec<Option<Result<u64, Vec<String>>>> = files.par_iter()
+        .map(|file_path| {
+            if input_format.resolve(file_path) != InputFormat::Parquet {
+                return None;
+            }
+            let mut file = File::open(file_path).ok()?;
+            let mut magic = [0u8; 4];
+            // Unreadable and compressed files are reported by the run itself
+            if file.read_exact(&mut magic).is_err() || magic != PARQUET_MAGIC {
+                return None;
+            }
+            Some(match SerializedFileReader::new(file) {
+                Ok(reader) => match check_schema(reader.metadata().file_metadata().schema()) {
+                    Ok(_) => Ok(reader.metadata().file_metadata().num_rows() as u64),
+                    Err(problems) => Err(problems.iter().map(ToString::to_string).collect()),
+                },
+                Err(e) => Err(vec![format!("Failed to read the parquet footer: {}", e)]),
+            })
+        })
+        .collect();
+    let mut incompatible: Vec<(&String, &Vec<String>)> = files.iter().zip(&checked)
+        .filter_map(|(file_path, checked)| match checked {
+            Some(Err(problems)) => Some((file_path, problems)),
+            _ => None,
+        })
+        .collect();
+    if incompatible.is_empty() {
+        return Ok(checked.iter()
+            .map(|checked| checked.as_ref().and_then(|rows| rows.as_ref().ok()))
+            .sum());
+    }
+    
+    incompatible.sort();
+    error!("✗ {} of {} input files have an incompatible schema:", incompatible.len(), files.len());
+    for (file_path, problems) in &incompatible {
+        error!("  {}:", file_path);
+        for problem in *problems {
+            error!("    {}", problem);
+        }
+    }
+    Err(anyhow::anyhow!("{} input files have an incompatible schema; nothing was processed", incompatible.len()))
+}
+
+fn open_parquet_events(file_path: &str) -> Result<InputEvents> {
+    let (file, temp_input) = open_input_file(file_path)?;
+    
+    let reader = SerializedFileReader::new(file)?;
+    // Validate against the full schema so a missing column is reported alongside
+    // everything the file actually has, then resolve positions within the projection
+    let file_schema = reader.metadata().file_metadata().schema();
+    validate_schema(file_schema)
+        .context(format!("Unsupported input schema in {}", file_path))?;
+    let projection = projected_schema(file_schema)?;
+    let columns = validate_schema(&projection)?;
+    let row_count = reader.metadata().file_metadata().num_rows() as u64;
+    
+    let row_iter = RowIter::from_file_into(Box::new(reader)).project(Some(projection))?;
+    
+    // Extract data directly from parquet row without JSON conversion
+    let events = row_iter.map(move |row| {
+        row.map_err(anyhow::Error::from)
+            .and_then(|row| timing::time(Phase::Extract, || extract_data_from_parquet_row(&row, &columns)))
+    });
+    Ok(InputEvents { events: Box::new(events), row_count: Some(row_count), _temp_input: temp_input })
+}
+
+/// Opens a GH Archive hourly file, decompressing it if it is gzipped.
+fn open_json_reader(file_path: &str) -> Result<Box<dyn BufRead>> {
+    let mut file = File::open(file_path)
+        .map_err(|source| ArchiveError::Io { context: "Failed to open JSON file", path: file_path.to_string(), source })?;
+    let mut magic = [0u8; 2];
+    let is_gzip = file.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+    let file = File::open(file_path)?;
+    Ok(if is_gzip {
+        // Hourly archives can be concatenated gzip members
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    })
+}
+
+/// Reads newline-delimited events, skipping blank lines.
+fn open_json_events(file_path: &str) -> Result<InputEvents> {
+    let events = gh::read_events(open_json_reader(file_path)?)
+        .map(|event| timing::time(Phase::Extract, || extract_data_from_json_event(event?)));
+    Ok(InputEvents { events: Box::new(events), row_count: None, _temp_input: None })
+}
+
+/// Converts one GH Archive event into a row.
+fn extract_data_from_json_event(event: GitHubEvent) -> Result<Option<EventRow>> {
+    let created_at = DateTime::parse_from_rfc3339(&event.created_at)
+        .context(format!("Invalid created_at: {}", event.created_at))?
+        .timestamp_millis();
+    
+    Ok(Some(EventRow {
+        id: event.id,
+        event_type: event.event_type,
+        repo_name: event.repo.name,
+        payload: event.payload.to_string(),
+        created_at,
+        actor_login: event.actor.login,
+        actor_id: event.actor.id as i64,
+        extracted: Vec::new(),
+    }))
+}
+
+/// Opens an input file in whichever format it is in.
+fn open_input_events(file_path: &str, input_format: InputFormat) -> Result<InputEvents> {
+    match input_format.resolve(file_path) {
+        InputFormat::Json => open_json_events(file_path),
+        _ => open_parquet_events(file_path),
+    }
+}
+
+fn process_input_file(file_path: &str, parquet_writers: &ParquetWriters, derived_writers: &DerivedWriters, ctx: &RunContext, stats: &mut ProcessStats) -> Result<()> {
+    let (local_path, _staged) = stage_input(file_path, ctx.input_remote)?;
+    let input = open_input_events(&local_path, ctx.input_format)?;
+    write_events(input.events, input.row_count, file_path, parquet_writers, derived_writers, ctx, stats)
+}
+
+/// Processes an input file, trying again up to `retries` times after a transient I/O
+/// error reading it. Each new attempt reopens the file and continues after the last row
+/// the failed one read, so the rows already written are kept rather than written twice.
+/// Errors writing a bucket are not retried, since its file is shared with other inputs.
+fn process_input_file_with_retries(file_path: &str, parquet_writers: &ParquetWriters, derived_writers: &DerivedWriters, ctx: &RunContext, retries: u32) -> Result<ProcessStats> {
+    let mut stats = ProcessStats::default();
+    let mut attempt = 0;
+    loop {
+        match process_input_file(file_path, parquet_writers, derived_writers, ctx, &mut stats) {
+            Err(e) if attempt < retries && error::is_transient(&e) && !error::is_write_error(&e) && !INTERRUPTED.load(Ordering::Relaxed) => {
+                attempt += 1;
+                let delay = Duration::from_secs(1 << (attempt - 1).min(6));
+                warn!("⚠ Transient error in {}, retrying from row {} in {}s ({} of {}): {:#}", file_path, stats.rows_read, delay.as_secs(), attempt, retries, e);
+                std::thread::sleep(delay);
+            }
+            result => return result.map(|()| stats),
+        }
+    }
+}
+
+/// Where a row goes: the bucket it is written to, or why it is dropped
+enum RowRoute {
+    Bucket(String),
+    FilteredEventType,
+    FilteredRepo,
+    Bot,
+    OtherShard,
+    Clipped,
+    SampledOut,
+}
+
+/// Gives a row the name --rename-map records its repository under, returning the name
+/// it had when it was renamed.
+fn apply_renames(event: &mut EventRow, ctx: &RunContext) -> Option<String> {
+    let new_name = ctx.renames?.resolve(&event.repo_name, event.created_at)?;
+    Some(std::mem::replace(&mut event.repo_name, new_name.to_string()))
+}
+
+/// Applies the row filters and computes the bucket key for a row.
+fn route_event(event: &EventRow, ctx: &RunContext) -> Result<RowRoute> {
+    let filter = ctx.filter;
+    if !filter.allows_event_type(&event.event_type) {
+        return Ok(RowRoute::FilteredEventType);
+    }
+    
+    // Skip before any writer is created so unmatched repos leave no bucket behind
+    if !filter.allows_repo(&event.repo_name) {
+        return Ok(RowRoute::FilteredRepo);
+    }
+    
+    if filter.is_bot(&event.actor_login) {
+        return Ok(RowRoute::Bot);
+    }
+    
+    if filter.repo_shard.is_some_and(|shard| !shard.owns(&event.repo_name)) {
+        return Ok(RowRoute::OtherShard);
+    }
+    
+    // Input files can contain rows from just before or after the period they are
+    // named for, so the timeframe is enforced per row
+    if let Some((resolution, timeframe)) = &filter.timeframe && &format_created_at(event.created_at, *resolution)? != timeframe {
+        return Ok(RowRoute::Clipped);
+    }
+    
+    // Sampled last so the sample is drawn from the filtered rows
+    if !filter.sampled(&event.id) {
+        return Ok(RowRoute::SampledOut);
+    }
+    
+    let month = extract_month_from_created_at(event.created_at)?;
+    Ok(RowRoute::Bucket(get_bucket_key(
+        &event.repo_name,
+        &month,
+        ctx.split_by_event_type.then_some(event.event_type.as_str()),
+        ctx.partition_by,
+        ctx.config.hive_partitions,
+    )))
+}
+
+/// Filters, buckets and writes the rows of one input file, counting them in `stats`.
+/// Rows that fail to read are skipped and logged unless running in strict mode. The rows
+/// that written events add to the derived tables go to `derived_writers`. With the
+/// file's `row_count` the progress bar shows how far along the file is and an ETA. The
+/// rows `stats` already counts, read by an earlier attempt, are skipped.
+fn write_events(
+    events: impl Iterator<Item = Result<Option<EventRow>>>,
+    row_count: Option<u64>,
+    file_path: &str,
+    parquet_writers: &ParquetWriters,
+    derived_writers: &DerivedWriters,
+    ctx: &RunContext,
+    stats: &mut ProcessStats,
+) -> Result<()> {
+    let spinner = match row_count {
+        Some(row_count) => ctx.progress.add(ProgressBar::new(row_count)).with_style(ProgressStyle::default_bar()
+            .template("{spinner:.green} {msg} [{elapsed_precise}] {bar:30.cyan/blue} {percent:>3}% {human_pos}/{human_len} rows ({per_sec}, ETA {eta})")?),
+        // JSON inputs aren't counted ahead of reading them
+        None => ctx.progress.add(ProgressBar::new_spinner()).with_style(ProgressStyle::default_spinner()
+            .template("{spinner:.green} {msg} [{elapsed_precise}] {human_pos} rows processed ({per_sec})")?),
+    };
+    spinner.set_message(format!("Processing {}", Path::new(file_path).file_name().unwrap().to_string_lossy()));
+    spinner.set_position(stats.rows_read);
+    
+    let mut events = events.enumerate().skip(stats.rows_read as usize);
+    while let Some((row_index, extracted)) = timing::time(Phase::Decode, || events.next()) {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            stats.interrupted = true;
+            break;
+        }
+        // Buckets are named by month, which a timestamp before the epoch doesn't have
+        let extracted = extracted.and_then(|event| match event {
+            Some(event) if event.created_at < 0 => {
+                Err(anyhow::anyhow!("created_at {} of event {} is before 1970", event.created_at, event.id))
+            }
+            event => Ok(event),
+        });
+        // A transient read error fails the file before the row is counted, so --retries
+        // reads it again from this row rather than counting it as a bad row
+        let extracted = match extracted {
+            Err(e) if ctx.strict || error::is_transient(&e) => {
+                return Err(ArchiveError::RowDecode { file: file_path.to_string(), ordinal: row_index, source: e.into() }.into());
+            }
+            extracted => extracted,
+        };
+        spinner.inc(1);
+        if let Some(overall) = ctx.overall_rows {
+            overall.inc(1);
+        }
+        stats.rows_read += 1;
+        if ctx.progress_rows.is_some_and(|rows| stats.rows_read.is_multiple_of(rows)) {
+            report_progress(file_path, stats.rows_read, row_count, parquet_writers, derived_writers);
+        }
+        
+        let mut event = match extracted {
+            Ok(Some(event)) => event,
+            Ok(None) => {
+                trace!("No data found in row {} of {}", row_index, file_path);
+                continue;
+            }
+            Err(e) => {
+                stats.bad_rows += 1;
+                // Printed around the progress bars rather than through them, since
+                // indicatif drops println output when stderr isn't a terminal
+                if stats.bad_rows <= BAD_ROWS_SHOWN_PER_FILE {
+                    warn!("⚠ Skipping row {} of {}: {:#}", row_index, file_path, e);
+                    if stats.bad_rows == BAD_ROWS_SHOWN_PER_FILE {
+                        warn!("  Further bad rows in {} are counted but not shown", file_path);
+                    }
+                }
+                if let Some(error_log) = ctx.error_log {
+                    log_bad_row(error_log, file_path, row_index, &e)?;
+                }
+                continue;
+            }
+        };
+        
+        let original_repo_name = timing::time(Phase::Route, || apply_renames(&mut event, ctx));
+        let bucket_key = match timing::time(Phase::Route, || route_event(&event, ctx))? {
+            RowRoute::Bucket(bucket_key) => bucket_key,
+            RowRoute::FilteredEventType => {
+                *stats.filtered_event_types.entry(event.event_type).or_insert(0) += 1;
+                continue;
+            }
+            RowRoute::FilteredRepo => {
+                stats.filtered_repo_rows += 1;
+                continue;
+            }
+            RowRoute::Bot => {
+                *stats.bot_rows.entry(event.actor_login).or_insert(0) += 1;
+                continue;
+            }
+            RowRoute::OtherShard => {
+                stats.other_shard_rows += 1;
+                continue;
+            }
+            RowRoute::Clipped => {
+                stats.clipped_rows += 1;
+                continue;
+            }
+            RowRoute::SampledOut => {
+                stats.sampled_out_rows += 1;
+                continue;
+            }
+        };
         
-        let dir_parts = &parts[..parts.len()-1];
-        let month = parts[parts.len()-1];
-        
-        let repo_dir = format!("work/archives-separated/{}", dir_parts.join("/"));
-        create_dir_all(&repo_dir)?;
-        
-        let path = format!("{}/{}.parquet", repo_dir, month);
+        if ctx.row_limit.is_some_and(|limit| !limit.take()) {
+            stats.limit_reached = true;
+            break;
+        }
         
-        let file = File::create(&path)?;
-
-        let schema = Arc::new(parse_message_type(OUTPUT_SCHEMA)?);
+        if !ctx.config.extractions.columns().is_empty() {
+            event.extracted = timing::time(Phase::Extract, || ctx.config.extractions.values_of(&event.event_type, &event.payload));
+        }
+        if ctx.config.original_repo_names {
+            event.extracted.resize(ctx.config.extractions.columns().len(), None);
+            event.extracted.push(original_repo_name);
+        }
         
-        let props = WriterProperties::builder()
-            .set_compression(Compression::ZSTD(Default::default()))
-            .build();
+        // Parsed before the event is moved into its buffer, but only written with it
+        let derived = timing::time(Phase::Extract, || derived_writers.rows_of(&event));
         
-        let writer = SerializedFileWriter::new(file, schema, Arc::new(props))?;
-        let buffer = RowBuffer::new();
-        writers_map.insert(bucket_key.to_string(), (writer, buffer));
+        let event_type = event.event_type.clone();
+        let repo_name = ctx.count_repos.then(|| event.repo_name.clone());
+        let write_error = |e: anyhow::Error| anyhow::Error::from(ArchiveError::Write { bucket: bucket_key.clone(), source: e.into() });
+        if timing::time(Phase::Buffer, || write_row_to_parquet(parquet_writers, &bucket_key, event, ctx.config, ctx.seen_ids)).map_err(write_error)? {
+            stats.rows_written += 1;
+            if let Some(repo_name) = &repo_name {
+                stats.repo_counts.add(repo_name, &event_type);
+            }
+            *stats.written_event_types.entry(event_type).or_insert(0) += 1;
+            match derived {
+                Some((writers, Ok(rows))) => {
+                    for row in rows {
+                        match row {
+                            OutputRow::PullRequest(_) => stats.pull_requests_written += 1,
+                            _ => stats.commits_written += 1,
+                        }
+                        timing::time(Phase::Buffer, || buffer_row(writers, &bucket_key, row, ctx.config)).map_err(write_error)?;
+                    }
+                }
+                // The event itself is kept, so a payload that doesn't parse only costs its derived rows
+                Some((_, Err(e))) => {
+                    stats.bad_payloads += 1;
+                    if stats.bad_payloads <= BAD_ROWS_SHOWN_PER_FILE {
+                        warn!("⚠ Only the event written for row {} of {}: {:#}", row_index, file_path, e);
+                        if stats.bad_payloads == BAD_ROWS_SHOWN_PER_FILE {
+                            warn!("  Further unreadable payloads in {} are counted but not shown", file_path);
+                        }
+                    }
+                    if let Some(error_log) = ctx.error_log {
+                        log_bad_row(error_log, file_path, row_index, &e)?;
+                    }
+                }
+                None => {}
+            }
+        } else {
+            stats.duplicates_dropped += 1;
+            if let Some(limit) = ctx.row_limit {
+                limit.give_back(1);
+            }
+        }
     }
     
+    spinner.finish();
     Ok(())
 }
 
-fn extract_data_from_parquet_row(row: &Row) -> Result<Option<(String, String, String, i64)>> {
-    // Extract event type
-    let event_type = row.get_string(0)?.to_string();
-
-    let repo_group = row.get_group(3)?;
-    let repo_name = repo_group.get_string(1)?.to_string();
-
-    let payload = row.get_string(2)?.to_string();
-    
-    // Extract created_at timestamp
-    let created_timestamp = row.get_timestamp_micros(6)? / 1000;
-    
-    Ok(Some((event_type, repo_name, payload, created_timestamp)))
+/// Writes a --progress json line for an input file being read.
+fn report_progress(file_path: &str, rows_processed: u64, row_count: Option<u64>, parquet_writers: &ParquetWriters, derived_writers: &DerivedWriters) {
+    let (mut buckets_open, mut bytes_written) = (0, 0);
+    for writers in std::iter::once(parquet_writers).chain(derived_writers.pools()) {
+        buckets_open += writers.open_writers();
+        bytes_written += writers.bytes_written();
+    }
+    ProgressEvent::Processing { file: file_path, rows_processed, rows_total_estimate: row_count, buckets_open, bytes_written }.emit();
 }
 
-const OUTPUT_SCHEMA: &str = r#"
-message schema {
-  REQUIRED BYTE_ARRAY type (STRING);
-  REQUIRED BYTE_ARRAY payload (STRING);
-  REQUIRED BYTE_ARRAY repo_name (STRING);
-  REQUIRED INT64 created_at;
-}
-"#;
+/// Bad rows printed per input file; the rest only show up in the counts and error log
+const BAD_ROWS_SHOWN_PER_FILE: u64 = 5;
 
-fn process_parquet_file(file_path: &str, parquet_writers: ParquetWriters) -> Result<()> {
-    let file = File::open(file_path)
-        .context(format!("Failed to open parquet file: {}", file_path))?;
-    
-    let reader = SerializedFileReader::new(file)?;
-    
-    let spinner = ProgressBar::new_spinner();
-    spinner.set_message(format!("Processing {}", Path::new(file_path).file_name().unwrap().to_string_lossy()));
-    spinner.set_style(ProgressStyle::default_spinner()
-        .template("{spinner:.green} {msg} [{elapsed_precise}] {human_pos} rows processed ({per_sec})")?);
-    
-    let mut row_iter = reader.get_row_iter(None)?;
+/// Appends one skipped row to the error log as a JSON line.
+fn log_bad_row(error_log: &Mutex<BufWriter<File>>, file_path: &str, row_index: usize, error: &anyhow::Error) -> Result<()> {
+    let entry = serde_json::json!({
+        "file": file_path,
+        "row": row_index,
+        "error": format!("{:#}", error),
+    });
+    let mut error_log = error_log.lock().unwrap();
+    writeln!(error_log, "{}", entry).context("Failed to write to error log")?;
+    Ok(())
+}
 
-    let schema = reader.metadata().file_metadata().schema();
-    
-    while let Some(row) = row_iter.next() {
-        let row = row?;
-        
-        // Extract data directly from parquet row without JSON conversion
-        if let Some((event_type, repo_name, payload, created_at)) = extract_data_from_parquet_row(&row)? {
-            let month = extract_month_from_created_at(created_at)?;
-            let bucket_key = get_bucket_key(&repo_name, &month);
-            
-            // Pass the original row directly instead of converting to JSON
-            write_row_to_parquet(&parquet_writers, &bucket_key, &row)?;
-        } else {
-            println!("No data found in row");
+/// Buffers a row for its bucket. Returns false if the row was dropped as a duplicate.
+fn write_row_to_parquet(writers: &ParquetWriters, bucket_key: &str, event: EventRow, config: &WriterConfig, seen_ids: Option<&SeenIds>) -> Result<bool> {
+    if let Some(seen_ids) = seen_ids {
+        let mut seen_ids = seen_ids.lock().unwrap();
+        let is_new = match seen_ids.get_mut(bucket_key) {
+            Some(bucket_ids) => bucket_ids.insert(event.id.clone()),
+            None => {
+                seen_ids.insert(bucket_key.to_string(), HashSet::from([event.id.clone()]));
+                true
+            }
+        };
+        if !is_new {
+            return Ok(false);
         }
-        
-        spinner.inc(1);
     }
     
-    spinner.finish();
-    Ok(())
+    buffer_row(writers, bucket_key, OutputRow::Event(event), config)?;
+    Ok(true)
 }
 
-fn write_row_to_parquet(writers: &ParquetWriters, bucket_key: &str, row: &Row) -> Result<()> {
-    get_or_create_parquet_writer(writers, bucket_key)?;
-    
-    // Extract the data we need from the row
-    let (event_type, repo_name, payload, created_at) = extract_data_from_parquet_row(row)?.unwrap();
+/// Adds a row to its bucket's buffer in a pool, writing out buffers that grew too large.
+fn buffer_row(writers: &ParquetWriters, bucket_key: &str, row: OutputRow, config: &WriterConfig) -> Result<()> {
+    let mut guard = writers.pool(bucket_key);
+    get_or_create_parquet_writer(writers, &mut guard, bucket_key, config)?;
     
     // Add to buffer
     {
-        let mut writers_map = writers.lock().unwrap();
-        let (_, buffer) = writers_map.get_mut(bucket_key).unwrap();
-        buffer.add_row(event_type, payload, repo_name, created_at);
+        let pool = &mut *guard;
+        pool.add_buffered_bytes(row.data_bytes() as isize);
+        let open = pool.writers.get_mut(bucket_key).unwrap();
+        open.buffer.add_row(row);
         
         // Write batch when buffer reaches threshold
-        if buffer.len() >= 1000 {
-            flush_buffer_to_parquet(&mut writers_map.get_mut(bucket_key).unwrap())?;
+        if config.should_flush(&open.buffer) {
+            let bytes = open.buffer.bytes();
+            open.flush()?;
+            pool.add_buffered_bytes(-(bytes as isize));
+        }
+        
+        // Many buckets can each stay under their own threshold while together
+        // holding far more than fits in memory. Flushing down to half the cap keeps a
+        // pool sitting at the limit from flushing a tiny row group on every row
+        if let Some(limit) = config.max_buffer_bytes.filter(|&limit| pool.totals.buffered_bytes.load(Ordering::Relaxed) > limit) {
+            pool.flush_largest_buffers(limit / 2)?;
         }
     }
     
     Ok(())
 }
 
-fn flush_buffer_to_parquet((writer, buffer): &mut (SerializedFileWriter<File>, RowBuffer)) -> Result<()> {
+fn write_buffer_to_json_lines(out: &mut dyn Write, buffer: &mut RowBuffer) -> Result<()> {
+    for i in 0..buffer.len() {
+        let mut row = serde_json::json!({
+            "type": buffer.event_types[i],
+            "payload": buffer.payloads[i],
+            "repo_name": buffer.repo_names[i],
+            "created_at": timestamp_json(buffer.created_ats[i]),
+            "actor_login": buffer.actor_logins[i],
+            "actor_id": buffer.actor_ids[i],
+            "id": buffer.ids[i],
+        });
+        for (column, values) in &buffer.extracted {
+            row[column] = Value::from(values[i].clone());
+        }
+        serde_json::to_writer(&mut *out, &row)?;
+        out.write_all(b"\n")?;
+    }
+    buffer.clear();
+    Ok(())
+}
+
+/// Moves the buffered rows into a record batch, in output schema column order.
+fn take_buffer_record_batch(schema: SchemaRef, buffer: &mut RowBuffer) -> Result<RecordBatch> {
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.event_types))),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.payloads))),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.repo_names))),
+        Arc::new(TimestampMillisecondArray::from(std::mem::take(&mut buffer.created_ats)).with_timezone("UTC")),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.actor_logins))),
+        Arc::new(Int64Array::from(std::mem::take(&mut buffer.actor_ids))),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.ids))),
+    ];
+    for (_, values) in &mut buffer.extracted {
+        columns.push(Arc::new(StringArray::from(std::mem::take(values))));
+    }
+    buffer.clear();
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+fn flush_buffer_to_parquet(writer: &mut SerializedFileWriter<File>, buffer: &mut RowBuffer) -> Result<()> {
     if buffer.len() == 0 {
         return Ok(());
     }
@@ -289,88 +2869,939 @@ fn flush_buffer_to_parquet((writer, buffer): &mut (SerializedFileWriter<File>, R
         col_writer.close()?;
     }
     
+    // Write actor login column
+    {
+        let mut col_writer = row_group_writer.next_column()?.unwrap();
+        let values: Vec<parquet::data_type::ByteArray> = buffer.actor_logins.iter()
+            .map(|s| parquet::data_type::ByteArray::from(s.as_bytes()))
+            .collect();
+        col_writer.typed::<parquet::data_type::ByteArrayType>()
+            .write_batch(&values, None, None)?;
+        col_writer.close()?;
+    }
+    
+    // Write actor id column
+    {
+        let mut col_writer = row_group_writer.next_column()?.unwrap();
+        col_writer.typed::<parquet::data_type::Int64Type>()
+            .write_batch(&buffer.actor_ids, None, None)?;
+        col_writer.close()?;
+    }
+    
+    // Write event id column
+    {
+        let mut col_writer = row_group_writer.next_column()?.unwrap();
+        let values: Vec<parquet::data_type::ByteArray> = buffer.ids.iter()
+            .map(|s| parquet::data_type::ByteArray::from(s.as_bytes()))
+            .collect();
+        col_writer.typed::<parquet::data_type::ByteArrayType>()
+            .write_batch(&values, None, None)?;
+        col_writer.close()?;
+    }
+    
+    for (_, values) in &buffer.extracted {
+        write_optional_string_column(&mut row_group_writer, values)?;
+    }
+    
     row_group_writer.close()?;
     buffer.clear();
     
     Ok(())
 }
 
-fn finalize_parquet_writers(writers: ParquetWriters) -> Result<()> {
-    let writers_map = Arc::try_unwrap(writers)
-        .map_err(|_| anyhow::anyhow!("Failed to extract writers"))?
-        .into_inner()
-        .unwrap();
-    
-    let spinner = ProgressBar::new(writers_map.len() as u64);
+/// Flushes and closes every open writer, returning summaries of all bucket files written.
+/// With remote output the closed files are uploaded and their summaries name the uploads.
+fn finalize_parquet_writers(writers: &ParquetWriters, config: &WriterConfig, progress: &MultiProgress) -> Result<Vec<OutputFileSummary>> {
+    let spinner = progress.add(ProgressBar::new(writers.open_writers() as u64));
     spinner.set_message("Finalizing parquet files");
     spinner.set_style(ProgressStyle::default_bar()
         .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>3}/{len:3} {msg}")
         .unwrap()
         .progress_chars("##-"));
     
-    for (bucket_key, mut writer_buffer) in writers_map {
-        // Flush any remaining data in the buffer
-        if writer_buffer.1.len() > 0 {
-            flush_buffer_to_parquet(&mut writer_buffer)?;
+    let mut finalized = Vec::new();
+    for pool in &writers.pools {
+        let mut pool = pool.lock().unwrap();
+        let bucket_keys: Vec<String> = pool.writers.keys().cloned().collect();
+        for bucket_key in bucket_keys {
+            // Flushes any remaining buffered rows and closes the writer
+            pool.close_writer(&bucket_key)?;
+            spinner.inc(1);
+        }
+        finalized.append(&mut pool.finalized);
+    }
+    // Listed in a fixed order, so the finalized files are listed the same way every run
+    finalized.sort_by(|a, b| a.path.cmp(&b.path));
+    
+    spinner.finish_and_clear();
+    progress.remove(&spinner);
+    if config.wrap_zstd {
+        for file in &mut finalized {
+            timing::time(Phase::Write, || wrap_in_zstd(file))?;
+        }
+    }
+    if let Some(remote) = &config.remote_output {
+        // Evicted files were closed earlier and are uploaded along with the rest
+        for file in &mut finalized {
+            let relative = config.remote_relative_path(&file.path)?.to_string();
+            remote.upload(Path::new(&file.path), &relative)?;
+            std::fs::remove_file(&file.path)
+                .context(format!("Failed to remove uploaded file {}", file.path))?;
+            file.path = format!("{}/{}", remote.url(), relative);
+        }
+    }
+    Ok(finalized)
+}
+
+/// Compresses a closed output file into a zstd frame next to it, named like the input
+/// files (`.parquet.zst`), and removes the plain file. The frame is written to a
+/// temporary file and renamed into place, so a crash leaves either file but never a
+/// truncated frame.
+fn wrap_in_zstd(file: &mut OutputFileSummary) -> Result<()> {
+    let wrapped_path = format!("{}.zst", file.path);
+    let tmp_path = format!("{}.tmp", wrapped_path);
+    let mut input = File::open(&file.path)
+        .context(format!("Failed to open {}", file.path))?;
+    let output = File::create(&tmp_path)
+        .context(format!("Failed to create {}", tmp_path))?;
+    let mut encoder = zstd::Encoder::new(BufWriter::new(output), 0)?;
+    std::io::copy(&mut input, &mut encoder)
+        .context(format!("Failed to compress {}", file.path))?;
+    encoder.finish()?.flush()
+        .context(format!("Failed to write {}", tmp_path))?;
+    std::fs::rename(&tmp_path, &wrapped_path)
+        .context(format!("Failed to move {} into place", wrapped_path))?;
+    std::fs::remove_file(&file.path)
+        .context(format!("Failed to remove {}", file.path))?;
+    file.bytes = std::fs::metadata(&wrapped_path)
+        .context(format!("Failed to read size of {}", wrapped_path))?
+        .len();
+    file.path = wrapped_path;
+    Ok(())
+}
+
+/// Whether a file in an output directory is a parquet file, either plain or compressed
+/// by --wrap-zstd
+fn is_parquet_output(file_name: &str) -> bool {
+    file_name.ends_with(".parquet") || file_name.ends_with(".parquet.zst")
+}
+
+/// Rows and uncompressed bytes a dry run expects in one bucket
+#[derive(Debug, Default, Serialize)]
+struct BucketEstimate {
+    bucket: String,
+    rows: u64,
+    bytes: u64,
+}
+
+/// What a dry run expects a real run to produce
+#[derive(Debug, Serialize)]
+struct DryRunReport {
+    input_files: usize,
+    input_rows: u64,
+    /// Whether every row was routed rather than a sample of each file
+    full_read: bool,
+    rows_routed: u64,
+    estimated_rows_written: u64,
+    estimated_bytes_written: u64,
+    bucket_count: usize,
+    /// Largest buckets by row count
+    top_buckets: Vec<BucketEstimate>,
+}
+
+/// Dry run settings taken from the command line
+struct DryRunOptions<'a> {
+    sample_rows: usize,
+    full: bool,
+    top: usize,
+    json_path: Option<&'a Path>,
+    /// Where the estimated --repo-counts are written
+    repo_counts_path: Option<&'a Path>,
+}
+
+/// Prints what a run over these files would produce. Rows are routed exactly like a
+/// real run; unless every row is read, the per-bucket numbers are scaled up from a
+/// sample of each file's first rows.
+fn print_dry_run_plan(files: &[String], ctx: &RunContext, options: &DryRunOptions) -> Result<()> {
+    let mut total_rows = 0u64;
+    let mut routed = 0u64;
+    // Scaled row and byte counts per bucket
+    let mut buckets: HashMap<String, (f64, f64)> = HashMap::new();
+    // Ids already routed into each bucket, so a dry run drops the same duplicates
+    let mut seen_ids: HashSet<(String, String)> = HashSet::new();
+    let mut repo_counts = RepoCounts::default();
+    
+    println!("Dry run: nothing will be written");
+    for file_path in files {
+        let (local_path, _staged) = stage_input(file_path, ctx.input_remote)?;
+        let input = open_input_events(&local_path, ctx.input_format)
+            .context(format!("Failed to open {}", file_path))?;
+        let limit = if options.full { usize::MAX } else { options.sample_rows };
+        
+        let mut file_routed = 0u64;
+        let mut file_buckets: HashMap<String, (u64, u64)> = HashMap::new();
+        let mut file_repo_counts = RepoCounts::default();
+        for event in input.events.take(limit) {
+            file_routed += 1;
+            // Unreadable rows count as routed but not kept
+            let Ok(Some(mut event)) = event else { continue };
+            apply_renames(&mut event, ctx);
+            if let RowRoute::Bucket(bucket_key) = route_event(&event, ctx)? {
+                if ctx.seen_ids.is_some() && !seen_ids.insert((bucket_key.clone(), event.id.clone())) {
+                    continue;
+                }
+                let entry = file_buckets.entry(bucket_key).or_insert((0, 0));
+                entry.0 += 1;
+                entry.1 += event.data_bytes() as u64;
+                if ctx.count_repos {
+                    file_repo_counts.add(&event.repo_name, &event.event_type);
+                }
+            }
+        }
+        
+        let row_count = match input.row_count {
+            Some(row_count) => row_count,
+            None if options.full => file_routed,
+            // Formats without metadata are counted by line
+            None => open_json_reader(&local_path)?
+                .lines()
+                .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+                .count() as u64,
+        };
+        total_rows += row_count;
+        routed += file_routed;
+        
+        let scale = if file_routed > 0 { row_count as f64 / file_routed as f64 } else { 0.0 };
+        let file_kept: u64 = file_buckets.values().map(|(rows, _)| rows).sum();
+        for (bucket_key, (rows, bytes)) in file_buckets {
+            let entry = buckets.entry(bucket_key).or_insert((0.0, 0.0));
+            entry.0 += rows as f64 * scale;
+            entry.1 += bytes as f64 * scale;
+        }
+        repo_counts.merge_scaled(file_repo_counts, scale);
+        println!("  {}: {} rows, {} of {} routed rows kept", file_path, row_count, file_kept, file_routed);
+    }
+    
+    let mut estimates: Vec<BucketEstimate> = buckets.into_iter()
+        .map(|(bucket, (rows, bytes))| BucketEstimate { bucket, rows: rows.round() as u64, bytes: bytes.round() as u64 })
+        .collect();
+    estimates.sort_by(|a, b| b.rows.cmp(&a.rows).then_with(|| a.bucket.cmp(&b.bucket)));
+    
+    let report = DryRunReport {
+        input_files: files.len(),
+        input_rows: total_rows,
+        full_read: options.full,
+        rows_routed: routed,
+        estimated_rows_written: estimates.iter().map(|estimate| estimate.rows).sum(),
+        estimated_bytes_written: estimates.iter().map(|estimate| estimate.bytes).sum(),
+        bucket_count: estimates.len(),
+        top_buckets: estimates.into_iter().take(options.top).collect(),
+    };
+    
+    println!("Input files: {}", report.input_files);
+    println!("Input rows: {}", report.input_rows);
+    println!("Estimated rows written: {}", report.estimated_rows_written);
+    println!("Estimated uncompressed bytes: {}", report.estimated_bytes_written);
+    if report.full_read {
+        println!("Buckets: {}", report.bucket_count);
+    } else {
+        println!("Buckets seen in sample: {} (a lower bound for the full run)", report.bucket_count);
+    }
+    if !report.top_buckets.is_empty() {
+        println!("Largest buckets:");
+        for estimate in &report.top_buckets {
+            println!("  {:<40} {:>10} rows {:>14} bytes", estimate.bucket, estimate.rows, estimate.bytes);
         }
-        // Ensure the writer is properly closed
-        let writer = writer_buffer.0;
-        writer.close()?;
-        spinner.inc(1);
     }
     
-    spinner.finish_with_message("All parquet files finalized");
+    if let Some(json_path) = options.json_path {
+        std::fs::write(json_path, serde_json::to_string_pretty(&report)?)
+            .context(format!("Failed to write dry run report: {}", json_path.display()))?;
+    }
+    if let Some(repo_counts_path) = options.repo_counts_path {
+        repo_counts.write_csv(repo_counts_path)?;
+    }
     Ok(())
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    let progress = MultiProgress::new();
+    logging::init(args.verbose, args.quiet, args.progress, &progress)?;
+    
+    match &args.command {
+        Some(Command::Download(download_args)) => return download::run(download_args),
+        Some(Command::Compact(compact_args)) => return compact::run(compact_args),
+        Some(Command::Verify(verify_args)) => return verify::run(verify_args),
+        Some(Command::Query(query_args)) => return query::run(query_args),
+        None => {}
+    }
     
-    let timeframe = &args.timeframe;
+    // Given unless the inputs are listed explicitly, which clap enforces
+    let timeframe = args.timeframe.as_deref();
+    let listed_inputs = !args.files.is_empty() || args.file_list.is_some();
+    let writer_config = WriterConfig::from_args(&args)?;
+    if args.verify && (writer_config.remote_output.is_some() || writer_config.output_format != OutputFormat::Parquet) {
+        return Err(anyhow::anyhow!("--verify needs parquet files in a local --output-dir"));
+    }
     
-    let timeframe_patterns = parse_timeframe(timeframe)?;
-    let parquet_files = find_parquet_files(&timeframe_patterns)?;
+    let timeframe_patterns = timeframe.map(parse_timeframe).transpose()?.unwrap_or_default();
+    let resolution = timeframe.map(timeframe_resolution).transpose()?;
+    let row_filter = RowFilter {
+        timeframe: resolution.filter(|_| args.clip_to_timeframe).map(|resolution| (resolution, timeframe_patterns[0].clone())),
+        include_event_types: args.event_types.iter().cloned().collect(),
+        exclude_event_types: args.exclude_event_types.iter().cloned().collect(),
+        repos: {
+            let mut repos = args.repos.clone();
+            if let Some(repo_file) = &args.repo_file {
+                repos.extend(read_list_file(repo_file, "repo file")?);
+            }
+            repos
+        },
+        repo_shard: args.shard.filter(|_| args.shard_by == ShardBy::Repo),
+        sample: args.sample,
+        bot_logins: match &args.bot_logins_file {
+            Some(bot_logins_file) => Some(read_list_file(bot_logins_file, "bot logins file")?.into_iter().collect()),
+            None => args.no_bots.then(HashSet::new),
+        },
+    };
+    if args.sample.is_some_and(|fraction| !(fraction > 0.0 && fraction <= 1.0)) {
+        return Err(anyhow::anyhow!("--sample must be greater than 0 and at most 1"));
+    }
+    let row_limit = args.limit.map(|max| RowLimit { max, written: AtomicU64::new(0) });
+    let renames = args.rename_map.as_deref().map(RenameMap::load).transpose()?;
+    // Listed inputs are local paths, wherever --input-dir points
+    let input_remote = if listed_inputs { None } else { RemoteDir::parse(&args.input_dir)? };
+    let parquet_files = if listed_inputs {
+        list_input_files(&args.files, args.file_list.as_deref(), args.input_format)?
+    } else {
+        find_parquet_files(&args.input_dir, &timeframe_patterns, args.input_format, input_remote.as_ref())?
+    };
     
     if parquet_files.is_empty() {
-        return Err(anyhow::anyhow!("No parquet files found for timeframe: {}", timeframe));
+        return Err(match timeframe.filter(|_| !listed_inputs) {
+            Some(timeframe) => anyhow::anyhow!("No input files found for timeframe: {}", timeframe),
+            None => anyhow::anyhow!("The file list is empty"),
+        });
+    }
+    
+    // Input files are assigned by name so shards agree even when their input
+    // directories are mounted at different paths
+    let parquet_files: Vec<String> = match args.shard {
+        Some(shard) if args.shard_by == ShardBy::File => parquet_files.into_iter()
+            .filter(|file_path| shard.owns(&Path::new(file_path).file_name().unwrap().to_string_lossy()))
+            .collect(),
+        _ => parquet_files,
+    };
+    
+    // With remote output these are local copies in the staging directory
+    let output_remote = writer_config.remote_output.as_deref();
+    let output_dir = Path::new(&writer_config.output_dir);
+    let manifest_path = output_dir.join(shard_file_name(MANIFEST_FILE, args.shard));
+    let summary_path = output_dir.join(shard_file_name(SUMMARY_FILE, args.shard));
+    
+    // Taken before anything in the output directory is removed or rewritten. Shards
+    // write files of their own, so each has its own lock. Remote output is staged in a
+    // directory private to this process and isn't locked
+    let _output_lock = if args.dry_run || output_remote.is_some() {
+        None
+    } else {
+        create_dir_all(output_dir)
+            .context(format!("Failed to create output directory {}", output_dir.display()))?;
+        Some(OutputLock::acquire(&output_dir.join(shard_file_name(LOCK_FILE, args.shard)), args.force)?)
+    };
+    
+    let mut manifest = if args.resume || args.skip_existing {
+        Manifest::load(&manifest_path, output_remote)?
+    } else {
+        Manifest::default()
+    };
+    
+    if args.skip_existing {
+        // A changed input is redone as if it had never completed
+        let changed: HashSet<String> = parquet_files.iter()
+            .filter(|file_path| manifest.completed_inputs.contains(file_path))
+            .filter(|file_path| manifest.input_fingerprints.get(*file_path).copied() != InputFingerprint::of(file_path, input_remote.as_ref()).ok())
+            .cloned()
+            .collect();
+        if !changed.is_empty() {
+            info!("{} inputs changed since they were processed and will be redone", changed.len());
+        }
+        manifest.completed_inputs.retain(|file_path| !changed.contains(file_path));
+        manifest.input_fingerprints.retain(|file_path, _| !changed.contains(file_path));
+    }
+    
+    // An input's rows share files with the rest of its checkpoint, so redoing it removes
+    // them and the other inputs of the checkpoint have to be redone as well
+    let redo_parts: HashSet<String> = parquet_files.iter()
+        .filter(|file_path| !manifest.completed_inputs.contains(file_path))
+        .map(|file_path| manifest.part_of(file_path, args.shard))
+        .collect();
+    let completed_inputs = std::mem::take(&mut manifest.completed_inputs);
+    manifest.completed_inputs = completed_inputs.into_iter()
+        .filter(|file_path| !redo_parts.contains(&manifest.part_of(file_path, args.shard)))
+        .collect();
+    
+    let (up_to_date_inputs, parquet_files): (Vec<String>, Vec<String>) = parquet_files.into_iter()
+        .partition(|file_path| manifest.completed_inputs.contains(file_path));
+    let up_to_date_files = {
+        let parts: HashSet<String> = up_to_date_inputs.iter().map(|file_path| manifest.part_of(file_path, args.shard)).collect();
+        manifest.finalized_buckets.iter()
+            .filter(|path| {
+                let file_name = Path::new(path).file_name().unwrap_or_default().to_string_lossy();
+                part_name_of_output(&file_name, &parts, args.hive_partitions).is_some()
+            })
+            .count()
+    };
+    if !up_to_date_inputs.is_empty() {
+        info!("Skipping {} inputs already processed ({} output files up to date)", up_to_date_inputs.len(), up_to_date_files);
+    }
+    
+    if (args.resume || args.skip_existing) && !args.dry_run {
+        let removed = remove_incomplete_outputs(output_dir, &redo_parts, &mut manifest, args.hive_partitions, output_remote)?;
+        if removed > 0 {
+            info!("Removed {} output files of inputs being redone", removed);
+            manifest.save(&manifest_path, output_remote)?;
+        }
+    }
+    
+    match timeframe {
+        Some(timeframe) => info!("Processing {} parquet files for timeframe: {}", parquet_files.len(), timeframe),
+        None => info!("Processing {} listed parquet files", parquet_files.len()),
+    }
+    if let Some(shard) = args.shard {
+        info!("Shard {}/{}, split by {:?}", shard.index, shard.count, args.shard_by);
+    }
+    let total_rows = if input_remote.is_none() {
+        preflight_schemas(&parquet_files, args.input_format)?
+    } else {
+        None
+    };
+    
+    let total_stats = Mutex::new(ProcessStats::default());
+    let seen_ids: Option<SeenIds> = args.dedupe.then(|| Mutex::new(HashMap::new()));
+    let duplicates_by_file = Mutex::new(Vec::new());
+    let bad_rows_by_file = Mutex::new(Vec::new());
+    let failed_files = Mutex::new(Vec::new());
+    let verify_problems = Mutex::new(Vec::new());
+    let mut output_files = Vec::new();
+    let error_log = match args.error_log.as_ref().filter(|_| !args.dry_run) {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new().create(true).append(true).open(path)
+                .context(format!("Failed to open error log: {}", path.display()))?;
+            Some(Mutex::new(BufWriter::new(file)))
+        }
+        None => None,
+    };
+    if args.progress_rows == 0 {
+        return Err(anyhow::anyhow!("--progress-rows must be greater than zero"));
+    }
+    if args.checkpoint_every == 0 {
+        return Err(anyhow::anyhow!("--checkpoint-every must be greater than zero"));
+    }
+    let mut ctx = RunContext {
+        config: &writer_config,
+        filter: &row_filter,
+        seen_ids: seen_ids.as_ref(),
+        error_log: error_log.as_ref(),
+        strict: args.strict,
+        partition_by: args.partition_by,
+        split_by_event_type: args.split_by_event_type,
+        input_format: args.input_format,
+        input_remote: input_remote.as_ref(),
+        row_limit: row_limit.as_ref(),
+        count_repos: args.repo_counts.is_some(),
+        renames: renames.as_ref(),
+        progress: &progress,
+        progress_rows: (args.progress == ProgressFormat::Json).then_some(args.progress_rows),
+        overall_rows: None,
+    };
+    
+    if args.dry_run {
+        let options = DryRunOptions {
+            sample_rows: args.dry_run_sample_rows,
+            full: args.dry_run_full,
+            top: args.dry_run_top,
+            json_path: args.dry_run_json.as_deref(),
+            repo_counts_path: args.repo_counts.as_deref(),
+        };
+        let plan = print_dry_run_plan(&parquet_files, &ctx, &options);
+        if input_remote.is_some() {
+            let _ = std::fs::remove_dir_all(staging_dir("input"));
+        }
+        return plan;
     }
     
-    create_dir_all("work/archives-separated")?;
+    create_dir_all(output_dir)
+        .context(format!("Failed to create output directory {}", output_dir.display()))?;
     
-    println!("Processing {} parquet files for timeframe: {}", parquet_files.len(), timeframe);
+    if args.report_timing {
+        timing::enable();
+    }
     
-    let main_pb = ProgressBar::new(parquet_files.len() as u64);
-    main_pb.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}/{duration_precise}] {bar:40.cyan/blue} {pos:>3}/{len:3} {msg}")
-            .unwrap()
-            .progress_chars("##-")
-    );
-    main_pb.set_message("Processing parquet files");
+    // The first Ctrl-C stops reading and lets open writers be closed so their files
+    // stay readable; a second one exits immediately
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            lock::release_held();
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+        warn!("Interrupted, closing open output files (press Ctrl-C again to exit immediately)");
+    }).context("Failed to install Ctrl-C handler")?;
     
-    let parquet_writers: ParquetWriters = Arc::new(Mutex::new(HashMap::new()));
+    // With every input's row count known from its footer the bar counts rows, so its
+    // ETA covers the whole run; otherwise it counts files
+    let main_pb = match total_rows {
+        Some(total_rows) => {
+            let main_pb = progress.add(ProgressBar::new(total_rows)).with_style(ProgressStyle::default_bar()
+                .template("[{elapsed_precise}/{duration_precise}] {bar:40.cyan/blue} {human_pos}/{human_len} rows ({per_sec}, ETA {eta_precise}) {msg}")?
+                .progress_chars("##-"));
+            main_pb.set_message(format!("0/{} files", parquet_files.len()));
+            main_pb
+        }
+        None => {
+            let main_pb = progress.add(ProgressBar::new(parquet_files.len() as u64)).with_style(ProgressStyle::default_bar()
+                .template("[{elapsed_precise}/{duration_precise}] {bar:40.cyan/blue} {pos:>3}/{len:3} {msg}")?
+                .progress_chars("##-"));
+            main_pb.set_message("Processing parquet files");
+            main_pb
+        }
+    };
+    if total_rows.is_some() {
+        ctx.overall_rows = Some(&main_pb);
+    }
+    let json_progress = args.progress == ProgressFormat::Json;
+    let files_done = AtomicU64::new(0);
+    if json_progress {
+        ProgressEvent::Started { files_total: parquet_files.len() }.emit();
+    }
     
-    for file_path in &parquet_files {
-        main_pb.set_message(format!("Processing {}", Path::new(&file_path).file_name().unwrap().to_string_lossy()));
+    // Inputs are read in parallel a checkpoint at a time. Their rows go to the same
+    // writers, one file per bucket for the whole checkpoint, which are all closed at its
+    // end so its inputs can be recorded as completed
+    let mut checkpoints = BTreeMap::new();
+    for checkpoint in parquet_files.chunks(args.checkpoint_every) {
+        if INTERRUPTED.load(Ordering::Relaxed) || row_limit.as_ref().is_some_and(RowLimit::reached) {
+            break;
+        }
+        let part = output_part_name(&checkpoint[0], args.shard);
+        checkpoints.insert(part.clone(), checkpoint.to_vec());
+        // Saved before any file is opened, so a crash leaves a manifest that says which
+        // files a resumed run has to remove
+        for file_path in checkpoint {
+            manifest.input_parts.insert(file_path.clone(), part.clone());
+        }
+        manifest.save(&manifest_path, output_remote)?;
         
-        match process_parquet_file(&file_path, Arc::clone(&parquet_writers)) {
-            Ok(_) => {
-                main_pb.println(format!("✓ Successfully processed {}", file_path));
+        let parquet_writers = ParquetWriters::new(OutputTable::Events, &part);
+        let derived_writers = DerivedWriters::new(args.emit_commits, args.emit_pull_requests, &part);
+        let completed = Mutex::new(HashMap::new());
+        
+        let processed = checkpoint.par_iter().try_for_each(|file_path| -> Result<()> {
+            if INTERRUPTED.load(Ordering::Relaxed) || row_limit.as_ref().is_some_and(RowLimit::reached) {
+                return Ok(());
             }
-            Err(e) => {
-                main_pb.println(format!("✗ Failed to process {}: {}", file_path, e));
+            // Taken before reading, so a change made while the input is processed is
+            // noticed by the next run
+            let fingerprint = InputFingerprint::of(file_path, input_remote.as_ref()).ok();
+            let result = process_input_file_with_retries(file_path, &parquet_writers, &derived_writers, &ctx, args.retries);
+            
+            let done = files_done.fetch_add(1, Ordering::SeqCst) + 1;
+            if json_progress {
+                let (status, rows_processed, rows_written) = match &result {
+                    Ok(stats) if stats.interrupted || stats.limit_reached => (FileStatus::Stopped, stats.rows_read, stats.rows_written),
+                    Ok(stats) => (FileStatus::Ok, stats.rows_read, stats.rows_written),
+                    Err(_) => (FileStatus::Failed, 0, 0),
+                };
+                ProgressEvent::FileFinished {
+                    file: file_path,
+                    status,
+                    rows_processed,
+                    rows_written,
+                    files_done: done,
+                    files_total: parquet_files.len(),
+                }.emit();
+            }
+            
+            match result {
+                Ok(stats) if stats.interrupted || stats.limit_reached => {
+                    // Its rows are kept in the checkpoint's files, but the input isn't
+                    // recorded as completed so a resumed run processes it again
+                    let reason = if stats.interrupted { "Interrupted" } else { "Row limit reached" };
+                    warn!("- {} while processing {} ({} rows written)", reason, file_path, stats.rows_written);
+                    duplicates_by_file.lock().unwrap().push((file_path.clone(), stats.duplicates_dropped));
+                    if stats.bad_rows > 0 {
+                        bad_rows_by_file.lock().unwrap().push((file_path.clone(), stats.bad_rows));
+                    }
+                    total_stats.lock().unwrap().merge(stats);
+                }
+                Ok(stats) => {
+                    info!(
+                        "✓ Successfully processed {} ({} rows written, {} bad rows skipped)",
+                        file_path, stats.rows_written, stats.bad_rows
+                    );
+                    duplicates_by_file.lock().unwrap().push((file_path.clone(), stats.duplicates_dropped));
+                    if stats.bad_rows > 0 {
+                        bad_rows_by_file.lock().unwrap().push((file_path.clone(), stats.bad_rows));
+                    }
+                    total_stats.lock().unwrap().merge(stats);
+                    completed.lock().unwrap().insert(file_path.clone(), fingerprint);
+                }
+                Err(e) => {
+                    error!("✗ Failed to process {}: {:#}", file_path, e);
+                    failed_files.lock().unwrap().push((file_path.clone(), format!("{:#}", e)));
+                    if !args.keep_going {
+                        return Err(e.context(format!("Aborting after failure in {}", file_path)));
+                    }
+                }
+            }
+            
+            if total_rows.is_some() {
+                main_pb.set_message(format!("{}/{} files", done, parquet_files.len()));
+            } else {
+                main_pb.inc(1);
             }
+            Ok(())
+        });
+        
+        // Closed whatever happened to the inputs, so every file stays readable
+        let mut finalized = finalize_parquet_writers(&parquet_writers, &writer_config, &progress)?;
+        for writers in derived_writers.pools() {
+            finalized.extend(finalize_parquet_writers(writers, &writer_config, &progress)?);
+        }
+        if args.verify {
+            verify_problems.lock().unwrap().extend(verify::check_written_files(&finalized));
         }
         
-        main_pb.inc(1);
+        // Each file holds rows of several inputs, so the inputs are only recorded as
+        // completed when all of the checkpoint's are
+        let mut completed = completed.into_inner().unwrap();
+        let checkpoint_completed = completed.len() == checkpoint.len();
+        if checkpoint_completed {
+            for file_path in checkpoint {
+                manifest.completed_inputs.push(file_path.clone());
+                if let Some(fingerprint) = completed.remove(file_path).flatten() {
+                    manifest.input_fingerprints.insert(file_path.clone(), fingerprint);
+                }
+            }
+        }
+        manifest.finalized_buckets.extend(finalized.iter().map(|file| file.path.clone()));
+        manifest.save(&manifest_path, output_remote)?;
+        if json_progress {
+            ProgressEvent::Checkpoint {
+                part: &part,
+                inputs_completed: if checkpoint_completed { checkpoint.len() } else { 0 },
+                files_closed: finalized.len(),
+                bytes_written: finalized.iter().map(|file| file.bytes).sum(),
+            }.emit();
+        }
+        output_files.extend(finalized);
+        processed?;
+    }
+    
+    let interrupted = INTERRUPTED.load(Ordering::SeqCst);
+    if interrupted {
+        main_pb.abandon_with_message("Interrupted");
+    } else {
+        main_pb.finish_with_message("All parquet files processed");
+    }
+    manifest.save(&manifest_path, output_remote)?;
+    
+    if let Some(error_log) = error_log {
+        error_log.into_inner().unwrap().flush().context("Failed to write to error log")?;
+    }
+    
+    let total_stats = total_stats.into_inner().unwrap();
+    if json_progress {
+        ProgressEvent::Finished {
+            files_total: parquet_files.len(),
+            files_failed: failed_files.lock().unwrap().len(),
+            rows_written: total_stats.rows_written,
+        }.emit();
+    }
+    let mut files = output_files;
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    let bucket_count = files.iter().map(|file| &file.bucket).collect::<HashSet<_>>().len();
+    let written_event_types: BTreeMap<String, u64> = total_stats.written_event_types.clone().into_iter().collect();
+    
+    info!("Rows read: {}", total_stats.rows_read);
+    info!("Rows written: {}", total_stats.rows_written);
+    info!("Rows skipped: {}", total_stats.rows_read - total_stats.rows_written);
+    info!("Buckets written: {} ({} files)", bucket_count, files.len());
+    if !written_event_types.is_empty() {
+        info!("Rows written by event type:");
+        let width = written_event_types.keys().map(String::len).max().unwrap_or(0);
+        for (event_type, count) in &written_event_types {
+            info!("  {:<width$}  {:>10}", event_type, count, width = width);
+        }
+    }
+    if total_stats.bad_rows > 0 {
+        info!("Bad rows skipped: {}", total_stats.bad_rows);
+        let mut bad_rows_by_file = bad_rows_by_file.into_inner().unwrap();
+        bad_rows_by_file.sort();
+        for (file_path, count) in bad_rows_by_file {
+            info!("  {}: {}", file_path, count);
+        }
+    }
+    if !total_stats.filtered_event_types.is_empty() {
+        info!("Rows skipped by event type filter:");
+        let mut filtered: Vec<_> = total_stats.filtered_event_types.iter().collect();
+        filtered.sort();
+        for (event_type, count) in filtered {
+            info!("  {}: {}", event_type, count);
+        }
+    }
+    if args.emit_commits {
+        info!("Commits written: {}", total_stats.commits_written);
+    }
+    if args.emit_pull_requests {
+        info!("Pull request rows written: {}", total_stats.pull_requests_written);
+    }
+    if total_stats.bad_payloads > 0 {
+        info!("Events written without derived rows (payload not readable): {}", total_stats.bad_payloads);
+    }
+    if total_stats.filtered_repo_rows > 0 {
+        info!("Rows skipped by repository filter: {}", total_stats.filtered_repo_rows);
+    }
+    let bot_rows: u64 = total_stats.bot_rows.values().sum();
+    let mut top_bot_logins: Vec<(String, u64)> = total_stats.bot_rows.into_iter().collect();
+    top_bot_logins.sort_by(|(a_login, a_count), (b_login, b_count)| b_count.cmp(a_count).then(a_login.cmp(b_login)));
+    top_bot_logins.truncate(TOP_BOT_LOGINS);
+    if args.no_bots {
+        info!("Rows excluded as bot traffic: {}", bot_rows);
+        for (actor_login, count) in &top_bot_logins {
+            info!("  {}: {}", actor_login, count);
+        }
+    }
+    if total_stats.other_shard_rows > 0 {
+        info!("Rows left to other shards: {}", total_stats.other_shard_rows);
+    }
+    if let Some(fraction) = args.sample {
+        info!("Rows left out by --sample {}: {} (the output is a sample, not complete data)", fraction, total_stats.sampled_out_rows);
+    }
+    if total_stats.limit_reached {
+        info!("Stopped after --limit {} rows (the output is not complete data)", args.limit.unwrap_or_default());
+    }
+    if total_stats.clipped_rows > 0 {
+        info!("Rows outside the timeframe clipped: {}", total_stats.clipped_rows);
+    }
+    if args.dedupe {
+        info!("Duplicate events dropped: {}", total_stats.duplicates_dropped);
+        let mut duplicates_by_file = duplicates_by_file.into_inner().unwrap();
+        duplicates_by_file.sort();
+        for (file_path, count) in duplicates_by_file {
+            info!("  {}: {}", file_path, count);
+        }
     }
     
-    main_pb.finish_with_message("All parquet files processed");
+    if let Some(repo_counts_path) = &args.repo_counts {
+        total_stats.repo_counts.write_csv(repo_counts_path)?;
+        info!("Repository counts written to {}", repo_counts_path.display());
+    }
+    
+    let timing = args.report_timing.then(PhaseTimings::collect);
+    if let Some(timing) = &timing {
+        timing.print();
+    }
+    
+    let mut failed_files = failed_files.into_inner().unwrap();
+    failed_files.sort();
+    
+    let summary = RunSummary {
+        tool_version: env!("CARGO_PKG_VERSION"),
+        timeframe,
+        shard: args.shard,
+        shard_by: args.shard.map(|_| args.shard_by),
+        partial: interrupted,
+        sample: args.sample,
+        limit: args.limit,
+        limit_reached: total_stats.limit_reached,
+        input_files: parquet_files.len(),
+        up_to_date_inputs,
+        up_to_date_files,
+        failed_inputs: failed_files.iter().map(|(file_path, _)| file_path.clone()).collect(),
+        checkpoint_every: args.checkpoint_every,
+        checkpoints,
+        rows_read: total_stats.rows_read,
+        rows_skipped: total_stats.rows_read - total_stats.rows_written,
+        bucket_count,
+        event_types: written_event_types,
+        rows_written: files.iter().filter(|file| file.table == OutputTable::Events).map(|file| file.rows).sum(),
+        commits_written: args.emit_commits.then_some(total_stats.commits_written),
+        pull_requests_written: args.emit_pull_requests.then_some(total_stats.pull_requests_written),
+        bad_payloads: (args.emit_commits || args.emit_pull_requests).then_some(total_stats.bad_payloads),
+        bot_rows: args.no_bots.then_some(bot_rows),
+        top_bot_logins: args.no_bots.then_some(top_bot_logins),
+        bytes_written: files.iter().map(|file| file.bytes).sum(),
+        timing,
+        files,
+    };
+    summary.save(&summary_path, output_remote)?;
+    
+    // Everything in the staging directories has been uploaded or was temporary
+    if output_remote.is_some() {
+        std::fs::remove_dir_all(output_dir)
+            .context(format!("Failed to remove staging directory {}", output_dir.display()))?;
+    }
+    if input_remote.is_some() {
+        let _ = std::fs::remove_dir_all(staging_dir("input"));
+    }
+    
+    if interrupted {
+        warn!("✗ Run interrupted; output files written so far were closed and are readable");
+        lock::release_held();
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
+    
+    if !failed_files.is_empty() {
+        error!("✗ {} of {} input files failed:", failed_files.len(), parquet_files.len());
+        for (file_path, error) in &failed_files {
+            error!("  {}: {}", file_path, error);
+        }
+        return Err(anyhow::anyhow!("{} input files failed to process", failed_files.len()));
+    }
     
-    println!("Finalizing parquet files...");
-    finalize_parquet_writers(parquet_writers)?;
+    if args.verify {
+        let mut verify_problems = verify_problems.into_inner().unwrap();
+        if !verify_problems.is_empty() {
+            verify_problems.sort();
+            error!("✗ Output verification found {} problems:", verify_problems.len());
+            for problem in &verify_problems {
+                error!("  {}", problem);
+            }
+            return Err(anyhow::anyhow!("Output verification found {} problems", verify_problems.len()));
+        }
+        info!("✓ Verified {} output files", summary.files.len());
+    }
     
-    println!("✓ All processing complete!");
+    info!("✓ All processing complete!");
     
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event_with_payload(payload: String) -> EventRow {
+        EventRow {
+            id: "1".to_string(),
+            event_type: "PushEvent".to_string(),
+            repo_name: "owner/repo".to_string(),
+            payload,
+            created_at: 0,
+            actor_login: "octocat".to_string(),
+            actor_id: 1,
+            extracted: Vec::new(),
+        }
+    }
+
+    fn writer_config(args: &[&str]) -> WriterConfig {
+        let args = Args::try_parse_from(["archive", "2024-01"].into_iter().chain(args.iter().copied())).unwrap();
+        WriterConfig::from_args(&args).unwrap()
+    }
+
+    #[test]
+    fn buffer_rows_alone_decides_flushing_by_default() {
+        let config = writer_config(&[]);
+        assert_eq!(config.buffer_bytes, None);
+        let mut buffer = Buffered::Events(RowBuffer::new(&[]));
+        buffer.add_row(OutputRow::Event(event_with_payload("x".repeat(100 * 1024 * 1024))));
+        assert!(!config.should_flush(&buffer));
+        for _ in 1..config.buffer_rows {
+            buffer.add_row(OutputRow::Event(event_with_payload(String::new())));
+        }
+        assert!(config.should_flush(&buffer));
+    }
+
+    #[test]
+    fn single_gigantic_payload_flushes_promptly() {
+        let config = writer_config(&["--buffer-bytes", "1048576"]);
+        let mut buffer = Buffered::Events(RowBuffer::new(&[]));
+        buffer.add_row(OutputRow::Event(event_with_payload("x".repeat(16 * 1024 * 1024))));
+        assert_eq!(buffer.len(), 1);
+        assert!(config.should_flush(&buffer));
+    }
+
+    #[test]
+    fn created_at_before_the_epoch_is_an_error() {
+        assert!(format_created_at(-1, TimeframeResolution::Month).is_err());
+        assert!(format_created_at(i64::MIN, TimeframeResolution::Year).is_err());
+        assert_eq!(format_created_at(0, TimeframeResolution::Month).unwrap(), "1970-01");
+        assert_eq!(format_created_at(1_704_067_199_999, TimeframeResolution::Day).unwrap(), "2023-12-31");
+    }
+
+    #[test]
+    fn zero_buffer_bytes_is_rejected() {
+        let args = Args::try_parse_from(["archive", "2024-01", "--buffer-bytes", "0"]).unwrap();
+        assert!(WriterConfig::from_args(&args).is_err());
+    }
+
+    fn prefix_bucket(repo_name: &str) -> String {
+        get_bucket_key(repo_name, "2024-01", None, PartitionStrategy::Prefix3, false)
+    }
+
+    #[test]
+    fn multibyte_repo_names_bucket_by_character() {
+        assert_eq!(prefix_bucket("日本語org/repo"), "%E6%97%A5/%E6%9C%AC/%E8%AA%9E/2024-01");
+        assert_eq!(prefix_bucket("日本語org/repo"), prefix_bucket("日本語org/other"));
+        assert_eq!(prefix_bucket("ab"), "a/b/2024-01");
+        assert_eq!(prefix_bucket(""), "_/2024-01");
+    }
+
+    #[test]
+    fn emoji_and_windows_separators_are_escaped() {
+        assert_eq!(prefix_bucket("x"), "x/2024-01");
+        assert_eq!(prefix_bucket("🦀rs/repo"), "%F0%9F%A6%80/r/s/2024-01");
+        assert_eq!(prefix_bucket("a\\:b/repo"), "a/%5C/%3A/2024-01");
+        assert_eq!(sanitize_path_component("c:\\x"), "c%3A%5Cx");
+    }
+
+    #[test]
+    fn sanitized_components_are_safe_and_distinct() {
+        assert_eq!(prefix_bucket(".github/docs"), "%2E/g/i/2024-01");
+        assert_eq!(prefix_bucket("-foo/bar"), "-/f/o/2024-01");
+        assert_eq!(sanitize_path_component(".github"), "%2Egithub");
+        assert_eq!(sanitize_path_component("back\\slash"), "back%5Cslash");
+        assert_eq!(sanitize_path_component("nul"), "nu%6C");
+        
+        // Escapes can't collide with a name that is spelled like them
+        let names = [".", "%2E", "%", "%25", "/", "%2F", "\\", "%5C", "nul", "nu%6C", "nu%256C"];
+        let sanitized: HashSet<String> = names.iter().map(|name| sanitize_path_component(name)).collect();
+        assert_eq!(sanitized.len(), names.len());
+    }
+
+    #[test]
+    fn many_small_buckets_stay_under_the_buffer_cap() {
+        let output_dir = std::env::temp_dir().join(format!("archive-buffer-cap-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let config = writer_config(&["--max-buffer-mb", "1", "--output-dir", output_dir.to_str().unwrap()]);
+        let cap = config.max_buffer_bytes.unwrap();
+        let writers = ParquetWriters::new(OutputTable::Events, "2024-01-01-0");
+        
+        // Rows are spread over so many buckets that none fills its buffer by row count,
+        // while together they hold several times the cap
+        let (buckets, rows) = (500, 3_000);
+        let mut peak = 0;
+        for n in 0..rows {
+            let repo_name = format!("owner{}/repo", n % buckets);
+            let bucket_key = get_bucket_key(&repo_name, "2024-01", None, PartitionStrategy::Repo, false);
+            let event = EventRow { id: n.to_string(), repo_name, ..event_with_payload("x".repeat(2048)) };
+            let row_bytes = event.data_bytes();
+            write_row_to_parquet(&writers, &bucket_key, event, &config, None).unwrap();
+            let buffered = writers.totals.buffered_bytes.load(Ordering::Relaxed);
+            assert!(buffered <= cap + row_bytes, "{} bytes buffered over a cap of {}", buffered, cap);
+            peak = peak.max(buffered);
+        }
+        assert!(peak > cap / 2);
+        
+        let progress = MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden());
+        let files = finalize_parquet_writers(&writers, &config, &progress).unwrap();
+        assert_eq!(writers.totals.buffered_bytes.load(Ordering::Relaxed), 0);
+        let rows_read_back: i64 = files.iter()
+            .map(|file| SerializedFileReader::new(File::open(&file.path).unwrap()).unwrap().metadata().file_metadata().num_rows())
+            .sum();
+        assert_eq!(rows_read_back, rows as i64);
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+}
@@ -1,6 +1,9 @@
+use std::sync::Once;
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::gh::{IssueComment, Label, Milestone, PullRequest, PushEventPayload, User};
+use crate::gh::{GitHubEvent, GitHubEventType, IssueComment, Label, PullRequest, PullRequestReview, PullRequestReviewComment, PushEventPayload};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TrackedPullRequest {
@@ -30,34 +33,213 @@ impl TrackedPullRequest {
         });
 
         if let Some(event) = event {
-            if let TrackedEvent::Comment(comment_event) = event {
+            // Archive events can arrive out of order, so never replace a newer edit
+            if let TrackedEvent::Comment(comment_event) = event
+                && is_newer_edit(&comment_event.comment.updated_at, &comment.updated_at) {
                 comment_event.comment = comment;
             }
         } else {
             self.events.push(TrackedEvent::from_comment(comment));
         }
     }
+
+    pub fn accept_review(&mut self, review: PullRequestReview) {
+        let event = self.events.iter_mut().find(|event| {
+            if let TrackedEvent::Review(review_event) = event {
+                review_event.review.id == review.id
+            } else {
+                false
+            }
+        });
+
+        if let Some(TrackedEvent::Review(review_event)) = event {
+            review_event.review = review;
+        } else {
+            self.events.push(TrackedEvent::Review(Box::new(ReviewEvent {
+                timestamp: parse_timestamp(&review.submitted_at),
+                review,
+            })));
+        }
+    }
+
+    pub fn accept_review_comment(&mut self, comment: PullRequestReviewComment) {
+        let event = self.events.iter_mut().find(|event| {
+            if let TrackedEvent::ReviewComment(comment_event) = event {
+                comment_event.comment.id == comment.id
+            } else {
+                false
+            }
+        });
+
+        if let Some(TrackedEvent::ReviewComment(comment_event)) = event {
+            comment_event.comment = comment;
+        } else {
+            self.events.push(TrackedEvent::ReviewComment(Box::new(ReviewCommentEvent {
+                timestamp: parse_timestamp(&comment.created_at),
+                comment,
+            })));
+        }
+    }
+
+    /// Records an archive event belonging to this pull request. Events of types that
+    /// aren't tracked, or whose payload fails to parse, are ignored.
+    pub fn apply_event(&mut self, event: &GitHubEvent) {
+        match event.parse_typed() {
+            Ok(GitHubEventType::IssueCommentEvent(payload)) if payload.action != "deleted" => {
+                self.accept_comment_edit(payload.comment);
+            }
+            Ok(GitHubEventType::PullRequestReviewEvent(payload)) => {
+                let payload = *payload;
+                self.update_from(payload.pull_request);
+                self.accept_review(payload.review);
+            }
+            Ok(GitHubEventType::PullRequestReviewCommentEvent(payload)) => {
+                let payload = *payload;
+                self.update_from(payload.pull_request);
+                if payload.action != "deleted" {
+                    self.accept_review_comment(payload.comment);
+                }
+            }
+            Ok(GitHubEventType::PullRequestEvent(payload)) => {
+                let payload = *payload;
+                self.update_from(payload.pull_request);
+                let label_event = payload.label.map(|label| LabelEvent {
+                    label,
+                    timestamp: parse_timestamp(&event.created_at),
+                });
+                match (payload.action.as_str(), label_event) {
+                    ("labeled", Some(label_event)) => self.events.push(TrackedEvent::Labeled(label_event)),
+                    ("unlabeled", Some(label_event)) => self.events.push(TrackedEvent::Unlabeled(label_event)),
+                    _ => {}
+                }
+            }
+            Ok(GitHubEventType::PushEvent(push)) => {
+                self.events.push(TrackedEvent::Push(PushEvent {
+                    push,
+                    timestamp: parse_timestamp(&event.created_at),
+                }));
+            }
+            _ => {}
+        }
+    }
+
+    /// Orders events chronologically. Events with equal timestamps keep their order.
+    pub fn sort_events(&mut self) {
+        self.events.sort_by_key(|event| event.timestamp());
+    }
 }
 
+/// An event on a pull request's timeline. Comments and reviews are boxed so they don't
+/// set the size of every variant
 #[derive(Debug, Serialize, Deserialize)]
 pub enum TrackedEvent {
-    Comment(CommentEvent),
+    Comment(Box<CommentEvent>),
     Push(PushEvent),
+    Review(Box<ReviewEvent>),
+    ReviewComment(Box<ReviewCommentEvent>),
+    Labeled(LabelEvent),
+    Unlabeled(LabelEvent),
+}
+
+/// Whether an incoming edit with `incoming_updated_at` should replace the stored version.
+/// If either timestamp can't be parsed, the incoming edit wins (with a one-time warning).
+fn is_newer_edit(stored_updated_at: &str, incoming_updated_at: &str) -> bool {
+    static WARN_UNPARSEABLE: Once = Once::new();
+
+    match (
+        DateTime::parse_from_rfc3339(stored_updated_at),
+        DateTime::parse_from_rfc3339(incoming_updated_at),
+    ) {
+        (Ok(stored), Ok(incoming)) => incoming > stored,
+        _ => {
+            WARN_UNPARSEABLE.call_once(|| {
+                log::warn!(
+                    "⚠ Unparseable comment updated_at ('{}' / '{}'); accepting edits in arrival order",
+                    stored_updated_at, incoming_updated_at
+                );
+            });
+            true
+        }
+    }
+}
+
+/// Parses a GitHub ISO 8601 timestamp. Unparseable values map to the Unix epoch so the
+/// event still sorts deterministically.
+fn parse_timestamp(timestamp: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .unwrap_or_default()
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CommentEvent {
     pub comment: IssueComment,
+    /// When the comment was created
+    pub timestamp: DateTime<Utc>,
 }
 
 impl TrackedEvent {
     pub fn from_comment(comment: IssueComment) -> Self {
-        Self::Comment(CommentEvent { comment })
+        Self::Comment(Box::new(CommentEvent {
+            timestamp: parse_timestamp(&comment.created_at),
+            comment,
+        }))
+    }
+
+    /// When the event happened
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        match self {
+            TrackedEvent::Comment(event) => event.timestamp,
+            TrackedEvent::Push(event) => event.timestamp,
+            TrackedEvent::Review(event) => event.timestamp,
+            TrackedEvent::ReviewComment(event) => event.timestamp,
+            TrackedEvent::Labeled(event) | TrackedEvent::Unlabeled(event) => event.timestamp,
+        }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PushEvent {
-    pub push: PushEventPayload
+    pub push: PushEventPayload,
+    /// Time of the archive event, since push payloads carry no timestamp of their own
+    pub timestamp: DateTime<Utc>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewEvent {
+    pub review: PullRequestReview,
+    /// When the review was submitted
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewCommentEvent {
+    pub comment: PullRequestReviewComment,
+    /// When the review comment was created
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LabelEvent {
+    pub label: Label,
+    /// Time of the archive event, since labels carry no timestamp of their own
+    pub timestamp: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_edits_do_not_replace_newer_ones() {
+        assert!(is_newer_edit("2024-01-01T00:00:00Z", "2024-01-01T00:05:00Z"));
+        assert!(!is_newer_edit("2024-01-01T00:05:00Z", "2024-01-01T00:00:00Z"));
+        assert!(!is_newer_edit("2024-01-01T00:05:00Z", "2024-01-01T00:05:00Z"));
+    }
+
+    #[test]
+    fn unparseable_edit_times_fall_back_to_arrival_order() {
+        assert!(is_newer_edit("", "2024-01-01T00:00:00Z"));
+        assert!(is_newer_edit("2024-01-01T00:05:00Z", "not a timestamp"));
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::Write;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// How a run shows its progress
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ProgressFormat {
+    /// Progress bars, drawn when stderr is a terminal
+    #[default]
+    Bars,
+    /// One JSON object per line on stderr, for job runners without a terminal. Log
+    /// lines are written to stderr as well and can be told apart by not being JSON
+    Json,
+}
+
+/// How an input file ended, in a `file_finished` event
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Ok,
+    Failed,
+    /// Cut short by Ctrl-C or --limit
+    Stopped,
+}
+
+/// A line written by --progress json. The `phase` field names the event.
+#[derive(Debug, Serialize)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum ProgressEvent<'a> {
+    /// Before the first input file is read
+    Started {
+        files_total: usize,
+    },
+    /// Every --progress-rows rows read from an input file
+    Processing {
+        file: &'a str,
+        rows_processed: u64,
+        /// Rows in the file according to its footer; absent for JSON inputs
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rows_total_estimate: Option<u64>,
+        buckets_open: usize,
+        /// Bytes written so far to the checkpoint's outputs, not counting buffered rows
+        bytes_written: u64,
+    },
+    /// After the last row of an input file is read. Its rows stay buffered or in open
+    /// files until the end of its checkpoint
+    FileFinished {
+        file: &'a str,
+        status: FileStatus,
+        rows_processed: u64,
+        rows_written: u64,
+        files_done: u64,
+        files_total: usize,
+    },
+    /// After the files of a checkpoint are closed
+    Checkpoint {
+        /// The part name the checkpoint's files are named after
+        part: &'a str,
+        /// Inputs recorded as completed: all of the checkpoint's, or none when one of
+        /// them failed or was cut short
+        inputs_completed: usize,
+        files_closed: usize,
+        bytes_written: u64,
+    },
+    /// After the last input file
+    Finished {
+        files_total: usize,
+        files_failed: usize,
+        rows_written: u64,
+    },
+}
+
+impl ProgressEvent<'_> {
+    /// Writes the event to stderr as one line. A closed stderr is ignored, like the
+    /// progress bars would.
+    pub fn emit(&self) {
+        if let Ok(line) = serde_json::to_string(self) {
+            let _ = writeln!(std::io::stderr().lock(), "{}", line);
+        }
+    }
+}
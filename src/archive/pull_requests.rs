@@ -0,0 +1,228 @@
+use std::fs::File;
+use std::io::Write;
+use std::sync::Arc;
+
+use anyhow::Result;
+use arrow_array::{ArrayRef, BooleanArray, Int64Array, RecordBatch, StringArray, TimestampMillisecondArray};
+use arrow_schema::SchemaRef;
+use chrono::DateTime;
+use parquet::file::writer::SerializedFileWriter;
+
+use crate::{EventRow, timestamp_json};
+use crate::columns::{
+    write_int64_column, write_optional_bool_column, write_optional_int64_column, write_optional_string_column,
+    write_string_column,
+};
+use crate::gh::PullRequestEventPayload;
+
+/// Schema of the per-bucket pull request tables written by --emit-pull-requests.
+/// created_at and merged_at are the pull request's own; event_created_at is when the
+/// event happened, as in the events table.
+pub const PULL_REQUESTS_SCHEMA: &str = r#"
+message schema {
+  REQUIRED BYTE_ARRAY repo_name (STRING);
+  REQUIRED INT64 pr_number;
+  REQUIRED BYTE_ARRAY action (STRING);
+  OPTIONAL BYTE_ARRAY title (STRING);
+  OPTIONAL BYTE_ARRAY state (STRING);
+  OPTIONAL BOOLEAN merged;
+  OPTIONAL INT64 additions;
+  OPTIONAL INT64 deletions;
+  OPTIONAL INT64 changed_files;
+  OPTIONAL BYTE_ARRAY user_login (STRING);
+  OPTIONAL INT64 created_at (TIMESTAMP(MILLIS,true));
+  OPTIONAL INT64 merged_at (TIMESTAMP(MILLIS,true));
+  REQUIRED BYTE_ARRAY base_ref (STRING);
+  REQUIRED BYTE_ARRAY head_ref (STRING);
+  REQUIRED INT64 event_created_at (TIMESTAMP(MILLIS,true));
+}
+"#;
+
+/// One PullRequestEvent, flattened. Fields the payload doesn't have are null
+#[derive(Debug, Clone)]
+pub struct PullRequestRow {
+    pub repo_name: String,
+    pub pr_number: i64,
+    pub action: String,
+    pub title: Option<String>,
+    pub state: Option<String>,
+    pub merged: Option<bool>,
+    pub additions: Option<i64>,
+    pub deletions: Option<i64>,
+    pub changed_files: Option<i64>,
+    pub user_login: Option<String>,
+    pub created_at: Option<i64>,
+    pub merged_at: Option<i64>,
+    pub base_ref: String,
+    pub head_ref: String,
+    pub event_created_at: i64,
+}
+
+impl PullRequestRow {
+    /// Approximate in-memory size of the row's data, used to bound buffer sizes
+    pub fn data_bytes(&self) -> usize {
+        let strings = [&self.title, &self.state, &self.user_login];
+        self.repo_name.len() + self.action.len() + self.base_ref.len() + self.head_ref.len()
+            + strings.iter().map(|s| s.as_ref().map_or(0, String::len)).sum::<usize>()
+            + 7 * std::mem::size_of::<i64>() + 1
+    }
+}
+
+/// Timestamps in payloads are RFC 3339 strings; one that doesn't parse is left null.
+fn timestamp_millis(timestamp: Option<&str>) -> Option<i64> {
+    timestamp
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+        .map(|timestamp| timestamp.timestamp_millis())
+}
+
+/// The table row of a PullRequestEvent's payload.
+pub fn pull_request_of_event(event: &EventRow, payload: PullRequestEventPayload) -> PullRequestRow {
+    let pr = payload.pull_request;
+    PullRequestRow {
+        repo_name: event.repo_name.clone(),
+        pr_number: i64::from(payload.number),
+        action: payload.action,
+        title: pr.title,
+        state: pr.state,
+        merged: pr.merged,
+        additions: pr.additions.map(i64::from),
+        deletions: pr.deletions.map(i64::from),
+        changed_files: pr.changed_files.map(i64::from),
+        user_login: pr.user.map(|user| user.login),
+        created_at: timestamp_millis(pr.created_at.as_deref()),
+        merged_at: timestamp_millis(pr.merged_at.as_deref()),
+        base_ref: pr.base.ref_name,
+        head_ref: pr.head.ref_name,
+        event_created_at: event.created_at,
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PullRequestBuffer {
+    repo_names: Vec<String>,
+    pr_numbers: Vec<i64>,
+    actions: Vec<String>,
+    titles: Vec<Option<String>>,
+    states: Vec<Option<String>>,
+    merged: Vec<Option<bool>>,
+    additions: Vec<Option<i64>>,
+    deletions: Vec<Option<i64>>,
+    changed_files: Vec<Option<i64>>,
+    user_logins: Vec<Option<String>>,
+    pr_created_ats: Vec<Option<i64>>,
+    merged_ats: Vec<Option<i64>>,
+    base_refs: Vec<String>,
+    head_refs: Vec<String>,
+    /// Event times, which the file summary's time range is taken from
+    pub created_ats: Vec<i64>,
+    /// Approximate size of the buffered data in bytes
+    pub bytes: usize,
+}
+
+impl PullRequestBuffer {
+    pub fn add_row(&mut self, row: PullRequestRow) {
+        self.bytes += row.data_bytes();
+        self.repo_names.push(row.repo_name);
+        self.pr_numbers.push(row.pr_number);
+        self.actions.push(row.action);
+        self.titles.push(row.title);
+        self.states.push(row.state);
+        self.merged.push(row.merged);
+        self.additions.push(row.additions);
+        self.deletions.push(row.deletions);
+        self.changed_files.push(row.changed_files);
+        self.user_logins.push(row.user_login);
+        self.pr_created_ats.push(row.created_at);
+        self.merged_ats.push(row.merged_at);
+        self.base_refs.push(row.base_ref);
+        self.head_refs.push(row.head_ref);
+        self.created_ats.push(row.event_created_at);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pr_numbers.len()
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Writes the buffered rows as one row group, in PULL_REQUESTS_SCHEMA column order.
+pub fn flush_pull_requests_to_parquet(writer: &mut SerializedFileWriter<File>, buffer: &mut PullRequestBuffer) -> Result<()> {
+    if buffer.len() == 0 {
+        return Ok(());
+    }
+
+    let mut row_group_writer = writer.next_row_group()?;
+    write_string_column(&mut row_group_writer, &buffer.repo_names)?;
+    write_int64_column(&mut row_group_writer, &buffer.pr_numbers)?;
+    write_string_column(&mut row_group_writer, &buffer.actions)?;
+    write_optional_string_column(&mut row_group_writer, &buffer.titles)?;
+    write_optional_string_column(&mut row_group_writer, &buffer.states)?;
+    write_optional_bool_column(&mut row_group_writer, &buffer.merged)?;
+    write_optional_int64_column(&mut row_group_writer, &buffer.additions)?;
+    write_optional_int64_column(&mut row_group_writer, &buffer.deletions)?;
+    write_optional_int64_column(&mut row_group_writer, &buffer.changed_files)?;
+    write_optional_string_column(&mut row_group_writer, &buffer.user_logins)?;
+    write_optional_int64_column(&mut row_group_writer, &buffer.pr_created_ats)?;
+    write_optional_int64_column(&mut row_group_writer, &buffer.merged_ats)?;
+    write_string_column(&mut row_group_writer, &buffer.base_refs)?;
+    write_string_column(&mut row_group_writer, &buffer.head_refs)?;
+    write_int64_column(&mut row_group_writer, &buffer.created_ats)?;
+
+    row_group_writer.close()?;
+    buffer.clear();
+    Ok(())
+}
+
+/// Writes the buffered rows as one JSON object per line, with the same fields as the
+/// parquet columns and nulls where the parquet columns are null.
+pub fn write_pull_requests_to_json_lines(out: &mut dyn Write, buffer: &mut PullRequestBuffer) -> Result<()> {
+    for i in 0..buffer.len() {
+        let row = serde_json::json!({
+            "repo_name": buffer.repo_names[i],
+            "pr_number": buffer.pr_numbers[i],
+            "action": buffer.actions[i],
+            "title": buffer.titles[i],
+            "state": buffer.states[i],
+            "merged": buffer.merged[i],
+            "additions": buffer.additions[i],
+            "deletions": buffer.deletions[i],
+            "changed_files": buffer.changed_files[i],
+            "user_login": buffer.user_logins[i],
+            "created_at": buffer.pr_created_ats[i].map(timestamp_json),
+            "merged_at": buffer.merged_ats[i].map(timestamp_json),
+            "base_ref": buffer.base_refs[i],
+            "head_ref": buffer.head_refs[i],
+            "event_created_at": timestamp_json(buffer.created_ats[i]),
+        });
+        serde_json::to_writer(&mut *out, &row)?;
+        out.write_all(b"\n")?;
+    }
+    buffer.clear();
+    Ok(())
+}
+
+/// Moves the buffered rows into a record batch, in PULL_REQUESTS_SCHEMA column order.
+pub fn take_pull_requests_record_batch(schema: SchemaRef, buffer: &mut PullRequestBuffer) -> Result<RecordBatch> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.repo_names))),
+        Arc::new(Int64Array::from(std::mem::take(&mut buffer.pr_numbers))),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.actions))),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.titles))),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.states))),
+        Arc::new(BooleanArray::from(std::mem::take(&mut buffer.merged))),
+        Arc::new(Int64Array::from(std::mem::take(&mut buffer.additions))),
+        Arc::new(Int64Array::from(std::mem::take(&mut buffer.deletions))),
+        Arc::new(Int64Array::from(std::mem::take(&mut buffer.changed_files))),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.user_logins))),
+        Arc::new(TimestampMillisecondArray::from(std::mem::take(&mut buffer.pr_created_ats)).with_timezone("UTC")),
+        Arc::new(TimestampMillisecondArray::from(std::mem::take(&mut buffer.merged_ats)).with_timezone("UTC")),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.base_refs))),
+        Arc::new(StringArray::from(std::mem::take(&mut buffer.head_refs))),
+        Arc::new(TimestampMillisecondArray::from(std::mem::take(&mut buffer.created_ats)).with_timezone("UTC")),
+    ];
+    buffer.clear();
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
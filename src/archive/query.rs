@@ -0,0 +1,233 @@
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::ValueEnum;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::{Field, Row, RowAccessor};
+use parquet::record::reader::RowIter;
+use serde_json::{Map, Value};
+
+use crate::compact::bucket_of;
+use crate::{OutputTable, PartitionStrategy, get_bucket_key, is_parquet_output, open_input_file, timestamp_json, timestamp_millis};
+
+/// Print one repository's events for a month, read from its bucket in the output directory
+#[derive(clap::Args, Debug)]
+pub struct QueryArgs {
+    /// Repository whose events are printed (owner/name), matched exactly against repo_name
+    #[arg(long)]
+    pub repo: String,
+
+    /// Month of the bucket to read (YYYY-MM)
+    #[arg(long)]
+    pub month: String,
+
+    /// Only print events of this type
+    #[arg(long)]
+    pub event_type: Option<String>,
+
+    /// Only print events created at or after this time (RFC 3339)
+    #[arg(long)]
+    pub since: Option<DateTime<Utc>>,
+
+    /// Only print events created before this time (RFC 3339)
+    #[arg(long)]
+    pub until: Option<DateTime<Utc>>,
+
+    /// How the events are printed
+    #[arg(long, value_enum, default_value_t = QueryFormat::Ndjson)]
+    pub format: QueryFormat,
+
+    /// Output directory of earlier runs the bucket is read from
+    #[arg(long, default_value = crate::OUTPUT_DIR)]
+    pub output_dir: PathBuf,
+
+    /// --partition-by the output was written with
+    #[arg(long, value_enum, default_value_t = PartitionStrategy::Prefix3)]
+    pub partition_by: PartitionStrategy,
+
+    /// The output was written with --hive-partitions
+    #[arg(long)]
+    pub hive_partitions: bool,
+
+    /// The output was written with --split-by-event-type
+    #[arg(long)]
+    pub split_by_event_type: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum QueryFormat {
+    /// One JSON object per event with the parquet columns as fields, like --output-format ndjson
+    Ndjson,
+    /// Aligned columns for reading in a terminal: time, type, actor and id
+    Table,
+}
+
+/// Lists the events parquet files of a bucket: its parts, or the merged file once
+/// compacted. Without an event type, a bucket split by event type is every file below
+/// its month.
+fn bucket_files(args: &QueryArgs) -> Result<Vec<PathBuf>> {
+    let split_event_type = if args.split_by_event_type { args.event_type.as_deref() } else { None };
+    let bucket_key = get_bucket_key(&args.repo, &args.month, split_event_type, args.partition_by, args.hive_partitions);
+
+    // Mirrors the layout bucket_output_path writes: hive keys are directories holding the
+    // part files, otherwise the key's last component prefixes the file names
+    let all_event_types = args.split_by_event_type && split_event_type.is_none();
+    let (dir, name) = if args.hive_partitions || all_event_types {
+        (args.output_dir.join(&bucket_key), None)
+    } else {
+        let (dir, name) = bucket_key.rsplit_once('/').unwrap_or(("", &bucket_key));
+        (args.output_dir.join(dir), Some(name.to_string()))
+    };
+
+    let mut files = Vec::new();
+    let mut dirs = vec![dir];
+    while let Some(current) = dirs.pop() {
+        let entries = match std::fs::read_dir(&current) {
+            Ok(entries) => entries,
+            // A missing directory is a bucket that never received rows
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).context(format!("Failed to list {}", current.display())),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                // Event type directories of a split hive bucket
+                if all_event_types && args.hive_partitions {
+                    dirs.push(path);
+                }
+                continue;
+            }
+            let file_name = path.file_name().unwrap().to_string_lossy();
+            if file_name.starts_with('.') || !is_parquet_output(&file_name) {
+                continue;
+            }
+            let (file_bucket, table) = bucket_of(&path);
+            if table == OutputTable::Events && name.as_ref().is_none_or(|name| *name == file_bucket) {
+                files.push(path);
+            }
+        }
+    }
+    if files.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No parquet files for bucket {} in {}; check --partition-by, --hive-partitions and --split-by-event-type against the run that wrote it",
+            bucket_key, args.output_dir.display()
+        ));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Positions of the columns rows are filtered on
+struct FilterColumns {
+    repo_name: usize,
+    event_type: usize,
+    created_at: usize,
+}
+
+impl FilterColumns {
+    fn of(reader: &SerializedFileReader<File>, path: &Path) -> Result<Self> {
+        let fields = reader.metadata().file_metadata().schema().get_fields();
+        let index = |name: &str| fields.iter().position(|field| field.name() == name)
+            .ok_or_else(|| anyhow::anyhow!("{} has no {} column", path.display(), name));
+        Ok(Self { repo_name: index("repo_name")?, event_type: index("type")?, created_at: index("created_at")? })
+    }
+}
+
+/// A row as written by the ndjson output: timestamps as RFC 3339 strings, extracted
+/// columns as strings or null.
+fn row_json(row: &Row) -> Value {
+    let mut object = Map::new();
+    for (name, field) in row.get_column_iter() {
+        let value = match field {
+            Field::TimestampMillis(millis) => timestamp_json(*millis),
+            Field::TimestampMicros(micros) => timestamp_json(micros.div_euclid(1000)),
+            Field::Str(s) => Value::from(s.as_str()),
+            Field::Long(n) => Value::from(*n),
+            Field::Null => Value::Null,
+            other => Value::from(other.to_string()),
+        };
+        object.insert(name.clone(), value);
+    }
+    Value::Object(object)
+}
+
+fn write_row(out: &mut dyn Write, row: &Row, format: QueryFormat, created_at: i64) -> Result<()> {
+    match format {
+        QueryFormat::Ndjson => {
+            // Written with writeln! so a closed stdout surfaces as an io::Error
+            writeln!(out, "{}", row_json(row))?;
+        }
+        QueryFormat::Table => {
+            let created_at = DateTime::from_timestamp_millis(created_at)
+                .map_or(String::new(), |time| time.format("%Y-%m-%d %H:%M:%S").to_string());
+            let column = |name: &str| row.get_column_iter()
+                .find(|(column, _)| column.as_str() == name)
+                .and_then(|(_, field)| match field {
+                    Field::Str(s) => Some(s.clone()),
+                    _ => None,
+                })
+                .unwrap_or_default();
+            writeln!(out, "{:<19}  {:<29}  {:<24}  {}", created_at, column("type"), column("actor_login"), column("id"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Streams the matching rows of each file to `out`, returning how many were printed.
+fn print_rows(args: &QueryArgs, files: &[PathBuf], out: &mut dyn Write) -> Result<u64> {
+    let since = args.since.map(|time| time.timestamp_millis());
+    let until = args.until.map(|time| time.timestamp_millis());
+    if args.format == QueryFormat::Table {
+        writeln!(out, "{:<19}  {:<29}  {:<24}  id", "created_at", "type", "actor_login")?;
+    }
+
+    let mut printed = 0;
+    for path in files {
+        // Files compressed by --wrap-zstd are read from an unpacked temporary copy
+        let (file, _unwrapped) = open_input_file(&path.to_string_lossy())?;
+        let reader = SerializedFileReader::new(file).context(format!("Failed to read the footer of {}", path.display()))?;
+        let columns = FilterColumns::of(&reader, path)?;
+        for row in RowIter::from_file_into(Box::new(reader)) {
+            let row = row.context(format!("Failed to read a row of {}", path.display()))?;
+            // Prefix buckets hold many repositories, so every layout is filtered by name
+            if row.get_string(columns.repo_name)? != &args.repo {
+                continue;
+            }
+            if args.event_type.as_ref().is_some_and(|event_type| row.get_string(columns.event_type).ok() != Some(event_type)) {
+                continue;
+            }
+            let created_at = timestamp_millis(&row, columns.created_at)?;
+            if since.is_some_and(|since| created_at < since) || until.is_some_and(|until| created_at >= until) {
+                continue;
+            }
+            write_row(out, &row, args.format, created_at)?;
+            printed += 1;
+        }
+    }
+    out.flush()?;
+    Ok(printed)
+}
+
+pub fn run(args: &QueryArgs) -> Result<()> {
+    if args.month.len() != 7 || NaiveDate::parse_from_str(&format!("{}-01", args.month), "%Y-%m-%d").is_err() {
+        return Err(anyhow::anyhow!("Invalid month '{}'. Use YYYY-MM", args.month));
+    }
+    if let (Some(since), Some(until)) = (args.since, args.until) && since >= until {
+        return Err(anyhow::anyhow!("--since must be before --until"));
+    }
+
+    let files = bucket_files(args)?;
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    match print_rows(args, &files, &mut out) {
+        Ok(printed) => {
+            eprintln!("{} events of {} in {} files", printed, args.repo, files.len());
+            Ok(())
+        }
+        // The reader went away, e.g. `| head`; that's not an error
+        Err(e) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == ErrorKind::BrokenPipe) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
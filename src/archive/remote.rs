@@ -0,0 +1,162 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, TryStreamExt};
+use object_store::aws::AmazonS3Builder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectStore, WriteMultipart};
+use tokio::runtime::Runtime;
+
+/// Size of the parts large files are uploaded in, and the largest file uploaded in one request
+const UPLOAD_PART_BYTES: usize = 16 * 1024 * 1024;
+/// Parts of one file uploaded at the same time
+const UPLOAD_CONCURRENCY: usize = 4;
+
+/// A directory in object storage, given as an s3://bucket/prefix or gs://bucket/prefix URL.
+/// Credentials come from the usual environment variables (AWS_ACCESS_KEY_ID, AWS_REGION,
+/// GOOGLE_APPLICATION_CREDENTIALS, ...) or, when those are unset, instance metadata.
+#[derive(Debug)]
+pub struct RemoteDir {
+    /// The URL without a trailing slash
+    url: String,
+    store: Arc<dyn ObjectStore>,
+    prefix: ObjectPath,
+    /// Runs the store's requests; the rest of the program is synchronous
+    runtime: Runtime,
+}
+
+impl RemoteDir {
+    /// Opens the directory a location names, or returns None for local paths.
+    pub fn parse(location: &Path) -> Result<Option<Self>> {
+        let location = location.to_string_lossy();
+        let Some((scheme, rest)) = location.split_once("://") else {
+            return Ok(None);
+        };
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(anyhow::anyhow!("No bucket in {}", location));
+        }
+        let store: Arc<dyn ObjectStore> = match scheme {
+            "s3" => Arc::new(AmazonS3Builder::from_env().with_bucket_name(bucket).build()
+                .context(format!("Failed to set up S3 access for {}", location))?),
+            "gs" => Arc::new(GoogleCloudStorageBuilder::from_env().with_bucket_name(bucket).build()
+                .context(format!("Failed to set up GCS access for {}", location))?),
+            _ => return Err(anyhow::anyhow!("Unsupported URL scheme {}:// (expected s3:// or gs://)", scheme)),
+        };
+        let prefix = ObjectPath::parse(prefix)
+            .context(format!("Invalid object path in {}", location))?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .context("Failed to start the runtime for object storage requests")?;
+        Ok(Some(Self { url: location.trim_end_matches('/').to_string(), store, prefix, runtime }))
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The object at a path relative to the directory, which may contain slashes
+    fn object_path(&self, relative: &str) -> Result<ObjectPath> {
+        let path = match self.prefix.as_ref() {
+            "" => relative.to_string(),
+            prefix => format!("{}/{}", prefix, relative),
+        };
+        ObjectPath::parse(&path).context(format!("Invalid object path {}", path))
+    }
+
+    /// The path relative to the directory of one of its objects' URLs
+    pub fn relative_path<'a>(&self, url: &'a str) -> Result<&'a str> {
+        url.strip_prefix(&self.url)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .ok_or_else(|| anyhow::anyhow!("{} is not in {}", url, self.url))
+    }
+
+    /// Lists the names of the files directly in the directory.
+    pub fn list_files(&self) -> Result<Vec<String>> {
+        let listing = self.runtime.block_on(self.store.list_with_delimiter(Some(&self.prefix)))
+            .context(format!("Failed to list {}", self.url))?;
+        Ok(listing.objects.into_iter()
+            .filter_map(|object| object.location.filename().map(str::to_string))
+            .collect())
+    }
+
+    /// Lists the paths of all files under the directory, relative to it.
+    pub fn list_all(&self) -> Result<Vec<String>> {
+        let objects: Vec<_> = self.runtime.block_on(self.store.list(Some(&self.prefix)).try_collect())
+            .context(format!("Failed to list {}", self.url))?;
+        Ok(objects.into_iter()
+            .filter_map(|object| {
+                let relative: Vec<_> = object.location.prefix_match(&self.prefix)?.collect();
+                Some(relative.iter().map(|part| part.as_ref()).collect::<Vec<_>>().join("/"))
+            })
+            .collect())
+    }
+
+    /// Size and modification time of a file, or None if it doesn't exist.
+    pub fn metadata(&self, relative: &str) -> Result<Option<(u64, DateTime<Utc>)>> {
+        match self.runtime.block_on(self.store.head(&self.object_path(relative)?)) {
+            Ok(meta) => Ok(Some((meta.size, meta.last_modified))),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e).context(format!("Failed to read metadata of {}/{}", self.url, relative)),
+        }
+    }
+
+    pub fn exists(&self, relative: &str) -> Result<bool> {
+        Ok(self.metadata(relative)?.is_some())
+    }
+
+    /// Streams a file to a local path.
+    pub fn download(&self, relative: &str, destination: &Path) -> Result<()> {
+        let location = self.object_path(relative)?;
+        let mut output = File::create(destination)
+            .context(format!("Failed to create {}", destination.display()))?;
+        self.runtime.block_on(async {
+            let mut stream = self.store.get(&location).await?.into_stream();
+            while let Some(chunk) = stream.next().await {
+                output.write_all(&chunk?)?;
+            }
+            anyhow::Ok(())
+        }).context(format!("Failed to download {}/{}", self.url, relative))?;
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Uploads a local file, in parts when it is large.
+    pub fn upload(&self, source: &Path, relative: &str) -> Result<()> {
+        let location = self.object_path(relative)?;
+        let mut input = File::open(source)
+            .context(format!("Failed to open {}", source.display()))?;
+        let size = input.metadata()?.len();
+        self.runtime.block_on(async {
+            if size <= UPLOAD_PART_BYTES as u64 {
+                let mut contents = Vec::with_capacity(size as usize);
+                input.read_to_end(&mut contents)?;
+                self.store.put(&location, contents.into()).await?;
+                return anyhow::Ok(());
+            }
+            let mut upload = WriteMultipart::new_with_chunk_size(self.store.put_multipart(&location).await?, UPLOAD_PART_BYTES);
+            let mut chunk = vec![0u8; UPLOAD_PART_BYTES];
+            loop {
+                let read = input.read(&mut chunk)?;
+                if read == 0 {
+                    break;
+                }
+                upload.wait_for_capacity(UPLOAD_CONCURRENCY).await?;
+                upload.write(&chunk[..read]);
+            }
+            upload.finish().await?;
+            anyhow::Ok(())
+        }).context(format!("Failed to upload {} to {}/{}", source.display(), self.url, relative))
+    }
+
+    pub fn delete(&self, relative: &str) -> Result<()> {
+        self.runtime.block_on(self.store.delete(&self.object_path(relative)?))
+            .context(format!("Failed to delete {}/{}", self.url, relative))
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate};
+
+/// Events column holding a renamed row's name as it was in the input. Null for rows
+/// whose name wasn't changed.
+pub const ORIGINAL_REPO_NAME_COLUMN: &str = "repo_name_original";
+
+/// One line of a --rename-map file
+#[derive(Debug)]
+struct Rename {
+    new_name: String,
+    /// Milliseconds since the Unix epoch. Only events created before it are renamed, since
+    /// the old name may belong to another repository afterwards
+    effective_at: Option<i64>,
+}
+
+/// Repository renames and transfers from a --rename-map file, so that a project's
+/// events end up under one name. Old names are matched case-insensitively, as GitHub
+/// does.
+#[derive(Debug, Default)]
+pub struct RenameMap {
+    renames: HashMap<String, Rename>,
+}
+
+/// Parses an effective date: YYYY-MM-DD (midnight UTC) or an RFC 3339 time.
+fn parse_effective_date(value: &str) -> Option<i64> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Some(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp_millis());
+    }
+    DateTime::parse_from_rfc3339(value).ok().map(|time| time.timestamp_millis())
+}
+
+impl RenameMap {
+    /// Reads a CSV file of `old_name,new_name[,effective_date]` lines. A header line
+    /// starting with `old_name` and lines starting with # are skipped.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .comment(Some(b'#'))
+            .from_path(path)
+            .with_context(|| format!("Failed to open rename map {}", path.display()))?;
+
+        let mut map = Self::default();
+        for (index, record) in reader.records().enumerate() {
+            let record = record.with_context(|| format!("Failed to read rename map {}", path.display()))?;
+            let line = record.position().map_or(index as u64 + 1, |position| position.line());
+            if index == 0 && record.get(0) == Some("old_name") {
+                continue;
+            }
+            let (old_name, new_name) = match (record.get(0), record.get(1)) {
+                (Some(old_name), Some(new_name)) if !old_name.is_empty() && !new_name.is_empty() && record.len() <= 3 => (old_name, new_name),
+                _ => return Err(anyhow::anyhow!(
+                    "{} line {}: expected old_name,new_name[,effective_date]", path.display(), line
+                )),
+            };
+            let effective_at = match record.get(2).filter(|date| !date.is_empty()) {
+                Some(date) => Some(parse_effective_date(date).ok_or_else(|| anyhow::anyhow!(
+                    "{} line {}: invalid effective date '{}'. Use YYYY-MM-DD or an RFC 3339 time", path.display(), line, date
+                ))?),
+                None => None,
+            };
+            let rename = Rename { new_name: new_name.to_string(), effective_at };
+            if map.renames.insert(old_name.to_lowercase(), rename).is_some() {
+                return Err(anyhow::anyhow!("{} line {}: {} is renamed more than once", path.display(), line, old_name));
+            }
+        }
+        log::info!("Loaded {} repository renames from {}", map.renames.len(), path.display());
+        for cycle in map.cycles() {
+            log::warn!("⚠ Renames in {} form a cycle: {}; names in it are renamed until one repeats", path.display(), cycle.join(" -> "));
+        }
+        Ok(map)
+    }
+
+    /// Chains of renames that lead back to where they started, ignoring effective dates.
+    /// Each cycle is listed once.
+    fn cycles(&self) -> Vec<Vec<String>> {
+        let mut cycles = Vec::new();
+        let mut reported: HashSet<String> = HashSet::new();
+        let mut starts: Vec<&String> = self.renames.keys().collect();
+        starts.sort();
+        for start in starts {
+            let mut chain = vec![start.clone()];
+            let mut current = start.clone();
+            while let Some(rename) = self.renames.get(&current) {
+                current = rename.new_name.to_lowercase();
+                if let Some(position) = chain.iter().position(|name| *name == current) {
+                    let cycle = &chain[position..];
+                    if cycle.iter().all(|name| reported.insert(name.clone())) {
+                        let mut names = cycle.to_vec();
+                        names.push(current.clone());
+                        cycles.push(names);
+                    }
+                    break;
+                }
+                chain.push(current.clone());
+            }
+        }
+        cycles
+    }
+
+    /// The name an event created at `created_at` is recorded under, following chained
+    /// renames, or None when the map leaves it as it is.
+    pub fn resolve(&self, repo_name: &str, created_at: i64) -> Option<&str> {
+        let mut seen = HashSet::new();
+        let mut resolved: Option<&str> = None;
+        let mut current = repo_name.to_lowercase();
+        seen.insert(current.clone());
+        while let Some(rename) = self.renames.get(&current) {
+            if rename.effective_at.is_some_and(|effective_at| created_at >= effective_at) {
+                break;
+            }
+            let next = rename.new_name.to_lowercase();
+            // A cycle stops at the last name before it repeats
+            if !seen.insert(next.clone()) {
+                break;
+            }
+            resolved = Some(&rename.new_name);
+            current = next;
+        }
+        resolved.filter(|name| *name != repo_name)
+    }
+}
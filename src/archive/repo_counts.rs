@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// Rows written per repository and event type, gathered for --repo-counts
+#[derive(Debug, Default)]
+pub struct RepoCounts {
+    repos: HashMap<String, HashMap<String, u64>>,
+}
+
+impl RepoCounts {
+    pub fn add(&mut self, repo_name: &str, event_type: &str) {
+        let event_types = match self.repos.get_mut(repo_name) {
+            Some(event_types) => event_types,
+            None => self.repos.entry(repo_name.to_string()).or_default(),
+        };
+        *event_types.entry(event_type.to_string()).or_insert(0) += 1;
+    }
+
+    /// Adds another set of counts, each multiplied by `scale`. Dry runs use this to
+    /// estimate a whole file from the rows they sampled.
+    pub fn merge_scaled(&mut self, other: RepoCounts, scale: f64) {
+        for (repo_name, event_types) in other.repos {
+            let totals = self.repos.entry(repo_name).or_default();
+            for (event_type, count) in event_types {
+                *totals.entry(event_type).or_insert(0) += (count as f64 * scale).round() as u64;
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: RepoCounts) {
+        self.merge_scaled(other, 1.0);
+    }
+
+    /// Writes one row per repository, most active first, with a total column followed by
+    /// one column per event type. Event type columns are ordered by their overall count.
+    pub fn write_csv(&self, output_path: &Path) -> Result<()> {
+        let file = File::create(output_path)
+            .with_context(|| format!("Failed to create repo counts file {}", output_path.display()))?;
+        let mut writer = csv::Writer::from_writer(BufWriter::new(file));
+
+        let mut type_totals: HashMap<&str, u64> = HashMap::new();
+        for event_types in self.repos.values() {
+            for (event_type, count) in event_types {
+                *type_totals.entry(event_type.as_str()).or_insert(0) += count;
+            }
+        }
+        let mut event_types: Vec<(&str, u64)> = type_totals.into_iter().collect();
+        event_types.sort_by(|(a_type, a_count), (b_type, b_count)| b_count.cmp(a_count).then(a_type.cmp(b_type)));
+
+        let mut header = vec!["repo_name", "total"];
+        header.extend(event_types.iter().map(|(event_type, _)| *event_type));
+        writer.write_record(&header)?;
+
+        let mut repos: Vec<(&String, u64)> = self.repos.iter()
+            .map(|(repo_name, counts)| (repo_name, counts.values().sum()))
+            .collect();
+        repos.sort_by(|(a_name, a_total), (b_name, b_total)| b_total.cmp(a_total).then(a_name.cmp(b_name)));
+
+        for (repo_name, total) in repos {
+            let counts = &self.repos[repo_name];
+            let mut record = vec![repo_name.clone(), total.to_string()];
+            record.extend(event_types.iter()
+                .map(|(event_type, _)| counts.get(*event_type).copied().unwrap_or(0).to_string()));
+            writer.write_record(&record)
+                .with_context(|| format!("Failed to write counts of {}", repo_name))?;
+        }
+
+        writer.flush().context("Failed to write repo counts")?;
+        Ok(())
+    }
+}
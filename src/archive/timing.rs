@@ -0,0 +1,102 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+
+use serde::Serialize;
+
+/// Set by --report-timing; while unset, `time` only runs the timed code
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Nanoseconds spent in each phase, summed over all threads
+static NANOS: [AtomicU64; PHASES.len()] = [const { AtomicU64::new(0) }; PHASES.len()];
+
+thread_local! {
+    /// Time spent in phases nested inside the one running on this thread, which is
+    /// taken off its own time so no nanosecond is counted twice
+    static NESTED_NANOS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Where a run spends its time
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    /// Reading and decoding input rows, parquet or JSON
+    Decode,
+    /// Turning a decoded row into the fields that are written, including --extract
+    Extract,
+    /// Row filters and the bucket key
+    Route,
+    /// Deduplication and adding rows to bucket buffers
+    Buffer,
+    /// Writing buffers out and closing output files
+    Write,
+}
+
+const PHASES: [(Phase, &str); 5] = [
+    (Phase::Decode, "Decoding input rows"),
+    (Phase::Extract, "Extracting row fields"),
+    (Phase::Route, "Filtering and bucket keys"),
+    (Phase::Buffer, "Buffering rows"),
+    (Phase::Write, "Writing output files"),
+];
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Runs `f`, counting its time towards `phase` when timing is enabled. Time spent in
+/// phases timed inside `f` counts towards those instead.
+pub fn time<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return f();
+    }
+    let outer_nested = NESTED_NANOS.with(|nested| nested.replace(0));
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed().as_nanos() as u64;
+    let nested = NESTED_NANOS.with(|nested| nested.replace(outer_nested + elapsed));
+    NANOS[phase as usize].fetch_add(elapsed.saturating_sub(nested), Ordering::Relaxed);
+    result
+}
+
+/// Seconds spent in each phase, summed over all threads, for the run summary
+#[derive(Debug, Serialize)]
+pub struct PhaseTimings {
+    decode_secs: f64,
+    extract_secs: f64,
+    route_secs: f64,
+    buffer_secs: f64,
+    write_secs: f64,
+}
+
+impl PhaseTimings {
+    pub fn collect() -> Self {
+        let secs = |phase: Phase| NANOS[phase as usize].load(Ordering::Relaxed) as f64 / 1e9;
+        Self {
+            decode_secs: secs(Phase::Decode),
+            extract_secs: secs(Phase::Extract),
+            route_secs: secs(Phase::Route),
+            buffer_secs: secs(Phase::Buffer),
+            write_secs: secs(Phase::Write),
+        }
+    }
+
+    fn secs(&self, phase: Phase) -> f64 {
+        match phase {
+            Phase::Decode => self.decode_secs,
+            Phase::Extract => self.extract_secs,
+            Phase::Route => self.route_secs,
+            Phase::Buffer => self.buffer_secs,
+            Phase::Write => self.write_secs,
+        }
+    }
+
+    pub fn print(&self) {
+        let total: f64 = PHASES.iter().map(|(phase, _)| self.secs(*phase)).sum();
+        log::info!("Time by phase (summed over worker threads):");
+        for (phase, label) in PHASES {
+            let secs = self.secs(phase);
+            let share = if total > 0.0 { secs / total * 100.0 } else { 0.0 };
+            log::info!("  {:<26} {:>10.2}s {:>6.1}%", label, secs, share);
+        }
+    }
+}
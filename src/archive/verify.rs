@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::statistics::Statistics;
+use parquet::record::reader::RowIter;
+use parquet::schema::types::Type;
+use serde::Deserialize;
+
+use crate::compact::merged_path_of;
+use crate::{OutputFileSummary, is_parquet_output, open_input_file, timestamp_millis};
+
+/// Check the bucket files in an output directory against the run summaries written next to them
+#[derive(clap::Args, Debug)]
+pub struct VerifyArgs {
+    /// Output directory of earlier runs whose files are checked
+    #[arg(long, default_value = crate::OUTPUT_DIR)]
+    pub output_dir: PathBuf,
+}
+
+/// What a run summary says about one of its files
+#[derive(Debug, Deserialize)]
+struct ListedFile {
+    path: String,
+    rows: u64,
+    min_created_at: Option<DateTime<Utc>>,
+    max_created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListedFiles {
+    files: Vec<ListedFile>,
+}
+
+/// What a file's footer says: its row count and the range of its event times
+struct FileFacts {
+    rows: u64,
+    min_created_at: Option<i64>,
+    max_created_at: Option<i64>,
+}
+
+/// Reads a parquet file's row count from its footer, and its event time range from the
+/// column statistics, scanning the column when a row group has none. Files compressed by
+/// --wrap-zstd are unpacked to a temporary file first.
+fn read_facts(path: &Path) -> Result<FileFacts> {
+    let (file, _unwrapped) = open_input_file(&path.to_string_lossy())?;
+    let reader = SerializedFileReader::new(file).context(format!("Failed to read the footer of {}", path.display()))?;
+    let metadata = reader.metadata();
+    let rows = metadata.file_metadata().num_rows() as u64;
+
+    // Pull request tables keep the pull request's own created_at next to the event's
+    let fields = metadata.file_metadata().schema().get_fields();
+    let name = if fields.iter().any(|field| field.name() == "event_created_at") { "event_created_at" } else { "created_at" };
+    let index = fields.iter().position(|field| field.name() == name)
+        .ok_or_else(|| anyhow::anyhow!("{} has no {} column", path.display(), name))?;
+
+    let mut range: Option<(i64, i64)> = None;
+    let mut from_statistics = true;
+    for row_group in metadata.row_groups() {
+        match row_group.column(index).statistics() {
+            Some(Statistics::Int64(stats)) if stats.min_opt().is_some() && stats.max_opt().is_some() => {
+                let (min, max) = (*stats.min_opt().unwrap(), *stats.max_opt().unwrap());
+                range = Some(range.map_or((min, max), |(lo, hi)| (lo.min(min), hi.max(max))));
+            }
+            _ if row_group.num_rows() == 0 => {}
+            _ => from_statistics = false,
+        }
+    }
+    if !from_statistics {
+        let projection = Type::group_type_builder("schema")
+            .with_fields(vec![fields[index].clone()])
+            .build()?;
+        range = None;
+        for row in RowIter::from_file_into(Box::new(reader)).project(Some(projection))? {
+            let millis = timestamp_millis(&row?, 0)?;
+            range = Some(range.map_or((millis, millis), |(lo, hi)| (lo.min(millis), hi.max(millis))));
+        }
+    }
+
+    Ok(FileFacts { rows, min_created_at: range.map(|(min, _)| min), max_created_at: range.map(|(_, max)| max) })
+}
+
+/// Compares one file with what was recorded while writing it, returning what differs.
+fn check_file(path: &str, rows: u64, min_created_at: Option<DateTime<Utc>>, max_created_at: Option<DateTime<Utc>>) -> Vec<String> {
+    let facts = match read_facts(Path::new(path)) {
+        Ok(facts) => facts,
+        Err(e) => return vec![format!("{}: {:#}", path, e)],
+    };
+    let mut problems = Vec::new();
+    if facts.rows != rows {
+        problems.push(format!("{}: {} rows in the footer, {} written", path, facts.rows, rows));
+    }
+    let expected = (min_created_at.map(|time| time.timestamp_millis()), max_created_at.map(|time| time.timestamp_millis()));
+    if (facts.min_created_at, facts.max_created_at) != expected {
+        let show = |millis: Option<i64>| millis
+            .and_then(DateTime::from_timestamp_millis)
+            .map_or("none".to_string(), |time| time.to_rfc3339());
+        problems.push(format!(
+            "{}: created_at ranges from {} to {}, expected {} to {}",
+            path, show(facts.min_created_at), show(facts.max_created_at), show(expected.0), show(expected.1)
+        ));
+    }
+    problems
+}
+
+/// Checks the files a run just closed, returning a description of each mismatch.
+pub fn check_written_files(files: &[OutputFileSummary]) -> Vec<String> {
+    files.iter()
+        .flat_map(|file| check_file(&file.path, file.rows, file.min_created_at, file.max_created_at))
+        .collect()
+}
+
+/// The run summaries in an output directory: the unsharded one and one per shard
+fn summary_paths(output_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(output_dir).context(format!("Failed to list {}", output_dir.display()))? {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+        if file_name.starts_with("_summary") && file_name.ends_with(".json") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+pub fn run(args: &VerifyArgs) -> Result<()> {
+    if !args.output_dir.is_dir() {
+        return Err(anyhow::anyhow!("Output directory {} does not exist", args.output_dir.display()));
+    }
+
+    // Files on disk, keyed by their path inside the output directory
+    let mut on_disk: HashMap<String, PathBuf> = HashMap::new();
+    let mut dirs = vec![args.output_dir.clone()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir).context(format!("Failed to list {}", dir.display()))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let file_name = path.file_name().unwrap().to_string_lossy();
+            if file_name.starts_with('.') || !is_parquet_output(&file_name) {
+                continue;
+            }
+            let relative = path.strip_prefix(&args.output_dir)?.to_string_lossy().into_owned();
+            on_disk.insert(relative, path);
+        }
+    }
+    let checked = on_disk.len();
+
+    // Summaries record paths as the run was given the output directory, which may differ
+    // from how it is given here, so listed paths are matched by their trailing components
+    let mut problems = Vec::new();
+    for summary_path in summary_paths(&args.output_dir)? {
+        let contents = std::fs::read_to_string(&summary_path)
+            .context(format!("Failed to read run summary {}", summary_path.display()))?;
+        let summary: ListedFiles = serde_json::from_str(&contents)
+            .context(format!("Failed to parse run summary {}", summary_path.display()))?;
+        for file in summary.files {
+            let mut suffixes = std::iter::once(file.path.as_str())
+                .chain(file.path.match_indices('/').map(|(i, _)| &file.path[i + 1..]));
+            match suffixes.clone().find_map(|relative| on_disk.remove(relative)) {
+                Some(path) => problems.extend(check_file(&path.to_string_lossy(), file.rows, file.min_created_at, file.max_created_at)),
+                // Compaction replaces parts with a merged file, which is checked on its own
+                None if suffixes.any(|relative| merged_path_of(&args.output_dir.join(relative)).exists()) => {}
+                None => problems.push(format!("{}: listed in {} but missing", file.path, summary_path.display())),
+            }
+        }
+    }
+    // Files no summary lists, like compacted files, can only be checked for a readable footer
+    let mut unlisted: Vec<PathBuf> = on_disk.into_values().collect();
+    unlisted.sort();
+    for path in unlisted {
+        if let Err(e) = read_facts(&path) {
+            problems.push(format!("{}: {:#}", path.display(), e));
+        }
+    }
+
+    println!("Checked {} files", checked);
+    if !problems.is_empty() {
+        println!("✗ Verification found {} problems:", problems.len());
+        for problem in &problems {
+            println!("  {}", problem);
+        }
+        return Err(anyhow::anyhow!("Verification found {} problems", problems.len()));
+    }
+    println!("✓ All files verified");
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use git2::{Commit, Mailmap, Repository};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error::{HistoryError, Result};
+
+/// A commit author as exported
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+impl Identity {
+    /// Parses `Name <email>`
+    fn parse(text: &str) -> Option<Self> {
+        let (name, rest) = text.trim().split_once('<')?;
+        let email = rest.strip_suffix('>')?;
+        Some(Self { name: name.trim().to_string(), email: email.trim().to_string() })
+    }
+}
+
+/// Canonical identities from an --authors-map file, a JSON object whose keys are either
+/// `Name <email>` or a bare email and whose values are `Name <email>`. A key with a name
+/// only matches that exact name and email; a bare email matches any name. Emails are
+/// compared case-insensitively, like .mailmap does.
+#[derive(Debug, Default)]
+pub struct AuthorsMap {
+    by_identity: HashMap<(String, String), Identity>,
+    by_email: HashMap<String, Identity>,
+}
+
+impl AuthorsMap {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|source| HistoryError::AuthorsMapRead { path: path.to_path_buf(), source })?;
+        let entries: HashMap<String, String> = serde_json::from_str(&text)
+            .map_err(|source| HistoryError::AuthorsMapFormat { path: path.to_path_buf(), source })?;
+
+        let mut map = Self::default();
+        for (raw, canonical) in entries {
+            let canonical = Identity::parse(&canonical)
+                .ok_or_else(|| HistoryError::AuthorsMapEntry(format!("Authors map entry for '{}' must map to 'Name <email>', got '{}'", raw, canonical)))?;
+            match Identity::parse(&raw) {
+                Some(identity) => {
+                    map.by_identity.insert((identity.name, identity.email.to_lowercase()), canonical);
+                }
+                None if raw.contains('@') => {
+                    map.by_email.insert(raw.trim().to_lowercase(), canonical);
+                }
+                None => return Err(HistoryError::AuthorsMapEntry(format!("Authors map key '{}' is neither 'Name <email>' nor an email", raw))),
+            }
+        }
+        Ok(map)
+    }
+
+    fn get(&self, identity: &Identity) -> Option<&Identity> {
+        let email = identity.email.to_lowercase();
+        self.by_identity.get(&(identity.name.clone(), email.clone()))
+            .or_else(|| self.by_email.get(&email))
+    }
+}
+
+/// Resolves the author recorded for each commit. The repository's .mailmap is applied
+/// first (with --use-mailmap), then --authors-map to its result, so an authors map entry
+/// wins over the mailmap when both know an identity.
+pub struct Authors {
+    mailmap: Option<Mailmap>,
+    map: AuthorsMap,
+}
+
+impl Authors {
+    pub fn new(repo: &Repository, use_mailmap: bool, map_path: Option<&Path>) -> Result<Self> {
+        let mailmap = if use_mailmap {
+            Some(repo.mailmap().map_err(HistoryError::MailmapRead)?)
+        } else {
+            None
+        };
+        let map = match map_path {
+            Some(path) => AuthorsMap::load(path)?,
+            None => AuthorsMap::default(),
+        };
+        Ok(Self { mailmap, map })
+    }
+
+    pub fn author_of(&self, commit: &Commit) -> Result<Identity> {
+        let signature = match &self.mailmap {
+            Some(mailmap) => commit.author_with_mailmap(mailmap)
+                .map_err(|source| HistoryError::MailmapApply { commit: commit.id(), source })?,
+            None => commit.author().to_owned(),
+        };
+        let identity = Identity {
+            name: signature.name().unwrap_or("").to_string(),
+            email: signature.email().unwrap_or("").to_string(),
+        };
+        Ok(self.map.get(&identity).cloned().unwrap_or(identity))
+    }
+}
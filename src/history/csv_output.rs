@@ -0,0 +1,40 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use git2::Repository;
+use git_history_exporter::{ExportOptions, stream_history};
+
+/// Write one row per file and commit, with `delimiter` between fields. Rows are written
+/// as the commits are diffed, oldest commit first, so the history is never held in
+/// memory. Diffs are left out since multi-line patches don't fit a cell, and messages
+/// are cut to their first line. Returns the number of rows written.
+pub fn write_csv(repo: &Repository, options: &ExportOptions, output_path: &Path, delimiter: u8) -> Result<usize> {
+    let file = File::create(output_path)
+        .with_context(|| format!("Failed to create output file {}", output_path.display()))?;
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(BufWriter::new(file));
+
+    writer.write_record(["path", "commit_hash", "author", "author_email", "additions", "deletions", "message"])?;
+
+    let mut rows = 0;
+    stream_history(repo, options, |path, commit| -> Result<()> {
+        let message = commit.commit_message.lines().next().unwrap_or("");
+        writer.write_record([
+            path,
+            &commit.commit_hash,
+            &commit.author_name,
+            &commit.author_email,
+            &commit.additions.to_string(),
+            &commit.deletions.to_string(),
+            message,
+        ]).with_context(|| format!("Failed to write commit {} for {}", commit.commit_hash, path))?;
+        rows += 1;
+        Ok(())
+    })?;
+
+    writer.flush().context("Failed to write CSV output")?;
+    Ok(rows)
+}
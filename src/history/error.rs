@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+use git2::Oid;
+use thiserror::Error;
+
+/// Errors returned by the exporter's public functions. The messages read like the
+/// command line tool's; the variants let callers tell the failures apart.
+#[derive(Debug, Error)]
+pub enum HistoryError {
+    #[error("Failed to open repository at {}", path.display())]
+    RepoOpen {
+        path: PathBuf,
+        #[source]
+        source: git2::Error,
+    },
+    
+    #[error("Failed to resolve ref '{revision}' to a commit")]
+    RevisionNotFound {
+        revision: String,
+        #[source]
+        source: git2::Error,
+    },
+    
+    /// The followed path isn't in any commit reachable from the revision
+    #[error("'{path}' does not exist in any commit reachable from {revision}")]
+    PathNotFound {
+        path: String,
+        revision: String,
+    },
+    
+    #[error("Failed to read authors map {}", path.display())]
+    AuthorsMapRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    
+    #[error("Authors map {} must be a JSON object of strings", path.display())]
+    AuthorsMapFormat {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    
+    /// A key or value of the authors map isn't an identity
+    #[error("{0}")]
+    AuthorsMapEntry(String),
+    
+    #[error("Failed to read the repository's .mailmap")]
+    MailmapRead(#[source] git2::Error),
+    
+    #[error("Failed to apply .mailmap to the author of {commit}")]
+    MailmapApply {
+        commit: Oid,
+        #[source]
+        source: git2::Error,
+    },
+    
+    #[error("Failed to read the {attribute} attribute of {path}")]
+    Attribute {
+        attribute: String,
+        path: String,
+        #[source]
+        source: git2::Error,
+    },
+    
+    /// Any other git operation, such as walking history or diffing a commit
+    #[error(transparent)]
+    Git(#[from] git2::Error),
+}
+
+pub(crate) type Result<T, E = HistoryError> = std::result::Result<T, E>;
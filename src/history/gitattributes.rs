@@ -0,0 +1,76 @@
+use git2::{AttrCheckFlags, AttrValue, Commit, Index, Repository};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error::{HistoryError, Result};
+
+/// What a path's .gitattributes say about exporting it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PathAttributes {
+    /// `export-ignore` or `linguist-generated`: the path is left out of the export
+    pub skip: bool,
+    /// `binary` or `-diff`: exported as a binary file, without a text diff
+    pub binary: bool,
+}
+
+/// Attributes set without a value, or set to "true", e.g. `linguist-generated=true`
+fn is_set(value: AttrValue) -> bool {
+    matches!(value, AttrValue::True | AttrValue::String("true"))
+}
+
+/// Looks up the export rules of one path in the .gitattributes files of the repository's
+/// index, which `AttributeCache` replaces with the exported commit's tree.
+fn path_attributes(repo: &Repository, path: &str) -> Result<PathAttributes> {
+    let get = |name: &str| -> Result<AttrValue> {
+        let value = repo.get_attr(Path::new(path), name, AttrCheckFlags::INDEX_ONLY)
+            .map_err(|source| HistoryError::Attribute { attribute: name.to_string(), path: path.to_string(), source })?;
+        Ok(AttrValue::from_string(value))
+    };
+    Ok(PathAttributes {
+        skip: is_set(get("export-ignore")?) || is_set(get("linguist-generated")?),
+        binary: is_set(get("binary")?) || get("diff")? == AttrValue::False,
+    })
+}
+
+/// Caches the rules of each path seen while walking history. The rules are the ones
+/// committed in the exported commit, whatever is checked out or staged. When disabled by
+/// --no-gitattributes, every path gets the defaults.
+pub struct AttributeCache {
+    /// A handle of its own whose index is the exported commit's tree, held in memory
+    /// only, so the caller's repository and the index on disk are left alone
+    repo: Option<Repository>,
+    paths: HashMap<String, PathAttributes>,
+}
+
+impl AttributeCache {
+    pub fn new(repo: &Repository, commit: &Commit, enabled: bool) -> Result<Self> {
+        let repo = if enabled {
+            let attributes_repo = Repository::open(repo.path())
+                .map_err(|source| HistoryError::RepoOpen { path: repo.path().to_path_buf(), source })?;
+            let mut index = Index::new()?;
+            index.read_tree(&commit.tree()?)?;
+            attributes_repo.set_index(&mut index)?;
+            Some(attributes_repo)
+        } else {
+            None
+        };
+        Ok(Self { repo, paths: HashMap::new() })
+    }
+
+    pub fn get(&mut self, path: &str) -> Result<PathAttributes> {
+        let Some(repo) = &self.repo else {
+            return Ok(PathAttributes::default());
+        };
+        if let Some(attributes) = self.paths.get(path) {
+            return Ok(*attributes);
+        }
+        let attributes = path_attributes(repo, path)?;
+        self.paths.insert(path.to_string(), attributes);
+        Ok(attributes)
+    }
+
+    /// Number of distinct paths left out of the export so far
+    pub fn skipped_paths(&self) -> usize {
+        self.paths.values().filter(|attributes| attributes.skip).count()
+    }
+}
@@ -0,0 +1,1251 @@
+//! Exports the history of a git repository: every file that ever existed, with the
+//! commits that touched it and its contents at the exported revision. The `history`
+//! binary is a command-line front end to `export_history`, `stream_history` and
+//! `follow_file`.
+
+mod authors;
+mod error;
+mod gitattributes;
+
+use base64::Engine;
+use clap::ValueEnum;
+use git2::{Repository, Commit, Delta, DiffOptions, FileMode, ObjectType, Oid, DiffDelta, Patch, Tree};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+
+use authors::Authors;
+use error::Result;
+use gitattributes::AttributeCache;
+
+pub use error::HistoryError;
+
+/// A step of an export that reports progress
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Walking the commits and diffing each against its parent
+    Commits,
+    /// Reading the files as they are at the exported revision
+    CurrentContents,
+    /// Walking the commits that touched a followed file; the total isn't known up front
+    Follow,
+}
+
+/// Progress reported while exporting
+#[derive(Clone, Copy, Debug)]
+pub enum Progress {
+    /// A step began; `total` is the number of items it processes, when known
+    Started { step: Step, total: Option<u64> },
+    /// `done` items of the step have been processed. Sent in batches of about 1% of
+    /// the total rather than for every item.
+    Advanced { step: Step, done: u64 },
+    Finished { step: Step },
+    /// Paths were left out because .gitattributes mark them export-ignore or
+    /// linguist-generated. Sent once the commits are walked, if any were.
+    SkippedPaths(usize),
+}
+
+/// Receives progress while exporting. Current contents are read on several threads,
+/// so it has to be callable from any of them.
+pub type ProgressCallback = dyn Fn(Progress) + Send + Sync;
+
+/// What is exported and how
+pub struct ExportOptions {
+    /// Branch, tag, or other revision to export
+    pub revision: String,
+    /// Number of leading bytes scanned for a null byte when deciding whether a file is binary
+    pub binary_scan_bytes: usize,
+    /// How the current contents of binary files are exported
+    pub binary_mode: BinaryMode,
+    /// Lines of unchanged context around each hunk; git's default when unset
+    pub context_lines: Option<u32>,
+    pub ignore_whitespace: bool,
+    pub ignore_whitespace_eol: bool,
+    pub merge_diff: MergeDiff,
+    /// Keep the textual diff of each commit, not only its line stats
+    pub include_patch: bool,
+    /// Read the current contents of each file; without them `current_contents` stays empty
+    pub include_contents: bool,
+    /// Follow .gitattributes: leave out export-ignore and linguist-generated paths, and
+    /// export paths marked binary or -diff as binary files
+    pub gitattributes: bool,
+    /// Files larger than this get a placeholder as their current contents, and each
+    /// file's diff in a commit is cut off after this many bytes
+    pub max_file_bytes: Option<usize>,
+    /// JSON file mapping author identities to the canonical ones exported in their place
+    pub authors_map: Option<PathBuf>,
+    /// Rewrite authors through the repository's .mailmap, before `authors_map`
+    pub use_mailmap: bool,
+    /// Only commits whose message matches are exported, as with `git log --grep`
+    pub grep: Option<Regex>,
+    /// Export the commits whose message does not match `grep` instead
+    pub grep_invert: bool,
+    /// Leave out this commit and everything reachable from it, like
+    /// `git log <since>..<revision>`
+    pub since_commit: Option<String>,
+    /// Keep only this many of the newest commits in each file's history
+    pub max_history_per_file: Option<usize>,
+    /// Threads diffing commits during the export; one per CPU when unset
+    pub threads: Option<usize>,
+    /// Called as the export progresses; nothing is reported when unset
+    pub progress: Option<Box<ProgressCallback>>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            revision: "HEAD".to_string(),
+            binary_scan_bytes: 8192,
+            binary_mode: BinaryMode::Placeholder,
+            context_lines: None,
+            ignore_whitespace: false,
+            ignore_whitespace_eol: false,
+            merge_diff: MergeDiff::FirstParent,
+            include_patch: true,
+            include_contents: true,
+            gitattributes: true,
+            max_file_bytes: None,
+            authors_map: None,
+            use_mailmap: false,
+            grep: None,
+            grep_invert: false,
+            since_commit: None,
+            max_history_per_file: None,
+            threads: None,
+            progress: None,
+        }
+    }
+}
+
+impl ExportOptions {
+    fn report(&self, progress: Progress) {
+        if let Some(callback) = &self.progress {
+            callback(progress);
+        }
+    }
+    
+    /// Whether `commit` passes the `grep` filter; every commit does without one
+    fn includes_commit(&self, commit: &Commit) -> bool {
+        self.grep.as_ref().is_none_or(|grep| grep.is_match(commit.message().unwrap_or("")) != self.grep_invert)
+    }
+    
+    fn diff_settings(&self) -> DiffSettings {
+        DiffSettings {
+            context_lines: self.context_lines,
+            ignore_whitespace: self.ignore_whitespace,
+            ignore_whitespace_eol: self.ignore_whitespace_eol,
+            merge_diff: self.merge_diff,
+            max_diff_bytes: self.max_file_bytes,
+        }
+    }
+    
+    fn binary_handling(&self) -> BinaryHandling {
+        BinaryHandling {
+            scan_bytes: self.binary_scan_bytes,
+            mode: self.binary_mode,
+            max_bytes: self.max_file_bytes,
+        }
+    }
+    
+    fn walk_settings(&self) -> WalkSettings<'_> {
+        WalkSettings { diff: self.diff_settings(), include_patch: self.include_patch, options: self }
+    }
+}
+
+/// Opens the repository at `path`, which may be a working tree or a bare repository.
+pub fn open_repository(path: &Path) -> Result<Repository> {
+    Repository::open(path).map_err(|source| HistoryError::RepoOpen { path: path.to_path_buf(), source })
+}
+
+fn resolve_revision<'repo>(repo: &'repo Repository, revision: &str) -> Result<Commit<'repo>> {
+    repo.revparse_single(revision)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|source| HistoryError::RevisionNotFound { revision: revision.to_string(), source })
+}
+
+/// A walk over the commits reachable from `start`, less those excluded by `since_commit`
+fn revwalk_from<'repo>(repo: &'repo Repository, start: Oid, options: &ExportOptions) -> Result<git2::Revwalk<'repo>> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(start)?;
+    if let Some(since) = &options.since_commit {
+        revwalk.hide(resolve_revision(repo, since)?.id())?;
+    }
+    Ok(revwalk)
+}
+
+/// Whether any commit reachable from `start` has a file at `path`
+fn path_in_any_commit(repo: &Repository, start: Oid, path: &str) -> Result<bool> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(start)?;
+    for commit_id in revwalk {
+        if repo.find_commit(commit_id?)?.tree()?.get_path(Path::new(path)).is_ok() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Exports every file that ever existed in the history of `opts.revision`, keyed by
+/// path, with the commits that touched it in chronological order. Current contents come
+/// from the revision's tree, so bare repositories work as well.
+pub fn export_history(repo: &Repository, opts: &ExportOptions) -> Result<ExportData> {
+    let target_commit = resolve_revision(repo, &opts.revision)?;
+    let mut attributes = AttributeCache::new(repo, &target_commit, opts.gitattributes)?;
+    let authors = Authors::new(repo, opts.use_mailmap, opts.authors_map.as_deref())?;
+    
+    let mut export_data = ExportData::new();
+    // Commits dropped from each file's history so far under max_history_per_file
+    let mut dropped: HashMap<String, usize> = HashMap::new();
+    
+    // First, process commits to discover all files that have ever existed
+    // This will also build up the history for all files
+    process_commit_history(repo, target_commit.id(), opts.walk_settings(), &mut attributes, &authors, &mut |path, commit, marked_binary| {
+        push_history(&mut export_data, &mut dropped, opts.max_history_per_file, path, commit, marked_binary);
+        Ok::<_, HistoryError>(())
+    })?;
+    if attributes.skipped_paths() > 0 {
+        opts.report(Progress::SkippedPaths(attributes.skipped_paths()));
+    }
+    if let Some(max) = opts.max_history_per_file {
+        for (path, file_info) in export_data.iter_mut() {
+            file_info.keep_newest(max, dropped.get(path).copied().unwrap_or(0));
+        }
+    }
+    
+    // Now get current contents for files that still exist at the target commit
+    if opts.include_contents {
+        populate_current_contents(repo, &target_commit.tree()?, &mut export_data, opts.binary_handling(), opts)?;
+    }
+    Ok(export_data)
+}
+
+/// Walks the history of `opts.revision` like `export_history`, but hands every file a
+/// commit changed to `on_change` as soon as the commit is diffed rather than collecting
+/// them, so memory doesn't grow with the history. Commits arrive oldest first, and the
+/// files of each commit sorted by path. Current contents aren't read, and
+/// `max_history_per_file` is ignored since a file's newest commits are only known once
+/// the walk ends. An error from `on_change` stops the walk and is returned.
+pub fn stream_history<E: From<HistoryError>>(
+    repo: &Repository,
+    opts: &ExportOptions,
+    mut on_change: impl FnMut(&str, &CommitInfo) -> Result<(), E>,
+) -> Result<(), E> {
+    let target_commit = resolve_revision(repo, &opts.revision)?;
+    let mut attributes = AttributeCache::new(repo, &target_commit, opts.gitattributes)?;
+    let authors = Authors::new(repo, opts.use_mailmap, opts.authors_map.as_deref())?;
+    
+    process_commit_history(repo, target_commit.id(), opts.walk_settings(), &mut attributes, &authors, &mut |path, commit, _| {
+        on_change(&path, &commit)
+    })?;
+    if attributes.skipped_paths() > 0 {
+        opts.report(Progress::SkippedPaths(attributes.skipped_paths()));
+    }
+    Ok(())
+}
+
+/// Exports the history of a single file as of `opts.revision`, following renames. Fails
+/// with `HistoryError::PathNotFound` if no commit reachable from the revision has the
+/// file; the history is empty when the filters leave out every commit that has it. The
+/// file is exported even if .gitattributes would skip it, since it was asked for by name.
+pub fn follow_file(repo: &Repository, path: &str, opts: &ExportOptions) -> Result<FileInfo> {
+    let target_commit = resolve_revision(repo, &opts.revision)?;
+    let mut attributes = AttributeCache::new(repo, &target_commit, opts.gitattributes)?;
+    let authors = Authors::new(repo, opts.use_mailmap, opts.authors_map.as_deref())?;
+    
+    let (history, dropped) = follow_file_history(repo, target_commit.id(), path, opts.walk_settings(), &mut attributes, &authors)?;
+    // `since_commit`, `grep` and `max_history_per_file` can leave out every commit of
+    // the file, so an empty history is only an error for a path no commit has
+    if history.is_empty() && dropped == 0 && !path_in_any_commit(repo, target_commit.id(), path)? {
+        return Err(HistoryError::PathNotFound { path: path.to_string(), revision: opts.revision.clone() });
+    }
+    let marked_binary = attributes.get(path)?.binary;
+    let current = if opts.include_contents {
+        read_current_contents(repo, &target_commit.tree()?, path, opts.binary_handling(), marked_binary)
+    } else {
+        CurrentContents::marker("")
+    };
+    let mut file_info = FileInfo {
+        current_contents: current.contents,
+        binary: current.binary,
+        symlink: current.symlink,
+        history,
+        truncated: false,
+        total_commits: None,
+        blob_id: current.blob_id,
+    };
+    if let Some(max) = opts.max_history_per_file {
+        file_info.keep_newest(max, dropped);
+    }
+    Ok(file_info)
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryMode {
+    /// Replace the contents with "[Binary file]"
+    Placeholder,
+    /// Store the contents base64-encoded
+    Base64,
+    /// Leave the contents empty
+    Skip,
+}
+
+/// Binary detection and size settings used when reading current file contents
+#[derive(Clone, Copy, Debug)]
+struct BinaryHandling {
+    scan_bytes: usize,
+    mode: BinaryMode,
+    /// Larger blobs are replaced by a placeholder without being read
+    max_bytes: Option<usize>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MergeDiff {
+    /// Diff merges against their first parent
+    #[default]
+    FirstParent,
+    /// Diff merges against every parent. A file appears if it differs from any parent;
+    /// its diff holds the patch against each such parent and its line stats are summed
+    /// across them
+    Combined,
+}
+
+/// Settings applied to every commit diff
+#[derive(Clone, Copy, Debug, Default)]
+struct DiffSettings {
+    context_lines: Option<u32>,
+    ignore_whitespace: bool,
+    ignore_whitespace_eol: bool,
+    merge_diff: MergeDiff,
+    /// Cap on the diff text kept per file and commit
+    max_diff_bytes: Option<usize>,
+}
+
+impl DiffSettings {
+    fn diff_options(&self) -> DiffOptions {
+        let mut options = DiffOptions::new();
+        if let Some(context_lines) = self.context_lines {
+            options.context_lines(context_lines);
+        }
+        options.ignore_whitespace(self.ignore_whitespace);
+        options.ignore_whitespace_eol(self.ignore_whitespace_eol);
+        options
+    }
+}
+
+/// How history is walked: the diff settings, whether diff text is kept, and the options
+/// progress is reported through
+#[derive(Clone, Copy)]
+struct WalkSettings<'a> {
+    diff: DiffSettings,
+    include_patch: bool,
+    options: &'a ExportOptions,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ContentKind {
+    Text,
+    Binary,
+}
+
+/// A commit that touched a file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CommitInfo {
+    pub commit_hash: String,
+    pub author_name: String,
+    pub author_email: String,
+    pub commit_message: String,
+    /// The file's diff in this commit; unset when diffs aren't exported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    pub additions: usize,
+    pub deletions: usize,
+}
+
+/// Changes made to a single file by a commit
+#[derive(Debug, Default)]
+struct FileChange {
+    diff: String,
+    additions: usize,
+    deletions: usize,
+    /// Set once the diff reached --max-file-bytes and the truncation marker was appended
+    truncated: bool,
+    /// The old and new mode, when the commit changed the file's mode
+    mode_change: Option<(i32, i32)>,
+}
+
+/// Stands in for current contents and diffs that aren't exported as text
+const BINARY_PLACEHOLDER: &str = "[Binary file]";
+
+impl FileChange {
+    /// The change recorded for a file marked binary in .gitattributes: no line stats,
+    /// and a placeholder instead of the diff, after the mode lines of a mode change
+    fn binary(include_patch: bool, mode_change: Option<(i32, i32)>) -> Self {
+        let diff = match mode_change {
+            _ if !include_patch => String::new(),
+            Some((old_mode, new_mode)) => format!("old mode {:o}\nnew mode {:o}\n{}", old_mode, new_mode, BINARY_PLACEHOLDER),
+            None => BINARY_PLACEHOLDER.to_string(),
+        };
+        Self { diff, additions: 0, deletions: 0, truncated: false, mode_change }
+    }
+    
+    /// Appends diff text, keeping at most `max_bytes` of it. The text past the cap is
+    /// dropped and replaced by a single marker line.
+    fn push_diff(&mut self, text: &str, max_bytes: Option<usize>) {
+        if self.truncated {
+            return;
+        }
+        let Some(max_bytes) = max_bytes else {
+            self.diff.push_str(text);
+            return;
+        };
+        if self.diff.len() + text.len() <= max_bytes {
+            self.diff.push_str(text);
+            return;
+        }
+        let mut end = max_bytes - self.diff.len();
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.diff.push_str(&text[..end]);
+        self.truncate_diff(max_bytes);
+    }
+    
+    /// Ends the diff with the truncation marker; later text is dropped.
+    fn truncate_diff(&mut self, max_bytes: usize) {
+        if !self.diff.is_empty() && !self.diff.ends_with('\n') {
+            self.diff.push('\n');
+        }
+        self.diff.push_str(&format!("[truncated: diff exceeds {} bytes]\n", max_bytes));
+        self.truncated = true;
+    }
+}
+
+/// Current contents of files over --max-file-bytes
+fn omitted_placeholder(size: usize) -> String {
+    format!("[omitted: {} bytes]", size)
+}
+
+/// A file's contents at the exported revision and the commits that touched it
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileInfo {
+    /// "[deleted]" for files that no longer exist at the exported revision
+    #[serde(rename = "currentContents")]
+    pub current_contents: String,
+    /// Set when the current contents were detected as binary; see `BinaryMode`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub binary: bool,
+    /// Set when the file is a symlink; the current contents are then its target path
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub symlink: bool,
+    /// Oldest commit first
+    pub history: Vec<CommitInfo>,
+    /// Set when older commits were dropped from `history` for `max_history_per_file`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Number of commits that touched the file, when `history` was truncated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_commits: Option<usize>,
+    /// Blob of the current contents, when the file exists at the exported revision
+    #[serde(skip)]
+    pub blob_id: Option<Oid>,
+}
+
+impl FileInfo {
+    /// Drops all but the newest `max` commits of the history, given the number of
+    /// commits already dropped before.
+    fn keep_newest(&mut self, max: usize, dropped: usize) {
+        let excess = self.history.len().saturating_sub(max);
+        self.history.drain(..excess);
+        if dropped + excess > 0 {
+            self.truncated = true;
+            self.total_commits = Some(dropped + excess + self.history.len());
+        }
+    }
+}
+
+/// Exported files keyed by path. Sorted, so the same history always serializes to the
+/// same bytes
+pub type ExportData = BTreeMap<String, FileInfo>;
+
+/// A file as it is at the exported revision
+struct CurrentContents {
+    contents: String,
+    binary: bool,
+    symlink: bool,
+    blob_id: Option<Oid>,
+}
+
+impl CurrentContents {
+    /// Contents that aren't read from a blob, such as the [deleted] marker
+    fn marker(contents: &str) -> Self {
+        Self { contents: contents.to_string(), binary: false, symlink: false, blob_id: None }
+    }
+}
+
+
+/// Walks the commits reachable from `start` oldest first and calls `on_change` with
+/// each file a commit changed, its commit, and whether .gitattributes mark it binary.
+fn process_commit_history<E: From<HistoryError>>(
+    repo: &Repository,
+    start: Oid,
+    settings: WalkSettings,
+    attributes: &mut AttributeCache,
+    authors: &Authors,
+    on_change: &mut impl FnMut(String, CommitInfo, bool) -> Result<(), E>,
+) -> Result<(), E> {
+    let WalkSettings { diff: diff_settings, include_patch, options } = settings;
+    
+    // Get total count for progress bar (this is much more memory efficient)
+    let total_commits = revwalk_from(repo, start, options)?.count();
+    
+    options.report(Progress::Started { step: Step::Commits, total: Some(total_commits as u64) });
+    
+    // One thread walks the commits, a pool of workers diffs them and this thread merges
+    // the results. git2 handles can't be shared between threads, so the walk and each
+    // worker open their own Repository.
+    let threads = options.threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, usize::from)).max(1);
+    let repo_path = repo.path().to_path_buf();
+    let open_repo = || Repository::open(&repo_path).map_err(|source| HistoryError::RepoOpen { path: repo_path.clone(), source });
+    let open_repo = &open_repo;
+    
+    std::thread::scope(|scope| -> Result<(), E> {
+        // Bounded, so the walk stays only a little ahead of the workers and they stay
+        // only a little ahead of the merge
+        let (commit_sender, commit_receiver) = mpsc::sync_channel::<(usize, Oid)>(threads * 4);
+        let (result_sender, result_receiver) = mpsc::sync_channel::<Result<CommitChanges>>(threads * 4);
+        let commit_receiver = Arc::new(Mutex::new(commit_receiver));
+        
+        let walk_results = result_sender.clone();
+        scope.spawn(move || {
+            let walk = || -> Result<()> {
+                let walk_repo = open_repo()?;
+                // Start from the target commit and walk backwards through history
+                let mut revwalk = revwalk_from(&walk_repo, start, options)?;
+                revwalk.set_sorting(git2::Sort::TIME | git2::Sort::REVERSE)?; // REVERSE for chronological order
+                for (index, commit_id) in revwalk.enumerate() {
+                    // A closed channel means the merge stopped on an error
+                    if commit_sender.send((index, commit_id?)).is_err() {
+                        break;
+                    }
+                }
+                Ok(())
+            };
+            if let Err(e) = walk() {
+                let _ = walk_results.send(Err(e));
+            }
+        });
+        
+        for _ in 0..threads {
+            let commit_receiver = Arc::clone(&commit_receiver);
+            let results = result_sender.clone();
+            scope.spawn(move || {
+                let thread_repo = match open_repo() {
+                    Ok(thread_repo) => thread_repo,
+                    Err(e) => {
+                        let _ = results.send(Err(e));
+                        return;
+                    }
+                };
+                loop {
+                    // Take the lock only for the receive, so workers diff concurrently
+                    let job = commit_receiver.lock().unwrap().recv();
+                    let Ok((index, commit_id)) = job else {
+                        break;
+                    };
+                    let changes = thread_repo.find_commit(commit_id).map_err(HistoryError::from).and_then(|commit| {
+                        if !options.includes_commit(&commit) {
+                            return Ok(None);
+                        }
+                        commit_file_changes(&thread_repo, &commit, diff_settings, include_patch).map(Some)
+                    });
+                    if results.send(changes.map(|changes| (index, commit_id, changes))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(commit_receiver);
+        drop(result_sender);
+        
+        // Workers finish out of order; results are merged in walk order so each file's
+        // history stays chronological
+        let mut pending: BTreeMap<usize, (Oid, Option<HashMap<String, FileChange>>)> = BTreeMap::new();
+        let mut processed_count = 0;
+        let update_interval = std::cmp::max(1, total_commits / 100); // Update every 1% of commits
+        for result in result_receiver {
+            let (index, commit_id, changes) = result?;
+            pending.insert(index, (commit_id, changes));
+            while let Some((commit_id, changes)) = pending.remove(&processed_count) {
+                if let Some(changes) = changes {
+                    let commit = repo.find_commit(commit_id).map_err(HistoryError::from)?;
+                    record_commit(&commit, changes, attributes, authors, include_patch, on_change)?;
+                }
+                
+                processed_count += 1;
+                // Batch progress updates for better performance
+                if processed_count % update_interval == 0 || processed_count == total_commits {
+                    options.report(Progress::Advanced { step: Step::Commits, done: processed_count as u64 });
+                }
+            }
+        }
+        Ok(())
+    })?;
+    
+    options.report(Progress::Finished { step: Step::Commits });
+    
+    Ok(())
+}
+
+/// A commit's position in the chronological walk, with the files it changed; None when
+/// `grep` leaves the commit out
+type CommitChanges = (usize, Oid, Option<HashMap<String, FileChange>>);
+
+/// The files a commit changed, diffed against its first parent or, with
+/// `MergeDiff::Combined`, against every parent of a merge
+fn commit_file_changes(repo: &Repository, commit: &Commit, diff_settings: DiffSettings, include_patch: bool) -> Result<HashMap<String, FileChange>> {
+    if diff_settings.merge_diff == MergeDiff::Combined && commit.parent_count() > 1 {
+        get_merge_file_changes(repo, commit, diff_settings, include_patch)
+    } else {
+        let parent_id = if commit.parent_count() > 0 {
+            Some(commit.parent(0)?.id())
+        } else {
+            None
+        };
+        get_commit_file_changes(repo, commit, parent_id, diff_settings, include_patch)
+    }
+}
+
+/// Hands `on_change` every file a commit changed, sorted by path, leaving out hidden
+/// paths and the ones .gitattributes skip.
+fn record_commit<E: From<HistoryError>>(
+    commit: &Commit,
+    modified_files: HashMap<String, FileChange>,
+    attributes: &mut AttributeCache,
+    authors: &Authors,
+    include_patch: bool,
+    on_change: &mut impl FnMut(String, CommitInfo, bool) -> Result<(), E>,
+) -> Result<(), E> {
+    let author = authors.author_of(commit)?;
+    // Sorted, so a streamed export of the same history comes out the same every time
+    let mut modified_files: Vec<_> = modified_files.into_iter().collect();
+    modified_files.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    for (file_path, mut change) in modified_files {
+        // Skip .git directory and other hidden files
+        if file_path.starts_with(".git") || file_path.starts_with('.') {
+            continue;
+        }
+        
+        let path_attributes = attributes.get(&file_path)?;
+        if path_attributes.skip {
+            continue;
+        }
+        if path_attributes.binary {
+            change = FileChange::binary(include_patch, change.mode_change);
+        }
+        
+        let commit_info = CommitInfo {
+            commit_hash: commit.id().to_string(),
+            author_name: author.name.clone(),
+            author_email: author.email.clone(),
+            commit_message: commit.message().unwrap_or("").to_string(),
+            diff: include_patch.then_some(change.diff),
+            additions: change.additions,
+            deletions: change.deletions,
+        };
+        on_change(file_path, commit_info, path_attributes.binary)?;
+    }
+    Ok(())
+}
+
+/// Adds a commit to a file's history in `export_data`. A history is trimmed once it
+/// holds twice `max_history`, which keeps memory bounded without shifting the vector on
+/// every commit; the commits trimmed are counted in `dropped`.
+fn push_history(
+    export_data: &mut ExportData,
+    dropped: &mut HashMap<String, usize>,
+    max_history: Option<usize>,
+    file_path: String,
+    commit: CommitInfo,
+    marked_binary: bool,
+) {
+    // Use entry API to avoid a double lookup. A file marked binary starts
+    // out flagged, so its current contents are exported as binary too
+    let file_info = export_data.entry(file_path.clone()).or_insert_with(|| FileInfo {
+        current_contents: String::new(), // Will be populated later
+        binary: marked_binary,
+        symlink: false,
+        history: Vec::with_capacity(16), // Pre-allocate reasonable capacity
+        truncated: false,
+        total_commits: None,
+        blob_id: None,
+    });
+    
+    file_info.history.push(commit);
+    if let Some(max) = max_history && file_info.history.len() >= 2 * max.max(1) {
+        let excess = file_info.history.len() - max;
+        file_info.history.drain(..excess);
+        *dropped.entry(file_path).or_insert(0) += excess;
+    }
+}
+
+fn get_commit_file_changes(
+    repo: &Repository,
+    commit: &Commit,
+    parent_id: Option<Oid>,
+    diff_settings: DiffSettings,
+    include_patch: bool,
+) -> Result<HashMap<String, FileChange>> {
+    let mut file_changes = HashMap::new();
+    
+    let current_tree = commit.tree()?;
+    
+    if let Some(parent_id) = parent_id {
+        let parent_commit = repo.find_commit(parent_id)?;
+        let parent_tree = parent_commit.tree()?;
+        
+        let mut diff_options = diff_settings.diff_options();
+        let diff = repo.diff_tree_to_tree(Some(&parent_tree), Some(&current_tree), Some(&mut diff_options))?;
+        
+        // Process the full diff once and extract content and line stats for each file
+        diff.print(git2::DiffFormat::Patch, |delta, _hunk, line| {
+            if let Some(file_path) = get_file_path_from_delta(&delta) {
+                // Use entry API to avoid multiple HashMap lookups
+                let change = file_changes.entry(file_path).or_insert_with(|| FileChange {
+                    diff: String::with_capacity(if include_patch { 1024 } else { 0 }),
+                    mode_change: mode_change_of(&delta),
+                    ..Default::default()
+                });
+                
+                match line.origin() {
+                    '+' => change.additions += 1,
+                    '-' => change.deletions += 1,
+                    _ => {}
+                }
+                
+                if include_patch {
+                    // Append line content directly without intermediate allocations
+                    change.push_diff(std::str::from_utf8(line.content()).unwrap_or(""), diff_settings.max_diff_bytes);
+                }
+            }
+            true
+        })?;
+    } else {
+        // First commit - all files are additions. There is no context or whitespace to
+        // compare against, so the diff settings don't apply here.
+        let mut diff_options = DiffOptions::new();
+        diff_options.include_untracked(true);
+        
+        let diff = repo.diff_tree_to_tree(None, Some(&current_tree), Some(&mut diff_options))?;
+        
+        diff.foreach(
+            &mut |delta, _| {
+                if let Some(file_path) = get_file_path_from_delta(&delta)
+                    && let Ok(entry) = current_tree.get_path(Path::new(&file_path))
+                    && let Ok(object) = entry.to_object(repo)
+                    && object.kind() == Some(ObjectType::Blob)
+                {
+                    let blob = object.as_blob().unwrap();
+                    let bytes = blob.content();
+                    // Lines are counted on the raw bytes, the same count
+                    // str::lines() gives, so only the part of an oversized
+                    // blob that fits in the diff is decoded
+                    let additions = bytes.iter().filter(|&&byte| byte == b'\n').count()
+                        + usize::from(bytes.last().is_some_and(|&byte| byte != b'\n'));
+                                
+                    let mut change = FileChange { additions, ..Default::default() };
+                    if include_patch {
+                        let max_bytes = diff_settings.max_diff_bytes;
+                        let oversized = max_bytes.filter(|&max| bytes.len() > max);
+                        let content = String::from_utf8_lossy(&bytes[..oversized.unwrap_or(bytes.len())]);
+                        // Pre-allocate string capacity based on content size
+                        change.diff.reserve(content.len() + additions);
+                        for line in content.lines() {
+                            change.push_diff("+", max_bytes);
+                            change.push_diff(line, max_bytes);
+                            change.push_diff("\n", max_bytes);
+                        }
+                        if let Some(max) = oversized && !change.truncated {
+                            change.truncate_diff(max);
+                        }
+                    }
+                    file_changes.insert(file_path, change);
+                }
+                true
+            },
+            None,
+            None,
+            None,
+        )?;
+    }
+    
+    Ok(file_changes)
+}
+
+/// Collects the history of a single file, newest commit first while walking and returned
+/// in chronological order. Commits that leave the file's blob and mode unchanged are
+/// skipped without diffing; when a commit renames the file, older commits are matched
+/// against its previous path. Once the history holds `max_history_per_file` commits,
+/// older ones are only counted, which is returned alongside.
+fn follow_file_history(
+    repo: &Repository,
+    start: Oid,
+    path: &str,
+    settings: WalkSettings,
+    attributes: &mut AttributeCache,
+    authors: &Authors,
+) -> Result<(Vec<CommitInfo>, usize)> {
+    let WalkSettings { diff: diff_settings, include_patch, options } = settings;
+    let mut revwalk = revwalk_from(repo, start, options)?;
+    revwalk.set_sorting(git2::Sort::TIME)?;
+    
+    options.report(Progress::Started { step: Step::Follow, total: None });
+    
+    let mut current_path = path.to_string();
+    let mut history = Vec::new();
+    let mut dropped = 0;
+    
+    for (walked, commit_id) in revwalk.enumerate() {
+        let commit = repo.find_commit(commit_id?)?;
+        options.report(Progress::Advanced { step: Step::Follow, done: walked as u64 + 1 });
+        
+        let tree = commit.tree()?;
+        let parent_tree = if commit.parent_count() > 0 {
+            Some(commit.parent(0)?.tree()?)
+        } else {
+            None
+        };
+        
+        // Cheap check before diffing: did the blob or the mode at the current path change?
+        // A mode flip alone keeps the blob, and still belongs in the history
+        let blob_id = |tree: &Tree| tree.get_path(Path::new(&current_path)).ok().map(|entry| (entry.id(), entry.filemode()));
+        if blob_id(&tree) == parent_tree.as_ref().and_then(blob_id) {
+            continue;
+        }
+        
+        let mut diff_options = diff_settings.diff_options();
+        let mut diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), Some(&mut diff_options))?;
+        diff.find_similar(None)?;
+        
+        let current = Some(Path::new(&current_path));
+        let Some(index) = diff.deltas().position(|delta| {
+            delta.new_file().path() == current || (delta.status() == Delta::Deleted && delta.old_file().path() == current)
+        }) else {
+            continue;
+        };
+        let delta = diff.get_delta(index).unwrap();
+        // Older commits know the file by its name before a rename
+        let previous_path = (delta.status() == Delta::Renamed)
+            .then(|| delta.old_file().path().map(|old_path| old_path.to_string_lossy().to_string()))
+            .flatten();
+        let full = options.max_history_per_file.is_some_and(|max| history.len() >= max);
+        if full || !options.includes_commit(&commit) {
+            if full && options.includes_commit(&commit) {
+                dropped += 1;
+            }
+            if let Some(previous_path) = previous_path {
+                current_path = previous_path;
+            }
+            continue;
+        }
+        
+        let mut change = FileChange::default();
+        if attributes.get(&current_path)?.binary {
+            change = FileChange::binary(include_patch, mode_change_of(&delta));
+        } else if let Some(mut patch) = Patch::from_diff(&diff, index)? {
+            let (_, additions, deletions) = patch.line_stats()?;
+            change.additions = additions;
+            change.deletions = deletions;
+            if include_patch {
+                // Same text layout as the full export: root commits list every line
+                // with a '+', other commits use the patch content as-is
+                patch.print(&mut |_delta, _hunk, line| {
+                    let content = std::str::from_utf8(line.content()).unwrap_or("");
+                    if parent_tree.is_none() {
+                        if line.origin() == '+' {
+                            change.push_diff("+", diff_settings.max_diff_bytes);
+                            change.push_diff(content, diff_settings.max_diff_bytes);
+                        }
+                    } else {
+                        change.push_diff(content, diff_settings.max_diff_bytes);
+                    }
+                    true
+                })?;
+            }
+        }
+        
+        let author = authors.author_of(&commit)?;
+        history.push(CommitInfo {
+            commit_hash: commit.id().to_string(),
+            author_name: author.name,
+            author_email: author.email,
+            commit_message: commit.message().unwrap_or("").to_string(),
+            diff: include_patch.then_some(change.diff),
+            additions: change.additions,
+            deletions: change.deletions,
+        });
+        
+        if let Some(previous_path) = previous_path {
+            current_path = previous_path;
+        }
+    }
+    
+    options.report(Progress::Finished { step: Step::Follow });
+    
+    history.reverse();
+    Ok((history, dropped))
+}
+
+/// Diffs a merge commit against each of its parents and combines the per-file changes.
+fn get_merge_file_changes(
+    repo: &Repository,
+    commit: &Commit,
+    diff_settings: DiffSettings,
+    include_patch: bool,
+) -> Result<HashMap<String, FileChange>> {
+    let mut file_changes: HashMap<String, FileChange> = HashMap::new();
+    
+    for parent_id in commit.parent_ids() {
+        for (file_path, change) in get_commit_file_changes(repo, commit, Some(parent_id), diff_settings, include_patch)? {
+            match file_changes.entry(file_path) {
+                Entry::Occupied(mut existing) => {
+                    let existing = existing.get_mut();
+                    existing.push_diff(&change.diff, diff_settings.max_diff_bytes);
+                    existing.additions += change.additions;
+                    existing.deletions += change.deletions;
+                }
+                Entry::Vacant(slot) => {
+                    slot.insert(change);
+                }
+            }
+        }
+    }
+    
+    Ok(file_changes)
+}
+
+/// The old and new mode of a file whose mode the delta changes. Added and deleted
+/// files have no mode on one side, which isn't a mode change.
+fn mode_change_of(delta: &DiffDelta) -> Option<(i32, i32)> {
+    let old_mode = i32::from(delta.old_file().mode());
+    let new_mode = i32::from(delta.new_file().mode());
+    (old_mode != new_mode && old_mode != 0 && new_mode != 0).then_some((old_mode, new_mode))
+}
+
+fn get_file_path_from_delta(delta: &DiffDelta) -> Option<String> {
+    if let Some(new_file) = delta.new_file().path() {
+        Some(new_file.to_string_lossy().to_string())
+    } else {
+        delta.old_file().path().map(|old_file| old_file.to_string_lossy().to_string())
+    }
+}
+
+/// Treats content as binary if a null byte appears within the first `scan_bytes` bytes.
+fn classify_content(content: &[u8], scan_bytes: usize) -> ContentKind {
+    let check_len = std::cmp::min(content.len(), scan_bytes);
+    if content[..check_len].contains(&0) {
+        ContentKind::Binary
+    } else {
+        ContentKind::Text
+    }
+}
+
+/// Reads a file's contents from the tree. Files marked binary in .gitattributes are binary
+/// whatever their contents. A symlink's blob holds its target path, which is returned as
+/// the contents with the symlink flag set rather than passed off as a regular file.
+fn read_current_contents(repo: &Repository, tree: &Tree, file_path: &str, binary_handling: BinaryHandling, marked_binary: bool) -> CurrentContents {
+    // Check if file exists in the target tree
+    let Ok(entry) = tree.get_path(Path::new(file_path)) else {
+        return CurrentContents::marker("[deleted]");
+    };
+    let symlink = entry.filemode() == i32::from(FileMode::Link);
+    // The size comes from the object header, so oversized blobs are never loaded
+    if let Some(max_bytes) = binary_handling.max_bytes.filter(|_| !symlink) {
+        let header = repo.odb().and_then(|odb| odb.read_header(entry.id()));
+        if let Ok((size, ObjectType::Blob)) = header && size > max_bytes {
+            return CurrentContents { contents: omitted_placeholder(size), binary: false, symlink, blob_id: Some(entry.id()) };
+        }
+    }
+    let Ok(object) = entry.to_object(repo) else {
+        return CurrentContents::marker("[deleted]");
+    };
+    let Some(blob) = object.as_blob() else {
+        return CurrentContents::marker("[Binary file or unreadable]");
+    };
+    
+    let content = blob.content();
+    if symlink {
+        let target = String::from_utf8_lossy(content).to_string();
+        return CurrentContents { contents: target, binary: false, symlink, blob_id: Some(blob.id()) };
+    }
+    let kind = if marked_binary { ContentKind::Binary } else { classify_content(content, binary_handling.scan_bytes) };
+    match kind {
+        ContentKind::Text => CurrentContents {
+            contents: String::from_utf8_lossy(content).to_string(),
+            binary: false,
+            symlink: false,
+            blob_id: Some(blob.id()),
+        },
+        ContentKind::Binary => {
+            let contents = match binary_handling.mode {
+                BinaryMode::Placeholder => BINARY_PLACEHOLDER.to_string(),
+                BinaryMode::Base64 => base64::engine::general_purpose::STANDARD.encode(content),
+                BinaryMode::Skip => String::new(),
+            };
+            CurrentContents { contents, binary: true, symlink: false, blob_id: Some(blob.id()) }
+        }
+    }
+}
+
+/// Moves the current contents into a map keyed by blob OID, leaving the OID in each
+/// file's currentContents. Files sharing a blob share one entry.
+pub fn dedup_contents(export_data: &mut ExportData) -> BTreeMap<String, String> {
+    let mut blobs = BTreeMap::new();
+    for file_info in export_data.values_mut() {
+        let Some(blob_id) = file_info.blob_id else {
+            continue;
+        };
+        let oid = blob_id.to_string();
+        let contents = std::mem::replace(&mut file_info.current_contents, oid.clone());
+        blobs.entry(oid).or_insert(contents);
+    }
+    blobs
+}
+
+fn populate_current_contents(repo: &Repository, tree: &Tree, export_data: &mut ExportData, binary_handling: BinaryHandling, options: &ExportOptions) -> Result<()> {
+    let total_files = export_data.len();
+    options.report(Progress::Started { step: Step::CurrentContents, total: Some(total_files as u64) });
+    
+    let processed_count = AtomicUsize::new(0);
+    let update_interval = std::cmp::max(1, total_files / 100); // Update every 1% of files
+    
+    // Files are read in parallel. git2 handles can't be shared between threads, so
+    // each worker opens its own Repository and looks the tree up by id.
+    let repo_path = repo.path().to_path_buf();
+    let tree_id = tree.id();
+    
+    export_data.par_iter_mut().try_for_each_init(
+        || Repository::open(&repo_path),
+        |thread_repo, (file_path, file_info)| -> Result<()> {
+            let thread_repo = match thread_repo {
+                Ok(thread_repo) => thread_repo,
+                // The failed open is kept per thread, so each error is rebuilt from it
+                Err(e) => return Err(HistoryError::RepoOpen {
+                    path: repo_path.clone(),
+                    source: git2::Error::new(e.code(), e.class(), e.message()),
+                }),
+            };
+            let tree = thread_repo.find_tree(tree_id)?;
+            
+            // Set from .gitattributes while walking history; otherwise the contents decide
+            let current = read_current_contents(thread_repo, &tree, file_path, binary_handling, file_info.binary);
+            file_info.current_contents = current.contents;
+            file_info.binary = current.binary;
+            file_info.symlink = current.symlink;
+            file_info.blob_id = current.blob_id;
+            
+            let processed = processed_count.fetch_add(1, Ordering::Relaxed) + 1;
+            // Batch progress updates for better performance
+            if processed.is_multiple_of(update_interval) || processed == total_files {
+                options.report(Progress::Advanced { step: Step::CurrentContents, done: processed as u64 });
+            }
+            Ok(())
+        },
+    )?;
+    
+    options.report(Progress::Finished { step: Step::CurrentContents });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a fresh repository in the temp directory
+    fn temp_repo(name: &str) -> Repository {
+        let path = std::env::temp_dir().join(format!("history-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        Repository::init(&path).unwrap()
+    }
+
+    /// Commits a root-level tree holding `files` on top of `parents`
+    fn commit_files(repo: &Repository, parents: &[Oid], files: &[(&str, &str)]) -> Oid {
+        let files: Vec<_> = files.iter().map(|&(name, contents)| (name, contents, FileMode::Blob)).collect();
+        commit_tree(repo, None, parents, &files)
+    }
+
+    /// Commits a root-level tree holding `files` with their modes on top of `parents`,
+    /// moving `update_ref` to the new commit if given
+    fn commit_tree(repo: &Repository, update_ref: Option<&str>, parents: &[Oid], files: &[(&str, &str, FileMode)]) -> Oid {
+        commit_tree_at(repo, update_ref, parents, files, git2::Signature::now("Test", "test@example.com").unwrap())
+    }
+
+    /// Like `commit_tree`, with `signature` as author and committer. Commits made within
+    /// the same second walk in no particular order, so tests of walk order set the time
+    fn commit_tree_at(repo: &Repository, update_ref: Option<&str>, parents: &[Oid], files: &[(&str, &str, FileMode)], signature: git2::Signature) -> Oid {
+        let mut builder = repo.treebuilder(None).unwrap();
+        for (name, contents, mode) in files {
+            let blob = repo.blob(contents.as_bytes()).unwrap();
+            builder.insert(name, blob, i32::from(*mode)).unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let parents: Vec<Commit> = parents.iter().map(|id| repo.find_commit(*id).unwrap()).collect();
+        let parents: Vec<&Commit> = parents.iter().collect();
+        repo.commit(update_ref, &signature, &signature, "commit", &tree, &parents).unwrap()
+    }
+
+    /// A merge of two branches that each changed a different line of the same file
+    fn merge_of_two_edits(repo: &Repository) -> Oid {
+        let base = commit_files(repo, &[], &[("file.txt", "a\nb\nc\n")]);
+        let ours = commit_files(repo, &[base], &[("file.txt", "A\nb\nc\n")]);
+        let theirs = commit_files(repo, &[base], &[("file.txt", "a\nb\nC\n")]);
+        commit_files(repo, &[ours, theirs], &[("file.txt", "A\nb\nC\n")])
+    }
+
+    #[test]
+    fn merge_changes_sum_stats_across_parents() {
+        let repo = temp_repo("merge-stats");
+        let merge = repo.find_commit(merge_of_two_edits(&repo)).unwrap();
+        let changes = get_merge_file_changes(&repo, &merge, DiffSettings::default(), true).unwrap();
+        let change = &changes["file.txt"];
+        // Against the first parent the merge changed line 3, against the second line 1
+        assert_eq!((change.additions, change.deletions), (2, 2));
+        assert!(change.diff.contains("C\n") && change.diff.contains("A\n"));
+        assert!(!change.truncated);
+    }
+
+    #[test]
+    fn merge_diff_respects_max_bytes() {
+        let repo = temp_repo("merge-truncate");
+        let merge = repo.find_commit(merge_of_two_edits(&repo)).unwrap();
+        let single_parent = get_commit_file_changes(&repo, &merge, Some(merge.parent_id(0).unwrap()), DiffSettings::default(), true).unwrap();
+        let max_bytes = single_parent["file.txt"].diff.len() + 4;
+        let diff_settings = DiffSettings { max_diff_bytes: Some(max_bytes), ..DiffSettings::default() };
+        let changes = get_merge_file_changes(&repo, &merge, diff_settings, true).unwrap();
+        let change = &changes["file.txt"];
+        assert!(change.truncated);
+        let marker = format!("[truncated: diff exceeds {} bytes]\n", max_bytes);
+        assert!(change.diff.ends_with(&marker));
+        assert!(change.diff.len() <= max_bytes + 1 + marker.len());
+        assert_eq!((change.additions, change.deletions), (2, 2));
+    }
+
+    #[test]
+    fn streamed_history_comes_oldest_commit_first_with_paths_sorted() {
+        let repo = temp_repo("stream");
+        let commit = |parents: &[Oid], a: &str, b: &str, seconds: i64| {
+            let signature = git2::Signature::new("Test", "test@example.com", &git2::Time::new(seconds, 0)).unwrap();
+            commit_tree_at(&repo, Some("HEAD"), parents, &[("b.txt", b, FileMode::Blob), ("a.txt", a, FileMode::Blob)], signature)
+        };
+        let first = commit(&[], "a\n", "b\n", 1_000);
+        let second = commit(&[first], "a\n", "b\nb\n", 2_000);
+        let third = commit(&[second], "a\na\n", "b\n", 3_000);
+        
+        let options = ExportOptions { include_patch: false, include_contents: false, ..ExportOptions::default() };
+        let mut rows = Vec::new();
+        stream_history(&repo, &options, |path, commit| {
+            rows.push((path.to_string(), commit.commit_hash.clone(), commit.additions, commit.deletions));
+            Ok::<_, HistoryError>(())
+        }).unwrap();
+        let row = |path: &str, commit: Oid, additions, deletions| (path.to_string(), commit.to_string(), additions, deletions);
+        assert_eq!(rows, [row("a.txt", first, 1, 0), row("b.txt", first, 1, 0), row("b.txt", second, 1, 0), row("a.txt", third, 1, 0), row("b.txt", third, 0, 1)]);
+        
+        // The same commits export_history collects per file
+        let export_data = export_history(&repo, &options).unwrap();
+        for (path, file_info) in &export_data {
+            let streamed: Vec<_> = rows.iter().filter(|row| &row.0 == path).map(|row| row.1.as_str()).collect();
+            let exported: Vec<_> = file_info.history.iter().map(|commit| commit.commit_hash.as_str()).collect();
+            assert_eq!(streamed, exported);
+        }
+    }
+
+    #[test]
+    fn repeated_exports_serialize_to_the_same_bytes() {
+        let repo = temp_repo("deterministic");
+        let files: Vec<(String, String)> = (0..50).map(|n| (format!("file{}.txt", n), format!("{}\n", n))).collect();
+        let files: Vec<(&str, &str, FileMode)> = files.iter().map(|(name, contents)| (name.as_str(), contents.as_str(), FileMode::Blob)).collect();
+        let first = commit_tree(&repo, Some("HEAD"), &[], &files);
+        commit_tree(&repo, Some("HEAD"), &[first], &files[..25]);
+        
+        let export = || serde_json::to_vec_pretty(&export_history(&repo, &ExportOptions::default()).unwrap()).unwrap();
+        assert_eq!(export(), export());
+    }
+
+    /// A script committed, then made executable, then edited, on HEAD
+    fn mode_flip_repo(name: &str, extra_files: &[(&str, &str, FileMode)]) -> Repository {
+        let repo = temp_repo(name);
+        let with = |script: (&'static str, &'static str, FileMode)| {
+            let mut files = vec![script];
+            files.extend_from_slice(extra_files);
+            files
+        };
+        let first = commit_tree(&repo, Some("HEAD"), &[], &with(("run.sh", "echo hi\n", FileMode::Blob)));
+        let second = commit_tree(&repo, Some("HEAD"), &[first], &with(("run.sh", "echo hi\n", FileMode::BlobExecutable)));
+        commit_tree(&repo, Some("HEAD"), &[second], &with(("run.sh", "echo hi\necho bye\n", FileMode::BlobExecutable)));
+        repo
+    }
+
+    fn mode_flip_diff(history: &[CommitInfo]) -> &str {
+        assert_eq!(history.len(), 3);
+        history.iter().map(|commit| commit.diff.as_deref().unwrap()).find(|diff| diff.contains("old mode")).unwrap()
+    }
+
+    #[test]
+    fn mode_only_change_is_in_the_history() {
+        let repo = mode_flip_repo("mode-flip", &[]);
+        let export_data = export_history(&repo, &ExportOptions { include_contents: false, ..ExportOptions::default() }).unwrap();
+        let diff = mode_flip_diff(&export_data["run.sh"].history);
+        assert!(diff.contains("old mode 100644\nnew mode 100755\n"));
+        
+        let file_info = follow_file(&repo, "run.sh", &ExportOptions::default()).unwrap();
+        assert!(mode_flip_diff(&file_info.history).contains("old mode 100644\nnew mode 100755\n"));
+    }
+
+    #[test]
+    fn mode_change_of_a_file_marked_binary_keeps_the_mode_lines() {
+        let attributes = (".gitattributes", "*.sh binary\n", FileMode::Blob);
+        let repo = mode_flip_repo("mode-flip-binary", &[attributes]);
+        let export_data = export_history(&repo, &ExportOptions { include_contents: false, ..ExportOptions::default() }).unwrap();
+        let diff = mode_flip_diff(&export_data["run.sh"].history);
+        assert_eq!(diff, format!("old mode 100644\nnew mode 100755\n{}", BINARY_PLACEHOLDER));
+        
+        let file_info = follow_file(&repo, "run.sh", &ExportOptions::default()).unwrap();
+        assert_eq!(mode_flip_diff(&file_info.history), diff);
+    }
+
+    #[test]
+    fn following_a_path_no_commit_has_is_an_error() {
+        let repo = mode_flip_repo("follow-missing", &[]);
+        let error = follow_file(&repo, "missing.sh", &ExportOptions::default()).unwrap_err();
+        assert!(matches!(error, HistoryError::PathNotFound { .. }));
+    }
+
+    #[test]
+    fn following_a_path_left_out_by_since_commit_is_empty() {
+        let repo = mode_flip_repo("follow-since", &[]);
+        let options = ExportOptions { since_commit: Some("HEAD".to_string()), ..ExportOptions::default() };
+        let file_info = follow_file(&repo, "run.sh", &options).unwrap();
+        assert!(file_info.history.is_empty());
+    }
+
+    #[test]
+    fn gitattributes_come_from_the_exported_commit() {
+        let repo = temp_repo("attributes-commit");
+        let script = ("run.sh", "echo hi\n", FileMode::Blob);
+        let head = commit_tree(&repo, Some("HEAD"), &[], &[script]);
+        commit_tree(&repo, Some("refs/heads/marked"), &[head], &[script, (".gitattributes", "*.sh binary\n", FileMode::Blob)]);
+        // Rules in the working tree are not part of any commit and don't apply
+        std::fs::write(repo.workdir().unwrap().join(".gitattributes"), "*.sh export-ignore\n").unwrap();
+        
+        let export_data = export_history(&repo, &ExportOptions::default()).unwrap();
+        assert!(!export_data["run.sh"].binary);
+        
+        let marked = ExportOptions { revision: "marked".to_string(), ..ExportOptions::default() };
+        let export_data = export_history(&repo, &marked).unwrap();
+        assert!(export_data["run.sh"].binary);
+    }
+}
@@ -1,11 +1,20 @@
-use anyhow::{Context, Result};
-use clap::Parser;
-use git2::{Repository, Commit, DiffOptions, ObjectType, Oid, DiffDelta};
+mod csv_output;
+mod sqlite;
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, ValueEnum};
 use indicatif::{ProgressBar, ProgressStyle};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use git_history_exporter::{BinaryMode, ExportData, ExportOptions, MergeDiff, Progress, ProgressCallback, Step, dedup_contents, export_history, follow_file, open_repository};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -13,10 +22,30 @@ struct Args {
     /// Path to the git repository directory
     repo_path: PathBuf,
     
-    /// Output JSON file path
+    /// Branch, tag, or other revision to export (defaults to HEAD)
+    #[arg(long = "ref", value_name = "REF", default_value = "HEAD")]
+    ref_name: String,
+    
+    /// Output file path
     #[arg(short, long)]
     output: Option<PathBuf>,
     
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    format: OutputFormat,
+    
+    /// Write one JSON file per source path into this directory, plus an index.json
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["output", "format"])]
+    split_output: Option<PathBuf>,
+    
+    /// Export only this file's history, following renames, as a single JSON object
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["split_output", "format"])]
+    follow: Option<String>,
+    
+    /// Gzip the JSON output. Implied when the output path ends in .gz
+    #[arg(long)]
+    gzip: bool,
+    
     /// Pretty-print JSON output
     #[arg(long)]
     pretty: bool,
@@ -24,59 +53,269 @@ struct Args {
     /// Suppress output messages and progress bars
     #[arg(long)]
     silent: bool,
+    
+    /// Number of leading bytes scanned for a null byte when deciding whether a file is binary
+    #[arg(long, value_name = "N", default_value_t = 8192)]
+    binary_scan_bytes: usize,
+    
+    /// How the current contents of binary files are exported
+    #[arg(long, value_enum, default_value_t = BinaryMode::Placeholder)]
+    binary_mode: BinaryMode,
+    
+    /// Lines of unchanged context around each hunk (git's default is 3). The first
+    /// commit's diff lists whole files as additions, so it is unaffected
+    #[arg(long, value_name = "N")]
+    context_lines: Option<u32>,
+    
+    /// Ignore all whitespace changes when diffing
+    #[arg(long)]
+    ignore_whitespace: bool,
+    
+    /// Ignore whitespace changes at the end of lines when diffing
+    #[arg(long)]
+    ignore_whitespace_eol: bool,
+    
+    /// How merge commits are diffed: against their first parent only, or against every parent
+    #[arg(long, value_enum, default_value_t = MergeDiff::FirstParent)]
+    merge_diff: MergeDiff,
+    
+    /// Omit the textual diff and keep only the per-file line stats
+    #[arg(long)]
+    no_patch: bool,
+    
+    /// Ignore .gitattributes. By default paths marked export-ignore or linguist-generated
+    /// are left out, and paths marked binary or -diff are exported as binary files
+    #[arg(long)]
+    no_gitattributes: bool,
+    
+    /// Store each distinct current-contents blob once. The JSON output becomes
+    /// `{"files": {...}, "blobs": {oid: contents}}`, and the currentContents of every file
+    /// present at the exported revision is its blob's OID, a key of `blobs`. Deleted
+    /// files keep their "[deleted]" placeholder
+    #[arg(long, conflicts_with_all = ["split_output", "follow"])]
+    dedup_contents: bool,
+    
+    /// Files larger than N bytes get an "[omitted: <size> bytes]" placeholder as their
+    /// current contents, and each file's diff in a commit is cut off after N bytes
+    #[arg(long, value_name = "N")]
+    max_file_bytes: Option<usize>,
+    
+    /// JSON object mapping author identities, `Name <email>` or a bare email, to the
+    /// canonical `Name <email>` exported in their place. Unmapped authors are kept as they
+    /// are. With --use-mailmap the map is applied after .mailmap, so its entries win
+    #[arg(long, value_name = "FILE")]
+    authors_map: Option<PathBuf>,
+    
+    /// Rewrite author names and emails through the repository's .mailmap
+    #[arg(long)]
+    use_mailmap: bool,
+    
+    /// Export only commits whose message matches this regular expression, like
+    /// `git log --grep`. Files touched only by other commits are left out
+    #[arg(long, value_name = "REGEX")]
+    grep: Option<Regex>,
+    
+    /// Export the commits whose message does not match --grep instead
+    #[arg(long, requires = "grep")]
+    grep_invert: bool,
+    
+    /// Leave out this commit and everything before it, exporting only the commits
+    /// reachable from --ref but not from REV, like `git log REV..REF`
+    #[arg(long, value_name = "REV")]
+    since_commit: Option<String>,
+    
+    /// Keep only the N newest commits of each file. Truncated files get `"truncated":
+    /// true` and a `total_commits` count of every commit that touched them. Not
+    /// supported for csv and tsv output, which is written before the newest are known
+    #[arg(long, value_name = "N")]
+    max_history_per_file: Option<usize>,
+    
+    /// Threads diffing commits [default: one per CPU]
+    #[arg(long, value_name = "N")]
+    threads: Option<usize>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct CommitInfo {
-    commit_hash: String,
-    commit_message: String,
-    diff: String,
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// A single JSON document keyed by file path
+    Json,
+    /// A SQLite database with `files` and `commits` tables
+    Sqlite,
+    /// One comma-separated row per file and commit, without diffs or contents
+    Csv,
+    /// Like csv, separated by tabs
+    Tsv,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct FileInfo {
-    #[serde(rename = "currentContents")]
-    current_contents: String,
-    history: Vec<CommitInfo>,
+impl OutputFormat {
+    fn default_file_name(self) -> &'static str {
+        match self {
+            OutputFormat::Json => "history_exported.json",
+            OutputFormat::Sqlite => "history_exported.db",
+            OutputFormat::Csv => "history_exported.csv",
+            OutputFormat::Tsv => "history_exported.tsv",
+        }
+    }
+    
+    /// Flat formats only hold per-commit metadata, so diffs and current contents
+    /// aren't computed for them
+    fn is_tabular(self) -> bool {
+        matches!(self, OutputFormat::Csv | OutputFormat::Tsv)
+    }
 }
 
-type ExportData = HashMap<String, FileInfo>;
+/// Draws an export's progress as a bar per step, or a spinner while following `follow_path`
+fn progress_bars(follow_path: Option<String>) -> Box<ProgressCallback> {
+    let bar: Mutex<Option<ProgressBar>> = Mutex::new(None);
+    let follow_path = follow_path.unwrap_or_default();
+    Box::new(move |progress| match progress {
+        Progress::Started { step, total } => {
+            let pb = match (step, total) {
+                (Step::Follow, _) | (_, None) => ProgressBar::new_spinner(),
+                (_, Some(total)) => {
+                    let color = if step == Step::Commits { "green" } else { "cyan" };
+                    let pb = ProgressBar::new(total);
+                    pb.set_style(
+                        ProgressStyle::default_bar()
+                            .template(&format!("[{{elapsed_precise}}/{{eta_precise}}] {{bar:40.{}/blue}} {{pos:>7}}/{{len:7}} {{msg}} [{{per_sec}}]", color))
+                            .unwrap()
+                            .progress_chars("##-")
+                    );
+                    pb
+                }
+            };
+            pb.set_message(match step {
+                Step::Commits => "Processing commits".to_string(),
+                Step::CurrentContents => "Reading current file contents".to_string(),
+                Step::Follow => format!("Following {}", follow_path),
+            });
+            *bar.lock().unwrap() = Some(pb);
+        }
+        Progress::Advanced { done, .. } => {
+            if let Some(pb) = bar.lock().unwrap().as_ref() {
+                pb.set_position(done);
+            }
+        }
+        Progress::Finished { step } => {
+            if let Some(pb) = bar.lock().unwrap().take() {
+                pb.finish_with_message(match step {
+                    Step::Commits => "Finished processing commits".to_string(),
+                    Step::CurrentContents => "Finished reading current file contents".to_string(),
+                    Step::Follow => format!("Finished following {}", follow_path),
+                });
+            }
+        }
+        Progress::SkippedPaths(count) => {
+            println!("Skipped {} paths marked export-ignore or linguist-generated in .gitattributes", count);
+        }
+    })
+}
+
+/// The JSON document written with --dedup-contents
+#[derive(Serialize, Debug)]
+struct DedupedExport<'a> {
+    files: &'a ExportData,
+    blobs: BTreeMap<String, String>,
+}
 
 fn main() -> Result<()> {
     let args = Args::parse();
     
-    // Set default output file to "history_exported.json" within the repo directory
-    let output_path = args.output.unwrap_or_else(|| args.repo_path.join("history_exported.json"));
+    if args.gzip && args.format != OutputFormat::Json {
+        bail!("--gzip is only supported for JSON output");
+    }
+    if args.dedup_contents && args.format != OutputFormat::Json {
+        bail!("--dedup-contents is only supported for JSON output");
+    }
+    // Tabular rows are written as commits are diffed, before a file's newest commits are known
+    if args.max_history_per_file.is_some() && args.format.is_tabular() {
+        bail!("--max-history-per-file is not supported for csv or tsv output");
+    }
+    if args.max_history_per_file == Some(0) {
+        bail!("--max-history-per-file must be greater than zero");
+    }
+    if args.threads == Some(0) {
+        bail!("--threads must be greater than zero");
+    }
+    
+    let repo = open_repository(&args.repo_path)?;
+    
+    // A bare repository has no working tree to put the default output file in
+    if repo.is_bare() && args.output.is_none() && args.split_output.is_none() {
+        bail!("bare repository: --output is required");
+    }
+    
+    // Set default output file (e.g. "history_exported.json") within the repo directory
+    let output_path = match (&args.split_output, args.output) {
+        (Some(dir), _) => dir.clone(),
+        (None, Some(output)) => output,
+        (None, None) if args.gzip => args.repo_path.join(format!("{}.gz", args.format.default_file_name())),
+        (None, None) => args.repo_path.join(args.format.default_file_name()),
+    };
+    let gzip = args.gzip || (args.split_output.is_none() && output_path.extension().is_some_and(|ext| ext == "gz"));
     
     if !args.silent {
         println!("Exporting Git repository from: {}", args.repo_path.display());
+        println!("Revision: {}", args.ref_name);
         println!("Output file: {}", output_path.display());
     }
     
-    let repo = Repository::open(&args.repo_path)
-        .with_context(|| format!("Failed to open repository at {}", args.repo_path.display()))?;
-    
-    // Pre-allocate HashMap with estimated capacity to reduce reallocations
-    let mut export_data: ExportData = HashMap::with_capacity(1000);
+    let options = ExportOptions {
+        revision: args.ref_name.clone(),
+        binary_scan_bytes: args.binary_scan_bytes,
+        binary_mode: args.binary_mode,
+        context_lines: args.context_lines,
+        ignore_whitespace: args.ignore_whitespace,
+        ignore_whitespace_eol: args.ignore_whitespace_eol,
+        merge_diff: args.merge_diff,
+        include_patch: !args.no_patch && !args.format.is_tabular(),
+        include_contents: !args.format.is_tabular(),
+        gitattributes: !args.no_gitattributes,
+        max_file_bytes: args.max_file_bytes,
+        authors_map: args.authors_map.clone(),
+        use_mailmap: args.use_mailmap,
+        grep: args.grep.clone(),
+        grep_invert: args.grep_invert,
+        since_commit: args.since_commit.clone(),
+        max_history_per_file: args.max_history_per_file,
+        threads: args.threads,
+        progress: (!args.silent).then(|| progress_bars(args.follow.clone())),
+    };
     
-    // First, process commits to discover all files that have ever existed
-    // This will also build up the history for all files
-    process_commit_history(&repo, &mut export_data, args.silent)?;
+    if let Some(follow_path) = &args.follow {
+        let file_info = follow_file(&repo, follow_path, &options)?;
+        write_json(&file_info, &output_path, args.pretty, gzip)?;
+        
+        if !args.silent {
+            println!("Successfully exported {} commits of {} to {}", file_info.history.len(), follow_path, output_path.display());
+        }
+        return Ok(());
+    }
     
-    // Now get current contents for files that still exist
-    populate_current_contents(&repo, &args.repo_path, &mut export_data, args.silent)?;
+    if args.format.is_tabular() {
+        let delimiter = if args.format == OutputFormat::Tsv { b'\t' } else { b',' };
+        let rows = csv_output::write_csv(&repo, &options, &output_path, delimiter)?;
+        if !args.silent {
+            println!("Successfully exported {} rows to {}", rows, output_path.display());
+        }
+        return Ok(());
+    }
     
-    // Write to JSON file
-    let json_output = if args.pretty {
-        serde_json::to_string_pretty(&export_data)
-            .context("Failed to serialize data to JSON")?
-    } else {
-        serde_json::to_string(&export_data)
-            .context("Failed to serialize data to JSON")?
-    };
+    let mut export_data = export_history(&repo, &options)?;
     
-    fs::write(&output_path, json_output)
-        .with_context(|| format!("Failed to write to output file {}", output_path.display()))?;
+    match args.format {
+        _ if args.split_output.is_some() => write_split_output(&export_data, &output_path, args.pretty, gzip)?,
+        OutputFormat::Json if args.dedup_contents => {
+            let blobs = dedup_contents(&mut export_data);
+            if !args.silent {
+                println!("Stored the current contents of {} files as {} distinct blobs", export_data.values().filter(|file_info| file_info.blob_id.is_some()).count(), blobs.len());
+            }
+            write_json(&DedupedExport { files: &export_data, blobs }, &output_path, args.pretty, gzip)?
+        }
+        OutputFormat::Json => write_json(&export_data, &output_path, args.pretty, gzip)?,
+        OutputFormat::Sqlite => sqlite::write_sqlite(&export_data, &output_path)?,
+        OutputFormat::Csv | OutputFormat::Tsv => unreachable!("tabular output is streamed above"),
+    }
     
     if !args.silent {
         println!("Successfully exported {} files to {}", export_data.len(), output_path.display());
@@ -85,250 +324,90 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn process_commit_history(repo: &Repository, export_data: &mut ExportData, silent: bool) -> Result<()> {
-    let mut revwalk = repo.revwalk()?;
-    
-    // Start from HEAD and walk backwards through history
-    revwalk.push_head()?;
-    revwalk.set_sorting(git2::Sort::TIME | git2::Sort::REVERSE)?; // REVERSE for chronological order
-    
-    // Get total count for progress bar (this is much more memory efficient)
-    let total_commits = {
-        let mut count_walk = repo.revwalk()?;
-        count_walk.push_head()?;
-        count_walk.count()
-    };
+fn write_json<T: Serialize + ?Sized>(export_data: &T, output_path: &Path, pretty: bool, gzip: bool) -> Result<()> {
+    let file = File::create(output_path)
+        .with_context(|| format!("Failed to create output file {}", output_path.display()))?;
+    let mut writer = BufWriter::new(file);
     
-    let commit_pb = if !silent {
-        let pb = ProgressBar::new(total_commits as u64);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("[{elapsed_precise}/{eta_precise}] {bar:40.green/blue} {pos:>7}/{len:7} {msg} [{per_sec}]")
-                .unwrap()
-                .progress_chars("##-")
-        );
-        pb.set_message("Processing commits");
-        Some(pb)
+    if gzip {
+        let mut encoder = GzEncoder::new(&mut writer, Compression::default());
+        serialize_json(&mut encoder, export_data, pretty)?;
+        // finish() writes the gzip trailer; dropping the encoder would swallow any error
+        encoder.finish()
+            .with_context(|| format!("Failed to finish gzip stream for {}", output_path.display()))?;
     } else {
-        None
-    };
-    
-    // Process commits as we iterate (streaming)
-    let mut processed_count = 0;
-    let update_interval = std::cmp::max(1, total_commits / 100); // Update every 1% of commits
-    
-    for commit_id in revwalk {
-        let commit_id = commit_id?;
-        let commit = repo.find_commit(commit_id)?;
-        let parent_id = if commit.parent_count() > 0 {
-            Some(commit.parent(0)?.id())
-        } else {
-            None
-        };
-        
-        // Get the diff for this commit
-        let modified_files = get_commit_file_changes(repo, &commit, parent_id)?;
-        
-        for (file_path, diff) in modified_files {
-            // Skip .git directory and other hidden files
-            if file_path.starts_with(".git") || file_path.starts_with('.') {
-                continue;
-            }
-            
-            // Use entry API to avoid double HashMap lookup
-            let file_info = export_data.entry(file_path.clone()).or_insert_with(|| FileInfo {
-                current_contents: String::new(), // Will be populated later
-                history: Vec::with_capacity(16), // Pre-allocate reasonable capacity
-            });
-            
-            // Add to history
-            file_info.history.push(CommitInfo {
-                commit_hash: commit.id().to_string(),
-                commit_message: commit.message().unwrap_or("").to_string(),
-                diff,
-            });
-        }
-        
-        processed_count += 1;
-        // Batch update progress bar for better performance
-        if processed_count % update_interval == 0 || processed_count == total_commits {
-            if let Some(pb) = &commit_pb {
-                pb.set_position(processed_count as u64);
-            }
-        }
+        serialize_json(&mut writer, export_data, pretty)?;
     }
     
-    if let Some(pb) = commit_pb {
-        pb.finish_with_message("Finished processing commits");
-    }
+    writer.flush()
+        .with_context(|| format!("Failed to write to output file {}", output_path.display()))?;
     
     Ok(())
 }
 
-fn get_commit_file_changes(
-    repo: &Repository,
-    commit: &Commit,
-    parent_id: Option<Oid>,
-) -> Result<HashMap<String, String>> {
-    let mut file_changes = HashMap::new();
-    
-    let current_tree = commit.tree()?;
-    
-    if let Some(parent_id) = parent_id {
-        let parent_commit = repo.find_commit(parent_id)?;
-        let parent_tree = parent_commit.tree()?;
-        
-        let diff = repo.diff_tree_to_tree(Some(&parent_tree), Some(&current_tree), None)?;
-        
-        // Process the full diff once and extract content for each file
-        diff.print(git2::DiffFormat::Patch, |delta, _hunk, line| {
-            if let Some(file_path) = get_file_path_from_delta(&delta) {
-                // Use entry API to avoid multiple HashMap lookups
-                let diff_content = file_changes.entry(file_path).or_insert_with(|| String::with_capacity(1024));
-                
-                // Append line content directly without intermediate allocations
-                diff_content.push_str(std::str::from_utf8(line.content()).unwrap_or(""));
-            }
-            true
-        })?;
+/// Streams the JSON straight into the writer instead of building the whole document in memory
+fn serialize_json<W: Write, T: Serialize + ?Sized>(writer: W, data: &T, pretty: bool) -> Result<()> {
+    if pretty {
+        serde_json::to_writer_pretty(writer, data)
     } else {
-        // First commit - all files are additions
-        let mut diff_options = DiffOptions::new();
-        diff_options.include_untracked(true);
-        
-        let diff = repo.diff_tree_to_tree(None, Some(&current_tree), Some(&mut diff_options))?;
-        
-        diff.foreach(
-            &mut |delta, _| {
-                if let Some(file_path) = get_file_path_from_delta(&delta) {
-                    if let Ok(entry) = current_tree.get_path(Path::new(&file_path)) {
-                        if let Ok(object) = entry.to_object(repo) {
-                            if object.kind() == Some(ObjectType::Blob) {
-                                let blob = object.as_blob().unwrap();
-                                let content = String::from_utf8_lossy(blob.content());
-                                
-                                // Pre-allocate string capacity based on content size
-                                let mut diff_text = String::with_capacity(content.len() + content.lines().count());
-                                for line in content.lines() {
-                                    diff_text.push('+');
-                                    diff_text.push_str(line);
-                                    diff_text.push('\n');
-                                }
-                                file_changes.insert(file_path, diff_text);
-                            }
-                        }
-                    }
-                }
-                true
-            },
-            None,
-            None,
-            None,
-        )?;
+        serde_json::to_writer(writer, data)
     }
-    
-    Ok(file_changes)
+    .context("Failed to serialize data to JSON")
 }
 
-fn get_file_path_from_delta(delta: &DiffDelta) -> Option<String> {
-    if let Some(new_file) = delta.new_file().path() {
-        Some(new_file.to_string_lossy().to_string())
-    } else if let Some(old_file) = delta.old_file().path() {
-        Some(old_file.to_string_lossy().to_string())
-    } else {
-        None
-    }
+const SPLIT_INDEX_NAME: &str = "index";
+
+/// An entry in the split output's index.json
+#[derive(Serialize, Debug)]
+struct SplitIndexEntry<'a> {
+    path: &'a str,
+    file: String,
 }
 
-fn populate_current_contents(repo: &Repository, repo_path: &Path, export_data: &mut ExportData, silent: bool) -> Result<()> {
-    let total_files = export_data.len();
-    let pb = if !silent {
-        let progress_bar = ProgressBar::new(total_files as u64);
-        progress_bar.set_style(
-            ProgressStyle::default_bar()
-                .template("[{elapsed_precise}/{eta_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg} [{per_sec}]")
-                .unwrap()
-                .progress_chars("##-")
-        );
-        progress_bar.set_message("Reading current file contents");
-        Some(progress_bar)
-    } else {
-        None
-    };
+/// Writes each file's history to `<dir>/<sanitized-path>.json` and lists them in `<dir>/index.json`.
+/// With gzip the per-file outputs are written as `.json.gz`; the index stays plain JSON.
+fn write_split_output(export_data: &ExportData, output_dir: &Path, pretty: bool, gzip: bool) -> Result<()> {
+    fs::create_dir_all(output_dir)
+        .with_context(|| format!("Failed to create output directory {}", output_dir.display()))?;
     
-    // Get the current HEAD tree to check which files still exist
-    let head_tree = if let Ok(head) = repo.head() {
-        if let Ok(commit) = head.peel_to_commit() {
-            Some(commit.tree()?)
-        } else {
-            None
-        }
-    } else {
-        None
-    };
+    let paths: Vec<&String> = export_data.keys().collect();
     
-    let mut processed_count = 0;
-    let update_interval = std::cmp::max(1, total_files / 100); // Update every 1% of files
-    
-    for (file_path, file_info) in export_data.iter_mut() {
-        // Check if file exists in current HEAD
-        let current_contents = if let Some(tree) = &head_tree {
-            if let Ok(entry) = tree.get_path(Path::new(file_path)) {
-                if let Ok(object) = entry.to_object(repo) {
-                    if object.kind() == Some(ObjectType::Blob) {
-                        let blob = object.as_blob().unwrap();
-                        let content = blob.content();
-                        
-                        // Quick binary detection - check for null bytes in first 8192 bytes
-                        let check_len = std::cmp::min(content.len(), 8192);
-                        if content[..check_len].contains(&0) {
-                            "[Binary file]".to_string()
-                        } else {
-                            String::from_utf8_lossy(content).to_string()
-                        }
-                    } else {
-                        "[Binary file or unreadable]".to_string()
-                    }
-                } else {
-                    "[deleted]".to_string()
-                }
-            } else {
-                "[deleted]".to_string()
-            }
+    // Count sanitized names first so every path involved in a collision gets a
+    // hash suffix, regardless of the order the paths are visited in
+    let mut name_counts: HashMap<String, usize> = HashMap::with_capacity(paths.len());
+    // The index itself occupies "index.json"
+    name_counts.insert(SPLIT_INDEX_NAME.to_string(), 1);
+    for path in &paths {
+        *name_counts.entry(sanitize_split_name(path)).or_insert(0) += 1;
+    }
+    
+    let extension = if gzip { "json.gz" } else { "json" };
+    let mut index = Vec::with_capacity(paths.len());
+    for path in paths {
+        let name = sanitize_split_name(path);
+        let file_name = if name_counts[&name] > 1 {
+            format!("{}.{:08x}.{}", name, short_path_hash(path), extension)
         } else {
-            // No HEAD commit, try to read from filesystem
-            let full_path = repo_path.join(file_path);
-            if full_path.exists() {
-                // Try to detect binary files early
-                match fs::read(&full_path) {
-                    Ok(content) => {
-                        let check_len = std::cmp::min(content.len(), 8192);
-                        if content.len() > 0 && content[..check_len].contains(&0) {
-                            "[Binary file]".to_string()
-                        } else {
-                            String::from_utf8_lossy(&content).to_string()
-                        }
-                    }
-                    Err(_) => "[binary file or unreadable]".to_string(),
-                }
-            } else {
-                "[deleted]".to_string()
-            }
+            format!("{}.{}", name, extension)
         };
         
-        file_info.current_contents = current_contents;
-        
-        processed_count += 1;
-        // Batch update progress bar for better performance
-        if processed_count % update_interval == 0 || processed_count == total_files {
-            if let Some(progress_bar) = &pb {
-                progress_bar.set_position(processed_count as u64);
-            }
-        }
+        write_json(&export_data[path], &output_dir.join(&file_name), pretty, gzip)?;
+        index.push(SplitIndexEntry { path, file: file_name });
     }
     
-    if let Some(progress_bar) = pb {
-        progress_bar.finish_with_message("Finished reading current file contents");
+    write_json(&index, &output_dir.join(format!("{}.json", SPLIT_INDEX_NAME)), pretty, false)
+}
+
+fn sanitize_split_name(path: &str) -> String {
+    path.replace('/', "__")
+}
+
+/// A short FNV-1a hash of a path, stable across runs and platforms
+fn short_path_hash(path: &str) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in path.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
     }
-    Ok(())
+    hash
 }
@@ -0,0 +1,83 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+use git_history_exporter::ExportData;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS files (
+        path TEXT PRIMARY KEY,
+        current_contents TEXT,
+        binary INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS commits (
+        path TEXT,
+        commit_hash TEXT,
+        author_name TEXT,
+        author_email TEXT,
+        commit_message TEXT,
+        diff TEXT,
+        additions INTEGER,
+        deletions INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS commits_path ON commits (path);
+";
+
+/// Write the export into a SQLite database, replacing any rows from a previous run
+pub fn write_sqlite(export_data: &ExportData, output_path: &Path) -> Result<()> {
+    let mut conn = Connection::open(output_path)
+        .with_context(|| format!("Failed to open SQLite database {}", output_path.display()))?;
+
+    conn.execute_batch(SCHEMA)
+        .context("Failed to create SQLite schema")?;
+    add_author_columns(&conn)
+        .context("Failed to add author columns to SQLite schema")?;
+
+    // Everything goes into a single transaction so a re-run either fully replaces
+    // the previous export or leaves it untouched
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM commits", [])?;
+    tx.execute("DELETE FROM files", [])?;
+
+    {
+        let mut insert_file = tx.prepare("INSERT INTO files (path, current_contents, binary) VALUES (?1, ?2, ?3)")?;
+        let mut insert_commit = tx.prepare(
+            "INSERT INTO commits (path, commit_hash, author_name, author_email, commit_message, diff, additions, deletions) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
+        )?;
+
+        for (path, file_info) in export_data {
+            insert_file.execute(params![path, file_info.current_contents, file_info.binary])
+                .with_context(|| format!("Failed to insert file {}", path))?;
+
+            for commit in &file_info.history {
+                insert_commit.execute(params![
+                    path,
+                    commit.commit_hash,
+                    commit.author_name,
+                    commit.author_email,
+                    commit.commit_message,
+                    commit.diff,
+                    commit.additions as i64,
+                    commit.deletions as i64,
+                ]).with_context(|| format!("Failed to insert commit {} for {}", commit.commit_hash, path))?;
+            }
+        }
+    }
+
+    tx.commit().context("Failed to commit SQLite transaction")?;
+    Ok(())
+}
+
+/// Databases written before commits carried their author lack the author columns, which
+/// `CREATE TABLE IF NOT EXISTS` leaves alone
+fn add_author_columns(conn: &Connection) -> Result<()> {
+    let columns = conn.prepare("SELECT name FROM pragma_table_info('commits')")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for column in ["author_name", "author_email"] {
+        if !columns.iter().any(|existing| existing == column) {
+            conn.execute(&format!("ALTER TABLE commits ADD COLUMN {} TEXT", column), [])?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,447 @@
+//! Runs of the archive binary over small BigQuery-style parquet exports written here.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::Arc;
+
+use parquet::basic::{LogicalType, TimeUnit};
+use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::writer::SerializedFileWriter;
+use parquet::record::RowAccessor;
+use parquet::schema::parser::parse_message_type;
+
+/// An empty directory for one test
+fn temp_dir(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("archive-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+/// An input row; `created_at` is in microseconds, like the BigQuery exports
+struct Event {
+    event_type: String,
+    payload: String,
+    repo_name: String,
+    created_at: i64,
+    id: String,
+    actor_login: String,
+    actor_id: i64,
+}
+
+/// Event `n` of a fixture: a push to `repo_name` one second after the one before it,
+/// starting 2024-01-01
+fn push_event(n: usize, repo_name: &str) -> Event {
+    Event {
+        event_type: "PushEvent".to_string(),
+        payload: format!(r#"{{"push_id":{},"ref":"refs/heads/main","size":1}}"#, n),
+        repo_name: repo_name.to_string(),
+        created_at: (1_704_067_200 + n as i64) * 1_000_000,
+        id: (30_000_000_000 + n).to_string(),
+        actor_login: format!("user{}", n % 7),
+        actor_id: (n % 7) as i64,
+    }
+}
+
+enum Leaf {
+    Strings(Vec<String>),
+    Longs(Vec<i64>),
+}
+
+/// A top-level column of a fixture: its schema, and the values of its leaves in order
+struct Column {
+    schema: &'static str,
+    leaves: Vec<Leaf>,
+}
+
+fn strings(events: &[Event], field: impl Fn(&Event) -> &str) -> Leaf {
+    Leaf::Strings(events.iter().map(|event| field(event).to_string()).collect())
+}
+
+/// The columns of a current BigQuery export, in its column order
+fn export_columns(events: &[Event]) -> Vec<Column> {
+    vec![
+        Column { schema: "REQUIRED BYTE_ARRAY type (STRING);", leaves: vec![strings(events, |event| &event.event_type)] },
+        Column { schema: "REQUIRED BYTE_ARRAY payload (STRING);", leaves: vec![strings(events, |event| &event.payload)] },
+        Column { schema: "REQUIRED group repo { REQUIRED BYTE_ARRAY name (STRING); }", leaves: vec![strings(events, |event| &event.repo_name)] },
+        Column {
+            schema: "REQUIRED group actor { REQUIRED INT64 id; REQUIRED BYTE_ARRAY login (STRING); }",
+            leaves: vec![Leaf::Longs(events.iter().map(|event| event.actor_id).collect()), strings(events, |event| &event.actor_login)],
+        },
+        Column { schema: "REQUIRED INT64 created_at (TIMESTAMP(MICROS,true));", leaves: vec![Leaf::Longs(events.iter().map(|event| event.created_at).collect())] },
+        Column { schema: "REQUIRED BYTE_ARRAY id (STRING);", leaves: vec![strings(events, |event| &event.id)] },
+    ]
+}
+
+/// The columns of a pre-2015 timeline export: the repository's owner and name are
+/// split apart, the actor is only a login, and events have no id
+fn legacy_columns(events: &[Event]) -> Vec<Column> {
+    let (owners, names): (Vec<String>, Vec<String>) = events.iter().map(|event| {
+        let (owner, name) = event.repo_name.split_once('/').unwrap();
+        (owner.to_string(), name.to_string())
+    }).unzip();
+    vec![
+        Column { schema: "REQUIRED BYTE_ARRAY type (STRING);", leaves: vec![strings(events, |event| &event.event_type)] },
+        Column { schema: "REQUIRED BYTE_ARRAY payload (STRING);", leaves: vec![strings(events, |event| &event.payload)] },
+        Column {
+            schema: "REQUIRED group repository { REQUIRED BYTE_ARRAY owner (STRING); REQUIRED BYTE_ARRAY name (STRING); }",
+            leaves: vec![Leaf::Strings(owners), Leaf::Strings(names)],
+        },
+        Column { schema: "REQUIRED BYTE_ARRAY actor (STRING);", leaves: vec![strings(events, |event| &event.actor_login)] },
+        Column { schema: "REQUIRED INT64 created_at (TIMESTAMP(MICROS,true));", leaves: vec![Leaf::Longs(events.iter().map(|event| event.created_at).collect())] },
+    ]
+}
+
+fn write_parquet(path: &Path, columns: &[Column]) {
+    let schema: String = columns.iter().map(|column| format!("  {}\n", column.schema)).collect();
+    let schema = parse_message_type(&format!("message schema {{\n{}}}", schema)).unwrap();
+    let mut writer = SerializedFileWriter::new(File::create(path).unwrap(), Arc::new(schema), Arc::new(WriterProperties::builder().build())).unwrap();
+    let mut row_group = writer.next_row_group().unwrap();
+    for leaf in columns.iter().flat_map(|column| &column.leaves) {
+        let mut column_writer = row_group.next_column().unwrap().unwrap();
+        match leaf {
+            Leaf::Strings(values) => {
+                let values: Vec<ByteArray> = values.iter().map(|value| value.as_str().into()).collect();
+                column_writer.typed::<ByteArrayType>().write_batch(&values, None, None).unwrap();
+            }
+            Leaf::Longs(values) => {
+                column_writer.typed::<Int64Type>().write_batch(values, None, None).unwrap();
+            }
+        }
+        column_writer.close().unwrap();
+    }
+    row_group.close().unwrap();
+    writer.close().unwrap();
+}
+
+/// Runs the archive binary quietly into `output_dir`, failing the test if it fails
+fn run_archive(output_dir: &Path, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_archive"))
+        .arg("--output-dir")
+        .arg(output_dir)
+        .arg("--quiet")
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "archive failed: {}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+/// Files under `dir` whose names end in `suffix`, sorted
+fn output_files(dir: &Path, suffix: &str) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.to_string_lossy().ends_with(suffix) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// An output row as (type, payload, repo_name, created_at in milliseconds, id)
+type OutputEvent = (String, String, String, i64, String);
+
+fn read_output_events(path: &Path) -> Vec<OutputEvent> {
+    let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+    reader.get_row_iter(None).unwrap().map(|row| {
+        let row = row.unwrap();
+        (
+            row.get_string(0).unwrap().clone(),
+            row.get_string(1).unwrap().clone(),
+            row.get_string(2).unwrap().clone(),
+            row.get_timestamp_millis(3).unwrap(),
+            row.get_string(6).unwrap().clone(),
+        )
+    }).collect()
+}
+
+fn row_groups(path: &Path) -> usize {
+    SerializedFileReader::new(File::open(path).unwrap()).unwrap().metadata().num_row_groups()
+}
+
+#[test]
+fn larger_buffers_write_fewer_row_groups_and_smaller_files() {
+    let dir = temp_dir("buffer-rows");
+    let input = dir.join("2024-01-01-0.parquet");
+    let events: Vec<Event> = (0..20_000).map(|n| push_event(n, "rust-lang/rust")).collect();
+    write_parquet(&input, &export_columns(&events));
+
+    let output = |buffer_rows: &str| {
+        let output_dir = dir.join(format!("out-{}", buffer_rows));
+        run_archive(&output_dir, &["--files", input.to_str().unwrap(), "--buffer-rows", buffer_rows]);
+        let files = output_files(&output_dir, ".parquet");
+        assert_eq!(files.len(), 1);
+        files.into_iter().next().unwrap()
+    };
+    let small = output("1000");
+    let large = output("100000");
+    assert_eq!((row_groups(&small), row_groups(&large)), (20, 1));
+    assert_eq!(read_output_events(&small), read_output_events(&large));
+    let size = |path: &Path| std::fs::metadata(path).unwrap().len();
+    assert!(size(&large) < size(&small), "{} bytes with 100k buffered rows, {} with 1k", size(&large), size(&small));
+}
+
+#[test]
+fn created_at_is_written_as_a_utc_millisecond_timestamp() {
+    let dir = temp_dir("created-at-type");
+    let input = dir.join("2024-01-01-0.parquet");
+    write_parquet(&input, &export_columns(&[push_event(0, "rust-lang/rust")]));
+    let output_dir = dir.join("out");
+    run_archive(&output_dir, &["--files", input.to_str().unwrap()]);
+
+    let files = output_files(&output_dir, ".parquet");
+    let reader = SerializedFileReader::new(File::open(&files[0]).unwrap()).unwrap();
+    let schema = reader.metadata().file_metadata().schema_descr();
+    let created_at = schema.columns().iter().find(|column| column.name() == "created_at").unwrap();
+    assert_eq!(created_at.logical_type(), Some(LogicalType::Timestamp { is_adjusted_to_u_t_c: true, unit: TimeUnit::MILLIS(Default::default()) }));
+    assert_eq!(read_output_events(&files[0])[0].3, 1_704_067_200_000);
+}
+
+#[test]
+fn created_at_microseconds_are_truncated_to_milliseconds() {
+    let dir = temp_dir("created-at-millis");
+    let input = dir.join("2024-01-01-0.parquet");
+    let events: Vec<Event> = [1_704_067_200_123_456, 1_704_153_599_999_999].into_iter().enumerate().map(|(n, created_at)| {
+        Event { created_at, ..push_event(n, "rust-lang/rust") }
+    }).collect();
+    write_parquet(&input, &export_columns(&events));
+    let output_dir = dir.join("out");
+    run_archive(&output_dir, &["--files", input.to_str().unwrap()]);
+
+    let created_at: Vec<i64> = read_output_events(&output_files(&output_dir, ".parquet")[0]).into_iter().map(|event| event.3).collect();
+    assert_eq!(created_at, [1_704_067_200_123, 1_704_153_599_999]);
+}
+
+#[test]
+fn reordered_columns_are_read_by_name() {
+    let dir = temp_dir("reordered");
+    let events: Vec<Event> = (0..10).map(|n| push_event(n, if n % 2 == 0 { "rust-lang/rust" } else { "tokio-rs/tokio" })).collect();
+    let in_order = dir.join("in-order").join("2024-01-01-0.parquet");
+    let reordered = dir.join("reordered").join("2024-01-01-0.parquet");
+    for input in [&in_order, &reordered] {
+        std::fs::create_dir_all(input.parent().unwrap()).unwrap();
+    }
+    write_parquet(&in_order, &export_columns(&events));
+    let mut columns = export_columns(&events);
+    columns.reverse();
+    write_parquet(&reordered, &columns);
+
+    let events_of = |input: &Path, name: &str| {
+        let output_dir = dir.join(name);
+        run_archive(&output_dir, &["--files", input.to_str().unwrap()]);
+        output_files(&output_dir, ".parquet").iter().flat_map(|path| read_output_events(path)).collect::<Vec<_>>()
+    };
+    let expected = events_of(&in_order, "out-in-order");
+    assert_eq!(expected.len(), 10);
+    assert!(expected.iter().any(|event| event == &("PushEvent".to_string(), events[1].payload.clone(), "tokio-rs/tokio".to_string(), 1_704_067_201_000, events[1].id.clone())));
+    assert_eq!(events_of(&reordered, "out-reordered"), expected);
+}
+
+#[test]
+fn missing_column_fails_listing_the_columns_found() {
+    let dir = temp_dir("missing-column");
+    let input = dir.join("2024-01-01-0.parquet");
+    let mut columns = export_columns(&[push_event(0, "rust-lang/rust")]);
+    columns.retain(|column| !column.schema.contains(" payload "));
+    write_parquet(&input, &columns);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_archive"))
+        .arg("--output-dir")
+        .arg(dir.join("out"))
+        .args(["--quiet", "--files", input.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("payload") && stderr.contains("repo.name"), "{}", stderr);
+}
+
+#[test]
+fn hive_partitions_match_a_month_glob() {
+    let dir = temp_dir("hive");
+    let input = dir.join("2024-01-31-23.parquet");
+    // Half the rows fall in February, an hour after the first
+    let events: Vec<Event> = (0..8).map(|n| Event {
+        created_at: (1_706_742_000 + 3_600 * (n as i64 % 2)) * 1_000_000,
+        ..push_event(n, if n < 4 { "rust-lang/rust" } else { "tokio-rs/tokio" })
+    }).collect();
+    write_parquet(&input, &export_columns(&events));
+    let output_dir = dir.join("out");
+    run_archive(&output_dir, &["--files", input.to_str().unwrap(), "--hive-partitions"]);
+
+    let files = output_files(&output_dir, ".parquet");
+    let relative: Vec<PathBuf> = files.iter().map(|path| path.strip_prefix(&output_dir).unwrap().to_path_buf()).collect();
+    assert_eq!(relative.len(), 4);
+    let january = glob::Pattern::new("**/month=2024-01/*.parquet").unwrap();
+    let matched: Vec<&PathBuf> = relative.iter().filter(|path| january.matches_path(path)).collect();
+    assert_eq!(matched, [Path::new("prefix=rus/month=2024-01/2024-01-31-23.parquet"), Path::new("prefix=tok/month=2024-01/2024-01-31-23.parquet")]);
+    for path in &matched {
+        assert!(read_output_events(&output_dir.join(path)).iter().all(|event| event.3 < 1_706_745_600_000));
+    }
+
+    // The run summary lists the files under the same paths
+    let summary: serde_json::Value = serde_json::from_slice(&std::fs::read(output_dir.join("_summary.json")).unwrap()).unwrap();
+    let mut listed: Vec<PathBuf> = summary["files"].as_array().unwrap().iter().map(|file| PathBuf::from(file["path"].as_str().unwrap())).collect();
+    listed.sort();
+    assert_eq!(listed, files);
+}
+
+#[test]
+fn zstd_framed_and_plain_parquet_inputs_read_the_same() {
+    let dir = temp_dir("zstd-input");
+    let events: Vec<Event> = (0..100).map(|n| push_event(n, "rust-lang/rust")).collect();
+    let plain = dir.join("plain").join("2024-01-01-0.parquet");
+    let framed = dir.join("framed").join("2024-01-01-0.parquet.zst");
+    // Older exports are plain parquet files named .parquet.zst
+    let misnamed = dir.join("misnamed").join("2024-01-01-0.parquet.zst");
+    for input in [&plain, &framed, &misnamed] {
+        std::fs::create_dir_all(input.parent().unwrap()).unwrap();
+    }
+    write_parquet(&plain, &export_columns(&events));
+    std::fs::copy(&plain, &misnamed).unwrap();
+    let compressed = zstd::encode_all(File::open(&plain).unwrap(), 3).unwrap();
+    assert_eq!(compressed[..4], [0x28, 0xB5, 0x2F, 0xFD]);
+    std::fs::write(&framed, compressed).unwrap();
+
+    let events_of = |input: &Path, name: &str| {
+        let output_dir = dir.join(name);
+        run_archive(&output_dir, &["--files", input.to_str().unwrap()]);
+        let files = output_files(&output_dir, ".parquet");
+        assert_eq!(files.len(), 1);
+        read_output_events(&files[0])
+    };
+    let expected = events_of(&plain, "out-plain");
+    assert_eq!(expected.len(), 100);
+    assert_eq!(events_of(&framed, "out-framed"), expected);
+    assert_eq!(events_of(&misnamed, "out-misnamed"), expected);
+}
+
+/// Ctrl-C partway through an input closes the open files, so what was written reads back
+#[cfg(unix)]
+#[test]
+fn interrupted_run_leaves_readable_files() {
+    use std::io::{BufRead, BufReader};
+
+    let dir = temp_dir("interrupted");
+    let input = dir.join("2024-01-01-0.parquet");
+    let repos = ["rust-lang/rust", "tokio-rs/tokio", "serde-rs/serde"];
+    let events: Vec<Event> = (0..300_000).map(|n| push_event(n, repos[n % repos.len()])).collect();
+    write_parquet(&input, &export_columns(&events));
+    let output_dir = dir.join("out");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_archive"))
+        .arg("--output-dir")
+        .arg(&output_dir)
+        .args(["--files", input.to_str().unwrap(), "--progress", "json", "--progress-rows", "1000", "--buffer-rows", "500"])
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(child.stderr.take().unwrap()).lines();
+    // Interrupted once the first rows are read, well before the file's last one
+    for line in stderr.by_ref() {
+        if line.unwrap().contains(r#""phase":"processing""#) {
+            break;
+        }
+    }
+    let killed = Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+    assert!(killed.success());
+    let stderr: Vec<String> = stderr.map(Result::unwrap).collect();
+    let status = child.wait().unwrap();
+    assert_eq!(status.code(), Some(130), "{}", stderr.join("\n"));
+    assert!(stderr.iter().any(|line| line.contains(r#""status":"stopped""#)), "{}", stderr.join("\n"));
+
+    let summary: serde_json::Value = serde_json::from_slice(&std::fs::read(output_dir.join("_summary.json")).unwrap()).unwrap();
+    assert_eq!(summary["partial"], true);
+    let rows_written = summary["rows_written"].as_u64().unwrap();
+    assert!(rows_written > 0 && rows_written < events.len() as u64, "{} rows written", rows_written);
+    let files = output_files(&output_dir, ".parquet");
+    assert_eq!(files.len(), repos.len());
+    let rows_read_back: usize = files.iter().map(|path| read_output_events(path).len()).sum();
+    assert_eq!(rows_read_back as u64, rows_written);
+}
+
+#[test]
+fn arrow_output_matches_parquet_output() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::TimestampMillisecondType;
+
+    let dir = temp_dir("arrow");
+    let input = dir.join("2024-01-01-0.parquet");
+    let events: Vec<Event> = (0..2_500).map(|n| push_event(n, "rust-lang/rust")).collect();
+    write_parquet(&input, &export_columns(&events));
+    let parquet_dir = dir.join("out-parquet");
+    let arrow_dir = dir.join("out-arrow");
+    run_archive(&parquet_dir, &["--files", input.to_str().unwrap()]);
+    run_archive(&arrow_dir, &["--files", input.to_str().unwrap(), "--output-format", "arrow", "--compression", "lz4"]);
+
+    let expected = read_output_events(&output_files(&parquet_dir, ".parquet")[0]);
+    let arrow_files = output_files(&arrow_dir, ".arrow");
+    assert_eq!(arrow_files.len(), 1);
+    let reader = arrow_ipc::reader::FileReader::try_new(File::open(&arrow_files[0]).unwrap(), None).unwrap();
+    let batches: Vec<_> = reader.map(Result::unwrap).collect();
+    // One record batch per buffer flush of 1000 rows
+    assert_eq!(batches.len(), 3);
+    assert_eq!(batches.iter().map(|batch| batch.num_rows()).sum::<usize>(), expected.len());
+
+    let batch = &batches[1];
+    let string = |name: &str| batch.column_by_name(name).unwrap().as_string::<i32>().value(234).to_string();
+    let created_at = batch.column_by_name("created_at").unwrap().as_primitive::<TimestampMillisecondType>().value(234);
+    assert_eq!((string("type"), string("payload"), string("repo_name"), created_at, string("id")), expected[1_234]);
+}
+
+#[test]
+fn legacy_timeline_export_splits_by_owner_and_name() {
+    let dir = temp_dir("legacy");
+    let input = dir.join("2012-03-11-1.parquet");
+    // 2012-03-11 01:00 UTC
+    let event = |n: usize, event_type: &str, repo_name: &str| Event {
+        event_type: event_type.to_string(),
+        created_at: (1_331_427_600 + n as i64) * 1_000_000,
+        ..push_event(n, repo_name)
+    };
+    let events = [event(0, "PushEvent", "defunkt/resque"), event(1, "DownloadEvent", "rails/rails"), event(2, "ForkApplyEvent", "defunkt/resque")];
+    write_parquet(&input, &legacy_columns(&events));
+    let output_dir = dir.join("out");
+    run_archive(&output_dir, &["--files", input.to_str().unwrap()]);
+
+    let files = output_files(&output_dir, ".parquet");
+    let relative: Vec<&Path> = files.iter().map(|path| path.strip_prefix(&output_dir).unwrap()).collect();
+    assert_eq!(relative, [Path::new("d/e/f/2012-03.2012-03-11-1.parquet"), Path::new("r/a/i/2012-03.2012-03-11-1.parquet")]);
+
+    let resque = read_output_events(&files[0]);
+    let types: Vec<(&str, &str)> = resque.iter().map(|event| (event.0.as_str(), event.2.as_str())).collect();
+    assert_eq!(types, [("PushEvent", "defunkt/resque"), ("ForkApplyEvent", "defunkt/resque")]);
+    assert_eq!(read_output_events(&files[1])[0].0, "DownloadEvent");
+    // Legacy events get ids derived from their contents
+    assert!(resque.iter().all(|event| !event.4.is_empty()) && resque[0].4 != resque[1].4);
+}
+
+#[test]
+fn repeated_runs_write_identical_files() {
+    let dir = temp_dir("deterministic");
+    let input = dir.join("2024-01-01-0.parquet");
+    let repos = ["rust-lang/rust", "tokio-rs/tokio", "serde-rs/serde", "rust-lang/cargo"];
+    let events: Vec<Event> = (0..5_000).map(|n| push_event(n, repos[n % repos.len()])).collect();
+    write_parquet(&input, &export_columns(&events));
+
+    let run = |name: &str| {
+        let output_dir = dir.join(name);
+        run_archive(&output_dir, &["--files", input.to_str().unwrap()]);
+        output_files(&output_dir, ".parquet").into_iter()
+            .map(|path| (path.strip_prefix(&output_dir).unwrap().to_path_buf(), std::fs::read(&path).unwrap()))
+            .collect::<Vec<_>>()
+    };
+    let first = run("out-1");
+    assert_eq!(first.len(), 3);
+    assert!(first == run("out-2"), "the second run wrote different files");
+}